@@ -0,0 +1,72 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use csa::algo::{self, Incorporate, Profile};
+use csa::structures::Timetable;
+
+fn time(seconds: u32) -> String {
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// A synthetic network where each line shares stops with its neighbours,
+// so that the scan has transfers to evaluate
+fn synthetic_timetable(lines: usize, stops_per_line: usize, trips_per_line: usize) -> Timetable {
+    let mut b = Timetable::builder();
+    for line in 0..lines {
+        for trip in 0..trips_per_line {
+            let start = (trip * 600 + line * 30) as u32;
+            b.trip();
+            for stop in 0..stops_per_line {
+                let stop_id = format!("s{}", (line * stops_per_line / 2 + stop) % 1000);
+                b.s(&stop_id, &time(start + stop as u32 * 120));
+            }
+        }
+    }
+    b.build()
+}
+
+fn bench_compute(c: &mut Criterion) {
+    let timetable = synthetic_timetable(20, 15, 50);
+    c.bench_function("compute", |b| {
+        b.iter(|| algo::compute(black_box(&timetable), black_box(&[0])))
+    });
+}
+
+fn bench_incorporate(c: &mut Criterion) {
+    c.bench_function("incorporate", |b| {
+        b.iter(|| {
+            let mut profiles = Vec::new();
+            for i in 0..1000 {
+                profiles.incorporate(Profile {
+                    out_connection: None,
+                    dep_time: black_box(10_000 - i * 7),
+                    arr_time: black_box(20_000 - i * 5 - (i % 3) * 11),
+                    transfers: 0,
+                    destination: None,
+                    penalty: 0,
+                });
+            }
+            profiles
+        })
+    });
+}
+
+fn bench_gtfs_parsing(c: &mut Criterion) {
+    c.bench_function("gtfs_parsing", |b| {
+        b.iter(|| gtfs_structures::Gtfs::new(black_box("fixtures/")).unwrap())
+    });
+}
+
+fn bench_gtfs_conversion(c: &mut Criterion) {
+    let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
+    c.bench_function("gtfs_conversion", |b| {
+        b.iter(|| Timetable::from_gtfs(black_box(&gtfs), "2017-1-1", 10))
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_compute,
+    bench_incorporate,
+    bench_gtfs_parsing,
+    bench_gtfs_conversion
+);
+criterion_main!(benches);
@@ -0,0 +1,5 @@
+fn main() {
+    // The Node.js bindings are loaded by node, which provides the N-API symbols
+    #[cfg(feature = "node")]
+    napi_build::setup();
+}
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(data);
+    for record in reader.deserialize::<gtfs_structures::RawStopTime>() {
+        let _ = record;
+    }
+});
@@ -0,0 +1,100 @@
+#ifndef CSA_H
+#define CSA_H
+
+/* Generated with cbindgen from src/csa_ffi.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum CsaLegKind {
+  CsaLegKind_Vehicle,
+  CsaLegKind_Walk,
+} CsaLegKind;
+
+typedef struct CsaTimetable CsaTimetable;
+
+typedef struct CsaLeg {
+  enum CsaLegKind kind;
+  size_t from;
+  size_t to;
+  uint32_t dep_time;
+  uint32_t arr_time;
+  size_t trip;
+} CsaLeg;
+
+typedef struct CsaJourney {
+  struct CsaLeg *legs;
+  size_t legs_len;
+} CsaJourney;
+
+typedef struct CsaJourneys {
+  struct CsaJourney *journeys;
+  size_t len;
+} CsaJourneys;
+
+/*
+ Loads a binary timetable, as written by the convert binary
+
+ # Safety
+
+ `path` must be a valid NUL terminated string
+ */
+struct CsaTimetable *csa_timetable_load(const char *path);
+
+/*
+ # Safety
+
+ `timetable` must come from csa_timetable_load, or be null
+ */
+void csa_timetable_free(struct CsaTimetable *timetable);
+
+/*
+ The journeys between two stop areas (or stops), leaving after `departure`
+ (in seconds since the midnight of the first day of the timetable)
+
+ # Safety
+
+ `timetable` must come from csa_timetable_load, `from` and `to` must be NUL terminated strings
+ */
+struct CsaJourneys *csa_plan(const struct CsaTimetable *timetable,
+                             const char *from,
+                             const char *to,
+                             uint32_t departure,
+                             size_t count);
+
+/*
+ # Safety
+
+ `journeys` must come from csa_plan, or be null
+ */
+void csa_journeys_free(struct CsaJourneys *journeys);
+
+/*
+ The id of a stop, to be released with csa_string_free
+
+ # Safety
+
+ `timetable` must come from csa_timetable_load
+ */
+char *csa_stop_id(const struct CsaTimetable *timetable, size_t stop);
+
+/*
+ The id of a trip, to be released with csa_string_free
+
+ # Safety
+
+ `timetable` must come from csa_timetable_load
+ */
+char *csa_trip_id(const struct CsaTimetable *timetable, size_t trip);
+
+/*
+ # Safety
+
+ `s` must come from csa_stop_id or csa_trip_id, or be null
+ */
+void csa_string_free(char *s);
+
+#endif /* CSA_H */
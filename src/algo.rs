@@ -1,31 +1,55 @@
-use crate::structures::{Connection, Timetable};
+use crate::query::QueryOptions;
+use crate::structures::{Connection, Network, Timetable};
+use serde::Serialize;
+use std::time::Instant;
 
 // A profile defines a route
 // Given its connection, we can rebuild the whole route
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Profile {
     // If None, it means that it is the starting point
     pub out_connection: Option<usize>,
     pub dep_time: u32,
     pub arr_time: u32,
+    // How many times we change of trip on the route
+    pub transfers: u16,
+    // Which of the destinations is reached, None when not computed by a scan
+    pub destination: Option<usize>,
+    // In seconds, added to the arrival to compare the profiles, like for the walking reluctance
+    pub penalty: u32,
 }
 
 impl Profile {
-    pub fn route<'a>(
+    // The perceived arrival time, used by the dominance
+    pub fn cost(&self) -> u32 {
+        self.arr_time.saturating_add(self.penalty)
+    }
+
+    pub fn route<'a, N: Network>(
         &self,
         profiles: &[Vec<Profile>],
-        timetable: &'a Timetable,
+        network: &'a N,
     ) -> Vec<&'a Connection> {
         let mut current_connection = self.out_connection;
+        let mut penalty = self.penalty;
         let mut result = Vec::new();
 
         while let Some(c_index) = current_connection {
-            let conn = &timetable.connections[c_index];
+            let conn = network.connection(c_index);
 
-            current_connection = profiles[conn.arr_stop]
+            // The penalties of the walks and legs already done are not in the next profile
+            let same_arrival = profiles[conn.arr_stop]
                 .iter()
-                .find(|profile| self.arr_time == profile.arr_time)
-                .and_then(|profile| profile.out_connection);
+                .filter(|p| self.arr_time == p.arr_time);
+            let next = same_arrival
+                .clone()
+                .filter(|p| p.penalty <= penalty)
+                .min_by_key(|p| penalty - p.penalty)
+                .or_else(|| same_arrival.clone().next());
+            if let Some(next) = next {
+                penalty = next.penalty;
+            }
+            current_connection = next.and_then(|profile| profile.out_connection);
 
             result.push(conn);
         }
@@ -37,41 +61,110 @@ impl Default for Profile {
     fn default() -> Self {
         Self {
             out_connection: None,
-            dep_time: u32::max_value(),
+            dep_time: u32::MAX,
             arr_time: 0,
+            transfers: 0,
+            destination: None,
+            penalty: 0,
         }
     }
 }
 
-fn arrival_time_with_stop_change(profiles: &[Profile], c: &Connection) -> Option<u32> {
+// What a scan keeps for a connection: (cost, arrival, transfers, destination)
+// Ordered by cost, then by arrival and by transfers
+type Label = (u32, u32, u16, Option<usize>);
+
+// The arrival time with the number of transfers and the reached destination
+// With a single criterion, the profile leaving the earliest after the transfer arrives the earliest
+fn arrival_time_with_stop_change(
+    profiles: &[Profile],
+    c: &Connection,
+    single_criterion: bool,
+) -> Option<Label> {
     let transfer_duration = 5;
-    profiles
+    let label = |p: &Profile| {
+        if p.out_connection.is_some() {
+            (p.cost(), p.arr_time, p.transfers + 1, p.destination)
+        } else {
+            // If this is the very last connection to target, it gives us the arrival time
+            (c.arr_time, c.arr_time, 0, p.destination)
+        }
+    };
+    let pos = profiles
         .iter()
-        .rposition(|p| p.dep_time > c.arr_time + transfer_duration)
-        .map(|pos| {
-            let p = &profiles[pos];
-            if p.out_connection.is_some() {
-                p.arr_time
-            } else {
-                // If this is the very last connection to target, it gives us the arrival time
-                c.arr_time
-            }
-        })
+        .rposition(|p| p.dep_time > c.arr_time + transfer_duration)?;
+    if single_criterion {
+        Some(label(&profiles[pos]))
+    } else {
+        profiles[..=pos].iter().map(label).min()
+    }
 }
 
-trait Incorporate {
-    fn incorporate(&mut self, candidate: Profile) -> bool;
-    fn insert_and_filter(&mut self, candidate: Profile, pivot: usize);
+// Which profiles are kept at a stop
+pub trait Dominance {
+    // If `a`, leaving at the same time or later than `b`, is at least as good
+    fn dominates(&self, a: &Profile, b: &Profile) -> bool;
+
+    // When the kept profiles arrive earlier as they leave earlier, a candidate is only compared
+    // with the profile leaving just after it
+    fn is_single_criterion(&self) -> bool {
+        false
+    }
 }
 
-impl Incorporate for Vec<Profile> {
+// The default, by perceived arrival time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CostDominance;
+
+impl Dominance for CostDominance {
+    #[inline]
+    fn dominates(&self, a: &Profile, b: &Profile) -> bool {
+        a.cost() <= b.cost()
+    }
+
+    fn is_single_criterion(&self) -> bool {
+        true
+    }
+}
+
+// Custom criteria, like `|a, b| a.cost() <= b.cost() && a.transfers <= b.transfers`
+impl<F: Fn(&Profile, &Profile) -> bool> Dominance for F {
+    fn dominates(&self, a: &Profile, b: &Profile) -> bool {
+        self(a, b)
+    }
+}
+
+pub trait Incorporate {
+    fn incorporate(&mut self, candidate: Profile) -> bool {
+        self.incorporate_with(candidate, &CostDominance)
+    }
+
     fn insert_and_filter(&mut self, candidate: Profile, pivot: usize) {
+        self.insert_and_filter_with(candidate, pivot, &CostDominance)
+    }
+
+    fn incorporate_with<D: Dominance>(&mut self, candidate: Profile, dominance: &D) -> bool;
+    fn insert_and_filter_with<D: Dominance>(
+        &mut self,
+        candidate: Profile,
+        pivot: usize,
+        dominance: &D,
+    );
+}
+
+impl Incorporate for Vec<Profile> {
+    fn insert_and_filter_with<D: Dominance>(
+        &mut self,
+        candidate: Profile,
+        pivot: usize,
+        dominance: &D,
+    ) {
         // Remove all the dominated solutions
         // We only consider profiles leaving earlier after the candidate
         // As self is sorted by decreasing dep_time, we need only to look after the pivot
         let mut i = pivot + 1;
         while i < self.len() {
-            if candidate.arr_time <= self[i].arr_time {
+            if dominance.dominates(&candidate, &self[i]) {
                 self.remove(i);
             } else {
                 i += 1;
@@ -80,17 +173,22 @@ impl Incorporate for Vec<Profile> {
         self.insert(pivot + 1, candidate);
     }
 
-    fn incorporate(&mut self, candidate: Profile) -> bool {
+    fn incorporate_with<D: Dominance>(&mut self, candidate: Profile, dominance: &D) -> bool {
         // The profiles are ordered in decreasing dep_time
         // The pivot is the element leaving just after the candidate
         match self.iter().rposition(|p| p.dep_time >= candidate.dep_time) {
             Some(pivot) => {
-                if candidate.arr_time < self[pivot].arr_time {
-                    self.insert_and_filter(candidate, pivot);
-                    true
+                let dominated = if dominance.is_single_criterion() {
+                    dominance.dominates(&self[pivot], &candidate)
                 } else {
-                    false
+                    self[..=pivot]
+                        .iter()
+                        .any(|p| dominance.dominates(p, &candidate))
+                };
+                if !dominated {
+                    self.insert_and_filter_with(candidate, pivot, dominance);
                 }
+                !dominated
             }
             None => {
                 self.push(candidate);
@@ -100,7 +198,19 @@ impl Incorporate for Vec<Profile> {
     }
 }
 
-fn min_duration(a: Option<u32>, b: Option<u32>) -> Option<u32> {
+// The Pareto set of the profiles of several stops, like the platforms of a stop area
+// It can not be used to rebuild routes, as the connections do not always leave from the same stop
+pub fn merge_profiles<'a>(profiles: impl IntoIterator<Item = &'a Vec<Profile>>) -> Vec<Profile> {
+    let mut result = Vec::new();
+    for profile in profiles.into_iter().flatten() {
+        if profile.out_connection.is_some() {
+            result.incorporate(profile.clone());
+        }
+    }
+    result
+}
+
+fn min_duration<T: Ord + Copy>(a: Option<T>, b: Option<T>) -> Option<T> {
     match (a, b) {
         (None, _) => b,
         (_, None) => a,
@@ -109,58 +219,374 @@ fn min_duration(a: Option<u32>, b: Option<u32>) -> Option<u32> {
 }
 
 // It returns all the possible routes, from all possible nodes to the given destination
-pub fn compute(timetable: &Timetable, destinations: &[usize]) -> Vec<Vec<Profile>> {
+pub fn compute<N: Network>(network: &N, destinations: &[usize]) -> Vec<Vec<Profile>> {
+    compute_with_options(network, destinations, &QueryOptions::default())
+}
+
+// Like `compute`, with the walking durations adapted to the traveller
+pub fn compute_with_options<N: Network>(
+    network: &N,
+    destinations: &[usize],
+    options: &QueryOptions,
+) -> Vec<Vec<Profile>> {
+    compute_after(network, destinations, 0, options)
+}
+
+// Only the routes leaving after `departure`, the earlier connections are not scanned
+// This is much faster for the next departures from a given time
+pub fn compute_after<N: Network>(
+    network: &N,
+    destinations: &[usize],
+    departure: u32,
+    options: &QueryOptions,
+) -> Vec<Vec<Profile>> {
+    compute_with_stats(network, destinations, departure, options).0
+}
+
+// How much work a scan did
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScanStats {
+    pub connections_scanned: usize,
+    // Kept at the end of the scan, for all the stops
+    pub profiles: usize,
+    // Skipped as the origins can not catch them, see `QueryOptions::earliest_reach`
+    pub connections_pruned: usize,
+    // The budget of the options was exceeded before scanning all the connections
+    // The profiles of the earliest departures are missing, the others are optimal
+    pub partial: bool,
+}
+
+// The clock is only read every so many connections
+const BUDGET_CHECK_INTERVAL: usize = 1024;
+
+// None while the budget of the options is not exceeded, or when there is no budget
+struct Deadline(Option<Instant>);
+
+impl Deadline {
+    fn new(options: &QueryOptions) -> Self {
+        Deadline(options.budget.map(|budget| Instant::now() + budget))
+    }
+
+    fn is_exceeded(&self, connections_scanned: usize) -> bool {
+        self.0.is_some_and(|deadline| {
+            connections_scanned.is_multiple_of(BUDGET_CHECK_INTERVAL) && Instant::now() >= deadline
+        })
+    }
+}
+
+pub fn compute_with_stats<N: Network>(
+    network: &N,
+    destinations: &[usize],
+    departure: u32,
+    options: &QueryOptions,
+) -> (Vec<Vec<Profile>>, ScanStats) {
+    compute_with_dominance(network, destinations, departure, options, &CostDominance)
+}
+
+// Like `compute_with_stats`, keeping the profiles that are not dominated by the given criteria
+pub fn compute_with_dominance<N: Network, D: Dominance>(
+    network: &N,
+    destinations: &[usize],
+    departure: u32,
+    options: &QueryOptions,
+    dominance: &D,
+) -> (Vec<Vec<Profile>>, ScanStats) {
+    let timetable = network.timetable();
+    let mut stats = ScanStats::default();
     let mut arr_time_with_trip = vec![None; timetable.trips.len()];
     let mut profiles: Vec<_> = timetable.stops.iter().map(|_| Vec::new()).collect();
-    let mut final_footpaths = vec![None; timetable.stops.len()];
-    for destination in destinations {
-        for fp in &timetable.footpaths[*destination] {
-            final_footpaths[fp.from] = min_duration(final_footpaths[fp.from], Some(fp.duration));
+    let allowed_stops = options.allowed_stops(timetable);
+    let allowed = |stop: usize| allowed_stops.as_ref().is_none_or(|allowed| allowed[stop]);
+    let leg_penalties = options.leg_penalties(timetable);
+    // Boarding the trip, the labels of arr_time_with_trip do not include it
+    let leg_penalty = |trip: usize| match (&leg_penalties, timetable.trips[trip].route) {
+        (Some(penalties), Some(route)) => penalties[route],
+        (Some(_), None) => options.default_agency_penalty,
+        (None, _) => 0,
+    };
+    // The shortest walk to a destination, with that destination
+    let mut final_footpaths: Vec<Option<(u32, usize)>> = vec![None; timetable.stops.len()];
+    for &destination in destinations {
+        for fp in timetable.footpaths[destination]
+            .iter()
+            .filter(|fp| allowed(fp.from))
+        {
+            let walk = options.duration(fp).map(|d| (d, destination));
+            final_footpaths[fp.from] = min_duration(final_footpaths[fp.from], walk);
         }
-        profiles[*destination].push(Default::default());
+        profiles[destination].push(Profile {
+            destination: Some(destination),
+            ..Default::default()
+        });
     }
 
-    for (conn_index, c) in timetable.connections.iter().enumerate() {
+    let deadline = Deadline::new(options);
+    for (conn_index, c) in network.connections() {
+        // The connections are sorted by decreasing departure time
+        if c.dep_time < departure {
+            break;
+        }
+        if let Some(reach) = &options.earliest_reach {
+            if c.dep_time < reach[c.dep_stop] {
+                stats.connections_pruned += 1;
+                continue;
+            }
+        }
+        if deadline.is_exceeded(stats.connections_scanned) {
+            stats.partial = true;
+            break;
+        }
+        stats.connections_scanned += 1;
+        if !allowed(c.dep_stop) || !allowed(c.arr_stop) {
+            continue;
+        }
+
         // Case 1: walking to target
-        let t1 = final_footpaths[c.arr_stop].map(|d| c.arr_time + d);
+        let t1 = final_footpaths[c.arr_stop].map(|(d, destination)| {
+            let t = c.arr_time + d;
+            (t + options.walking_penalty(d), t, 0, Some(destination))
+        });
 
         // Case 2: Staying seated in the trip, we will reach the target at `t2`
         let t2 = arr_time_with_trip[c.trip];
 
         // Case 3: Transfering in the same stop, we look up the earliest compatible arrival
-        let t3 = arrival_time_with_stop_change(&profiles[c.arr_stop], c);
-
-        if let Some(t) = min_duration(t1, min_duration(t2, t3)) {
+        let t3 = arrival_time_with_stop_change(
+            &profiles[c.arr_stop],
+            c,
+            dominance.is_single_criterion(),
+        );
+
+        // With the same arrival, the fewest transfers
+        if let Some((cost, t, transfers, destination)) = [t1, t2, t3]
+            .iter()
+            .flatten()
+            .min()
+            .copied()
+            .filter(|(_, t, _, _)| !options.is_too_long(c.dep_time, *t))
+        {
             let candidate = Profile {
                 out_connection: Some(conn_index),
                 dep_time: c.dep_time,
                 arr_time: t,
+                transfers,
+                destination,
+                penalty: cost - t + leg_penalty(c.trip),
             };
 
-            if profiles[c.dep_stop].incorporate(candidate) {
+            let candidate_penalty = candidate.penalty;
+            if profiles[c.dep_stop].incorporate_with(candidate, dominance) {
                 for footpath in timetable.footpaths[c.dep_stop]
                     .iter()
-                    .filter(|p| p.duration < c.dep_time)
+                    .filter(|f| allowed(f.from))
                 {
-                    profiles[footpath.from].incorporate(Profile {
-                        out_connection: Some(conn_index),
-                        dep_time: c.dep_time - footpath.duration,
-                        arr_time: t,
-                    });
+                    let duration = options
+                        .duration(footpath)
+                        .filter(|d| *d < c.dep_time && !options.is_too_long(c.dep_time - d, t));
+                    if let Some(duration) = duration {
+                        let walk = Profile {
+                            out_connection: Some(conn_index),
+                            dep_time: c.dep_time - duration,
+                            arr_time: t,
+                            transfers,
+                            destination,
+                            penalty: candidate_penalty + options.walking_penalty(duration),
+                        };
+                        profiles[footpath.from].incorporate_with(walk, dominance);
+                    }
                 }
             }
             // Using this trip, we will reach the target at `t`
-            arr_time_with_trip[c.trip] = Some(t);
+            arr_time_with_trip[c.trip] = Some((cost, t, transfers, destination));
+        }
+    }
+
+    stats.profiles = profiles.iter().map(Vec::len).sum();
+    (profiles, stats)
+}
+
+// The earliest arrival at every stop when leaving the origins at `departure`, None if it can not be reached
+// Unlike `compute`, it only considers one departure time and is mostly used to build isochrones
+pub fn earliest_arrival(
+    timetable: &Timetable,
+    origins: &[usize],
+    departure: u32,
+) -> Vec<Option<u32>> {
+    let transfer_duration = 5;
+    let outgoing_footpaths = timetable.outgoing_footpaths();
+
+    let mut arrival = vec![None; timetable.stops.len()];
+    // The earliest time a connection can be boarded at every stop
+    let mut boarding = vec![u32::MAX; timetable.stops.len()];
+    let mut trip_reached = vec![false; timetable.trips.len()];
+    let mut reach = |boarding: &mut [u32], stop: usize, arr_time: u32, board_time: u32| {
+        for &(to, duration) in [(stop, 0)].iter().chain(&outgoing_footpaths[stop]) {
+            if arrival[to].is_none_or(|a| arr_time + duration < a) {
+                arrival[to] = Some(arr_time + duration);
+            }
+            boarding[to] = boarding[to].min(board_time + duration);
         }
+    };
+
+    for &origin in origins {
+        reach(&mut boarding, origin, departure, departure);
     }
 
-    profiles
+    // The connections are sorted by decreasing departure time
+    for c in timetable.connections.iter().rev() {
+        if c.dep_time < departure {
+            continue;
+        }
+        if trip_reached[c.trip] || boarding[c.dep_stop] <= c.dep_time {
+            trip_reached[c.trip] = true;
+            let board_time = c.arr_time + transfer_duration + 1;
+            reach(&mut boarding, c.arr_stop, c.arr_time, board_time);
+        }
+    }
+
+    arrival
+}
+
+// How a stop was reached, to rebuild the route
+#[derive(Debug, Clone, Copy)]
+enum Reach {
+    Origin(usize),
+    // The position of the connection in the scan
+    Connection(usize),
+}
+
+// The route arriving the earliest at one of the destinations when leaving the origins at `departure`
+// It returns the origin with the indices of the connections, much cheaper than the profiles
+// of every departure, but it does not minimize the transfers nor the penalties of the options
+pub fn earliest_arrival_route<N: Network>(
+    network: &N,
+    origins: &[usize],
+    destinations: &[usize],
+    departure: u32,
+    options: &QueryOptions,
+) -> (Option<(usize, Vec<usize>)>, ScanStats) {
+    let transfer_duration = 5;
+    let timetable = network.timetable();
+    let mut stats = ScanStats::default();
+    let allowed_stops = options.allowed_stops(timetable);
+    let allowed = |stop: usize| allowed_stops.as_ref().is_none_or(|allowed| allowed[stop]);
+    let mut outgoing_footpaths = vec![Vec::new(); timetable.stops.len()];
+    for (to, footpaths) in timetable.footpaths.iter().enumerate() {
+        for footpath in footpaths.iter().filter(|f| allowed(f.from) && allowed(to)) {
+            if let Some(duration) = options.duration(footpath) {
+                outgoing_footpaths[footpath.from].push((to, duration));
+            }
+        }
+    }
+    // The shortest walk to a destination
+    let mut final_footpaths: Vec<Option<u32>> = vec![None; timetable.stops.len()];
+    for &destination in destinations.iter().filter(|&&d| allowed(d)) {
+        final_footpaths[destination] = Some(0);
+        for fp in timetable.footpaths[destination]
+            .iter()
+            .filter(|fp| allowed(fp.from))
+        {
+            final_footpaths[fp.from] = min_duration(final_footpaths[fp.from], options.duration(fp));
+        }
+    }
+
+    // The earliest time a connection can be boarded at every stop, and how
+    let mut boarding: Vec<Option<(u32, Reach)>> = vec![None; timetable.stops.len()];
+    let board = |boarding: &mut [Option<(u32, Reach)>], stop: usize, time: u32, reach: Reach| {
+        for &(to, duration) in [(stop, 0)].iter().chain(&outgoing_footpaths[stop]) {
+            if boarding[to].is_none_or(|(t, _)| time + duration < t) {
+                boarding[to] = Some((time + duration, reach));
+            }
+        }
+    };
+    for &origin in origins.iter().filter(|&&o| allowed(o)) {
+        board(&mut boarding, origin, departure, Reach::Origin(origin));
+    }
+
+    // By increasing departure, as the scan goes forward in time
+    let mut scanned: Vec<_> = network
+        .connections()
+        .take_while(|(_, c)| c.dep_time >= departure)
+        .collect();
+    scanned.reverse();
+    // The previous connection of the trip, or how the stop was reached to board it
+    let mut previous: Vec<Option<Reach>> = Vec::with_capacity(scanned.len());
+    let mut trip_last: Vec<Option<usize>> = vec![None; timetable.trips.len()];
+    let mut best: Option<(u32, usize)> = None;
+    let deadline = Deadline::new(options);
+    for (position, (_, c)) in scanned.iter().enumerate() {
+        if best.is_some_and(|(arrival, _)| c.dep_time >= arrival) {
+            break;
+        }
+        // The best route so far might arrive later than the optimal one
+        if deadline.is_exceeded(stats.connections_scanned) {
+            stats.partial = true;
+            break;
+        }
+        stats.connections_scanned += 1;
+        let reach = match (trip_last[c.trip], boarding[c.dep_stop]) {
+            (Some(last), _) => Some(Reach::Connection(last)),
+            (None, Some((time, reach))) if time <= c.dep_time => Some(reach),
+            _ => None,
+        }
+        .filter(|_| allowed(c.dep_stop) && allowed(c.arr_stop));
+        previous.push(reach);
+        if reach.is_none() {
+            // The trip can not be followed through a stop that is not allowed
+            trip_last[c.trip] = None;
+            continue;
+        }
+        trip_last[c.trip] = Some(position);
+        if let Some(walk) = final_footpaths[c.arr_stop] {
+            let arrival = c.arr_time + walk;
+            if best.is_none_or(|(t, _)| arrival < t) {
+                best = Some((arrival, position));
+            }
+        }
+        board(
+            &mut boarding,
+            c.arr_stop,
+            c.arr_time + transfer_duration + 1,
+            Reach::Connection(position),
+        );
+    }
+
+    let (_, last) = match best {
+        Some(best) => best,
+        None => return (None, stats),
+    };
+    let mut route = vec![last];
+    let mut position = last;
+    let origin = loop {
+        match previous[position] {
+            Some(Reach::Connection(p)) => {
+                route.push(p);
+                position = p;
+            }
+            Some(Reach::Origin(origin)) => break origin,
+            None => unreachable!("The route goes through a connection that was not reached"),
+        }
+    };
+    route.reverse();
+    let route: Vec<_> = route.iter().map(|&p| scanned[p].0).collect();
+    if route
+        .first()
+        .zip(route.last())
+        .is_some_and(|(&first, &last)| {
+            options.is_too_long(
+                network.connection(first).dep_time,
+                network.connection(last).arr_time,
+            )
+        })
+    {
+        return (None, stats);
+    }
+    (Some((origin, route)), stats)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::structures::Footpath;
     #[test]
     fn test_incorporate() {
         let mut profiles = Vec::new();
@@ -168,6 +594,9 @@ mod tests {
             dep_time: 20,
             arr_time: 30,
             out_connection: None,
+            transfers: 0,
+            destination: None,
+            penalty: 0,
         });
 
         assert_eq!(1, profiles.len());
@@ -176,6 +605,9 @@ mod tests {
             dep_time: 10,
             arr_time: 20,
             out_connection: None,
+            transfers: 0,
+            destination: None,
+            penalty: 0,
         });
         assert_eq!(2, profiles.len());
 
@@ -184,6 +616,9 @@ mod tests {
             dep_time: 8,
             arr_time: 21,
             out_connection: None,
+            transfers: 0,
+            destination: None,
+            penalty: 0,
         });
         assert_eq!(2, profiles.len());
         assert_eq!(10, profiles[1].dep_time);
@@ -192,6 +627,9 @@ mod tests {
             dep_time: 0,
             arr_time: 10,
             out_connection: None,
+            transfers: 0,
+            destination: None,
+            penalty: 0,
         });
         assert_eq!(3, profiles.len());
 
@@ -200,6 +638,9 @@ mod tests {
             dep_time: 11,
             arr_time: 20,
             out_connection: None,
+            transfers: 0,
+            destination: None,
+            penalty: 0,
         });
         assert_eq!(3, profiles.len());
         assert_eq!(11, profiles[1].dep_time);
@@ -221,9 +662,31 @@ mod tests {
         assert_eq!(10, profiles[0][0].dep_time);
         assert_eq!(40, profiles[0][0].arr_time);
 
+        assert_eq!(1, profiles[0][0].transfers);
+
         assert_eq!(1, profiles[1].len());
         assert_eq!(30, profiles[1][0].dep_time);
         assert_eq!(40, profiles[1][0].arr_time);
+        assert_eq!(0, profiles[1][0].transfers);
+    }
+
+    #[test]
+    fn after_departure() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("a", "1:10")
+            .s("b", "1:20");
+
+        let t = b.build();
+        let (profiles, stats) = compute_with_stats(&t, &[1], 30, &QueryOptions::default());
+        assert_eq!(1, profiles[0].len());
+        assert_eq!(70, profiles[0][0].dep_time);
+        assert_eq!(1, stats.connections_scanned);
+        // With the one of the destination
+        assert_eq!(2, stats.profiles);
     }
 
     #[test]
@@ -299,6 +762,43 @@ mod tests {
         assert_eq!(40, profiles[0][0].arr_time);
     }
 
+    #[test]
+    fn custom_dominance() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("b", "0:30")
+            .s("c", "0:40")
+            .trip()
+            .s("a", "0:10")
+            .s("c", "0:50");
+
+        let t = b.build();
+        // Fewer transfers are worth arriving later
+        let dominance =
+            |a: &Profile, b: &Profile| a.cost() <= b.cost() && a.transfers <= b.transfers;
+        let (profiles, _) =
+            compute_with_dominance(&t, &[2], 0, &QueryOptions::default(), &dominance);
+        let mut arrivals: Vec<_> = profiles[0]
+            .iter()
+            .map(|p| (p.arr_time, p.transfers))
+            .collect();
+        arrivals.sort_unstable();
+        assert_eq!(vec![(40, 1), (50, 0)], arrivals);
+
+        // A criterion that is always met keeps the first candidate
+        let (profiles, _) = compute_with_dominance(
+            &t,
+            &[2],
+            0,
+            &QueryOptions::default(),
+            &|_: &Profile, _: &Profile| true,
+        );
+        assert_eq!(1, profiles[0].len());
+    }
+
     #[test]
     fn stay_seated() {
         let mut b = Timetable::builder();
@@ -313,6 +813,23 @@ mod tests {
         assert_eq!(40, profiles[1][0].arr_time);
     }
 
+    #[test]
+    fn fewest_transfers() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .s("c", "0:40")
+            .trip()
+            .s("b", "0:30")
+            .s("c", "0:40");
+        let t = b.build();
+        // Changing at b arrives at the same time
+        let profiles = compute(&t, &[2]);
+        assert_eq!(40, profiles[0][0].arr_time);
+        assert_eq!(0, profiles[0][0].transfers);
+    }
+
     #[test]
     fn footpath() {
         let mut b = Timetable::builder();
@@ -323,10 +840,7 @@ mod tests {
             .s("c", "0:30")
             .s("d", "0:40");
         let mut t = b.build();
-        t.footpaths[2].push(Footpath {
-            from: 1,
-            duration: 3,
-        });
+        t.add_footpath(1, 2, 3);
         let profiles = compute(&t, &[3]);
         assert_eq!(1, profiles[0].len());
         assert_eq!(10, profiles[0][0].dep_time);
@@ -334,6 +848,30 @@ mod tests {
         assert_eq!(27, profiles[1][0].dep_time);
     }
 
+    #[test]
+    fn budget() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("b", "0:20");
+        let t = b.build();
+        let mut options = QueryOptions {
+            budget: Some(std::time::Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let (profiles, stats) = compute_with_stats(&t, &[1], 0, &options);
+        assert!(!stats.partial);
+        assert_eq!(1, profiles[0].len());
+
+        // The scan stops before the first connection
+        options.budget = Some(std::time::Duration::ZERO);
+        let (profiles, stats) = compute_with_stats(&t, &[1], 0, &options);
+        assert!(stats.partial);
+        assert_eq!(0, stats.connections_scanned);
+        assert!(profiles[0].is_empty());
+        let (route, stats) = earliest_arrival_route(&t, &[0], &[1], 0, &options);
+        assert!(stats.partial);
+        assert!(route.is_none());
+    }
+
     #[test]
     fn final_footpath() {
         let mut b = Timetable::builder();
@@ -344,14 +882,33 @@ mod tests {
             .s("b", "0:30")
             .s("c", "0:40");
         let mut t = b.build();
-        t.footpaths[2].push(Footpath {
-            from: 1,
-            duration: 3,
-        });
+        t.add_footpath(1, 2, 3);
         let profiles = compute(&t, &[2]);
         assert_eq!(23, profiles[0][0].arr_time);
     }
 
+    #[test]
+    fn asymmetric_footpath() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("c", "0:30")
+            .s("d", "0:40");
+        let mut t = b.build();
+        // Walking from b to c is fast, but slow the other way around
+        t.add_footpath(1, 2, 3);
+        t.add_footpath(2, 1, 30);
+        let profiles = compute(&t, &[3]);
+        assert_eq!(1, profiles[0].len());
+
+        t.add_footpath(1, 2, 30);
+        t.add_footpath(2, 1, 3);
+        let profiles = compute(&t, &[3]);
+        assert!(profiles[0].is_empty());
+    }
+
     #[test]
     fn final_multiple_footpath() {
         let mut b = Timetable::builder();
@@ -362,16 +919,75 @@ mod tests {
             .s("c", "0:30")
             .s("d", "0:40");
         let mut t = b.build();
-        t.footpaths[2].push(Footpath {
-            from: 1,
-            duration: 3,
-        });
-        t.footpaths[3].push(Footpath {
-            from: 1,
-            duration: 10,
-        });
+        t.add_footpath(1, 2, 3);
+        t.add_footpath(1, 3, 10);
         let profiles = compute(&t, &[2, 3]);
         assert_eq!(23, profiles[0][0].arr_time);
+        assert_eq!(Some(2), profiles[0][0].destination);
+    }
+
+    #[test]
+    fn reached_destination() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .s("c", "0:30")
+            .trip()
+            .s("a", "0:40")
+            .s("d", "0:45");
+        let t = b.build();
+        let profiles = compute(&t, &[2, 3]);
+        assert_eq!(2, profiles[0].len());
+        assert_eq!(Some(3), profiles[0][0].destination);
+        assert_eq!(Some(2), profiles[0][1].destination);
+        // Staying seated
+        assert_eq!(Some(2), profiles[1][0].destination);
+    }
+
+    #[test]
+    fn earliest_arrival_with_transfer() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("b", "0:30")
+            .s("c", "0:40")
+            .trip()
+            .s("b", "0:22")
+            .s("d", "0:50");
+        let mut t = b.build();
+        t.add_footpath(2, 3, 4);
+        let arrival = earliest_arrival(&t, &[0], 5);
+        assert_eq!(vec![Some(5), Some(20), Some(40), Some(44)], arrival);
+        // The first trip left already
+        assert_eq!(
+            vec![Some(11), None, None, None],
+            earliest_arrival(&t, &[0], 11)
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a1", "0:10")
+            .s("c", "0:50")
+            .trip()
+            .s("a2", "0:20")
+            .s("c", "0:30")
+            .trip()
+            .s("a1", "0:40")
+            .s("c", "0:45");
+        let t = b.build();
+        let profiles = compute(&t, &[1]);
+        // Leaving a2 at 20 dominates leaving a1 at 10
+        let merged = merge_profiles(vec![&profiles[0], &profiles[2]]);
+        assert_eq!(2, merged.len());
+        assert_eq!(40, merged[0].dep_time);
+        assert_eq!(20, merged[1].dep_time);
+        assert!(merge_profiles(vec![&profiles[1]]).is_empty());
     }
 
     #[test]
@@ -0,0 +1,315 @@
+// Statistics on the timetable itself, for network planning
+use crate::algo::{compute_after, earliest_arrival};
+use crate::query::QueryOptions;
+use crate::structures::Timetable;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::{BufWriter, Write};
+
+const SECONDS_PER_DAY: u32 = 86_400;
+
+// The service at a stop over a day
+#[derive(Debug, Clone, Serialize)]
+pub struct StopService {
+    pub stop_id: String,
+    pub departures: usize,
+    // From 0:00 to 23:00
+    pub departures_per_hour: Vec<usize>,
+    // In seconds since midnight
+    pub first_departure: Option<u32>,
+    pub last_departure: Option<u32>,
+    pub routes: usize,
+}
+
+// Every stop of the timetable, in the same order, None if the date is before the timetable
+// A trip leaving twice from a stop (like along a hail-and-ride segment) counts as one departure
+pub fn stop_services(timetable: &Timetable, date: chrono::NaiveDate) -> Option<Vec<StopService>> {
+    let start = timetable.time(date.and_hms(0, 0, 0))?;
+    let end = start + SECONDS_PER_DAY;
+    let mut departures = vec![HashSet::new(); timetable.stops.len()];
+    for c in &timetable.connections {
+        if c.dep_time >= start && c.dep_time < end {
+            departures[c.dep_stop].insert((c.trip, c.dep_time - start));
+        }
+    }
+
+    let services = departures
+        .iter()
+        .zip(&timetable.stops)
+        .map(|(departures, stop)| {
+            let mut departures_per_hour = vec![0; 24];
+            for (_, time) in departures {
+                departures_per_hour[(time / 3600) as usize] += 1;
+            }
+            let routes: HashSet<_> = departures
+                .iter()
+                .filter_map(|(trip, _)| timetable.trips[*trip].route)
+                .collect();
+            StopService {
+                stop_id: stop.id.to_owned(),
+                departures: departures.len(),
+                departures_per_hour,
+                first_departure: departures.iter().map(|(_, time)| *time).min(),
+                last_departure: departures.iter().map(|(_, time)| *time).max(),
+                routes: routes.len(),
+            }
+        })
+        .collect();
+    Some(services)
+}
+
+// Like the GTFS times, as HH:MM:SS
+fn format_time(time: Option<u32>) -> String {
+    time.map(|t| format!("{:02}:{:02}:{:02}", t / 3600, t / 60 % 60, t % 60))
+        .unwrap_or_default()
+}
+
+// The ids are quoted when needed
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+// One line per stop, the departures of every hour being the columns `h0` to `h23`
+pub fn write_stop_services_csv<W: Write>(
+    services: &[StopService],
+    writer: W,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    let hours: Vec<_> = (0..24).map(|h| format!("h{}", h)).collect();
+    writeln!(
+        writer,
+        "stop_id,departures,first_departure,last_departure,routes,{}",
+        hours.join(",")
+    )?;
+    for service in services {
+        let per_hour: Vec<_> = service
+            .departures_per_hour
+            .iter()
+            .map(usize::to_string)
+            .collect();
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            csv_field(&service.stop_id),
+            service.departures,
+            format_time(service.first_departure),
+            format_time(service.last_departure),
+            service.routes,
+            per_hour.join(",")
+        )?;
+    }
+    writer.flush()
+}
+
+pub fn write_stop_services_json<W: Write>(
+    services: &[StopService],
+    writer: W,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    serde_json::to_writer(&mut writer, services)?;
+    writer.flush()
+}
+
+// How long it takes to reach a stop from the hub, even when over the budget
+#[derive(Debug, Clone, Serialize)]
+pub struct StopReachability {
+    pub stop_id: String,
+    pub reachable: bool,
+    // In seconds, None if the stop can not be reached at all
+    pub duration: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Reachability {
+    pub departure: u32,
+    // In seconds
+    pub budget: u32,
+    // Every stop of the timetable, in the same order
+    pub stops: Vec<StopReachability>,
+}
+
+impl Reachability {
+    pub fn reachable(&self) -> impl Iterator<Item = &StopReachability> {
+        self.stops.iter().filter(|stop| stop.reachable)
+    }
+
+    pub fn unreachable(&self) -> impl Iterator<Item = &StopReachability> {
+        self.stops.iter().filter(|stop| !stop.reachable)
+    }
+}
+
+// The stops that can be reached within `budget` seconds when leaving the hub at `departure`
+// The hub is given by its stops, like the platforms of a stop area
+pub fn reachability(
+    timetable: &Timetable,
+    hub: &[usize],
+    departure: u32,
+    budget: u32,
+) -> Reachability {
+    let stops = earliest_arrival(timetable, hub, departure)
+        .iter()
+        .zip(&timetable.stops)
+        .map(|(arrival, stop)| {
+            let duration = arrival.map(|arrival| arrival - departure);
+            StopReachability {
+                stop_id: stop.id.to_owned(),
+                reachable: duration.is_some_and(|d| d <= budget),
+                duration,
+            }
+        })
+        .collect();
+    Reachability {
+        departure,
+        budget,
+        stops,
+    }
+}
+
+// The travel time from every stop to the closest destination when leaving at `departure`
+// in seconds, waiting included, with a single backward profile scan
+// None if no destination can be reached
+pub fn travel_times(
+    timetable: &Timetable,
+    destinations: &[usize],
+    departure: u32,
+) -> Vec<Option<u32>> {
+    let profiles = compute_after(timetable, destinations, departure, &QueryOptions::default());
+    profiles
+        .iter()
+        .enumerate()
+        .map(|(stop, profiles)| {
+            if destinations.contains(&stop) {
+                return Some(0);
+            }
+            // Sorted by decreasing departure, the last one arrives first
+            profiles
+                .iter()
+                .rev()
+                .find(|p| p.out_connection.is_some() && p.dep_time >= departure)
+                .map(|p| p.arr_time - departure)
+        })
+        .collect()
+}
+
+// `stop_id,lat,lon,minutes` for every stop that reaches a destination, like QGIS or kepler.gl expect
+pub fn write_heatmap_csv<W: Write>(
+    timetable: &Timetable,
+    travel_times: &[Option<u32>],
+    writer: W,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    writeln!(writer, "stop_id,lat,lon,minutes")?;
+    for (stop, time) in timetable.stops.iter().zip(travel_times) {
+        if let Some(time) = time {
+            let coord = |c: Option<f64>| c.map(|c| c.to_string()).unwrap_or_default();
+            writeln!(
+                writer,
+                "{},{},{},{:.1}",
+                csv_field(&stop.id),
+                coord(stop.latitude),
+                coord(stop.longitude),
+                f64::from(*time) / 60.
+            )?;
+        }
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn services() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "61:00")
+            .s("b", "62:00")
+            .trip()
+            .s("a", "125:00")
+            .s("b", "126:00")
+            .trip()
+            .s("a", "1500:00")
+            .s("b", "1501:00");
+        let t = b.build();
+
+        let day = t.start_date;
+        let services = stop_services(&t, day).unwrap();
+        assert_eq!(2, services[0].departures);
+        assert_eq!(1, services[0].departures_per_hour[1]);
+        assert_eq!(1, services[0].departures_per_hour[2]);
+        assert_eq!(Some(3660), services[0].first_departure);
+        assert_eq!(Some(7500), services[0].last_departure);
+        assert_eq!(0, services[1].departures);
+        assert_eq!(None, services[1].first_departure);
+
+        // The third trip leaves the next day
+        let next_day = stop_services(&t, day.succ()).unwrap();
+        assert_eq!(1, next_day[0].departures);
+        assert!(stop_services(&t, day.pred()).is_none());
+
+        let mut csv = Vec::new();
+        write_stop_services_csv(&services, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let line = csv.lines().nth(1).unwrap();
+        assert!(line.starts_with("a,2,01:01:00,02:05:00,0,0,1,1,0"));
+    }
+
+    #[test]
+    fn reachable_stops() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "8:00")
+            .s("b", "20:00")
+            .s("c", "60:00")
+            .trip()
+            .s("d", "9:00")
+            .s("a", "10:00");
+        let t = b.build();
+
+        let reachability = reachability(&t, &[0], 480, 45 * 60);
+        let reachable: Vec<_> = reachability.reachable().map(|s| &s.stop_id).collect();
+        assert_eq!(vec!["a", "b"], reachable);
+        let unreachable: Vec<_> = reachability.unreachable().collect();
+        assert_eq!("c", unreachable[0].stop_id);
+        assert_eq!(Some(3120), unreachable[0].duration);
+        assert_eq!("d", unreachable[1].stop_id);
+        assert_eq!(None, unreachable[1].duration);
+    }
+
+    #[test]
+    fn heatmap() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "10:00")
+            .s("b", "20:00")
+            .s("c", "30:00")
+            .trip()
+            .s("d", "1:00")
+            .s("e", "2:00");
+        let mut t = b.build();
+        t.stops[0].latitude = Some(48.8);
+        t.stops[0].longitude = Some(2.3);
+
+        let times = travel_times(&t, &[2], 300);
+        assert_eq!(vec![Some(1500), Some(1500), Some(0), None, None], times);
+
+        let mut csv = Vec::new();
+        write_heatmap_csv(&t, &times, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            vec![
+                "stop_id,lat,lon,minutes",
+                "a,48.8,2.3,25.0",
+                "b,,,25.0",
+                "c,,,0.0"
+            ],
+            lines
+        );
+    }
+}
@@ -0,0 +1,191 @@
+// Aggregated usage of a server: the requests, errors and latencies by endpoint and the most
+// requested destinations, to size the horizon and the caches on the real demand
+// The memory does not grow with the requests: the latencies are counted by bucket, and there is at
+// most one counter by destination
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+// Every bucket is about 19% wider than the previous one, the percentiles are as precise
+const BUCKETS_BY_OCTAVE: f64 = 4.;
+
+#[derive(Debug, Clone, Default)]
+struct Latencies {
+    // By bucket of the logarithm of the microseconds
+    buckets: Vec<u64>,
+    count: u64,
+}
+
+impl Latencies {
+    fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().max(1) as f64;
+        let bucket = (micros.log2() * BUCKETS_BY_OCTAVE) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+    }
+
+    // In milliseconds, the upper bound of the bucket of the percentile, between 0 and 1
+    fn percentile(&self, percentile: f64) -> Option<f64> {
+        let rank = ((percentile * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = self.buckets.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        })?;
+        Some(2f64.powf((bucket + 1) as f64 / BUCKETS_BY_OCTAVE) / 1000.)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Endpoint {
+    requests: u64,
+    client_errors: u64,
+    server_errors: u64,
+    latencies: Latencies,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Analytics {
+    // By route, like /to/{stop_area}
+    endpoints: BTreeMap<String, Endpoint>,
+    // By stop area id
+    destinations: HashMap<String, u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EndpointReport {
+    pub requests: u64,
+    // Status 4xx
+    pub client_errors: u64,
+    // Status 5xx
+    pub server_errors: u64,
+    pub error_rate: f64,
+    pub p50_ms: Option<f64>,
+    pub p90_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DestinationReport {
+    pub destination: String,
+    pub requests: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub requests: u64,
+    pub endpoints: BTreeMap<String, EndpointReport>,
+    // The most requested first
+    pub top_destinations: Vec<DestinationReport>,
+}
+
+impl Analytics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_request(&mut self, endpoint: &str, status: u16, latency: Duration) {
+        if !self.endpoints.contains_key(endpoint) {
+            self.endpoints
+                .insert(endpoint.to_owned(), Endpoint::default());
+        }
+        let stats = self.endpoints.get_mut(endpoint).expect("Inserted endpoint");
+        stats.requests += 1;
+        match status {
+            400..=499 => stats.client_errors += 1,
+            500..=599 => stats.server_errors += 1,
+            _ => (),
+        }
+        stats.latencies.record(latency);
+    }
+
+    // Only the valid destinations, so that there are not more than the stop areas
+    pub fn record_destination(&mut self, destination: &str) {
+        match self.destinations.get_mut(destination) {
+            Some(count) => *count += 1,
+            None => {
+                self.destinations.insert(destination.to_owned(), 1);
+            }
+        }
+    }
+
+    // With the `top` most requested destinations, the ties by id
+    pub fn report(&self, top: usize) -> Report {
+        let endpoints = self
+            .endpoints
+            .iter()
+            .map(|(endpoint, stats)| {
+                let errors = stats.client_errors + stats.server_errors;
+                let report = EndpointReport {
+                    requests: stats.requests,
+                    client_errors: stats.client_errors,
+                    server_errors: stats.server_errors,
+                    error_rate: errors as f64 / stats.requests.max(1) as f64,
+                    p50_ms: stats.latencies.percentile(0.5),
+                    p90_ms: stats.latencies.percentile(0.9),
+                    p99_ms: stats.latencies.percentile(0.99),
+                };
+                (endpoint.to_owned(), report)
+            })
+            .collect();
+        let mut destinations: Vec<_> = self.destinations.iter().collect();
+        destinations.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        Report {
+            requests: self.endpoints.values().map(|e| e.requests).sum(),
+            endpoints,
+            top_destinations: destinations
+                .into_iter()
+                .take(top)
+                .map(|(destination, &requests)| DestinationReport {
+                    destination: destination.to_owned(),
+                    requests,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let mut analytics = Analytics::new();
+        for ms in 1..=100 {
+            analytics.record_request("/journeys", 200, Duration::from_millis(ms));
+        }
+        analytics.record_request("/journeys", 400, Duration::from_millis(1));
+        analytics.record_request("/plan", 500, Duration::from_millis(1));
+        for destination in &["b", "a", "c", "a", "c"] {
+            analytics.record_destination(destination);
+        }
+
+        let report = analytics.report(2);
+        assert_eq!(102, report.requests);
+        let journeys = &report.endpoints["/journeys"];
+        assert_eq!(
+            (101, 1, 0),
+            (
+                journeys.requests,
+                journeys.client_errors,
+                journeys.server_errors
+            )
+        );
+        // Within a bucket of the exact percentiles
+        let p50 = journeys.p50_ms.unwrap();
+        assert!((50. ..50. * 1.2).contains(&p50), "{}", p50);
+        let p99 = journeys.p99_ms.unwrap();
+        assert!((99. ..99. * 1.2).contains(&p99), "{}", p99);
+        assert_eq!(1., report.endpoints["/plan"].error_rate);
+        let top: Vec<_> = report
+            .top_destinations
+            .iter()
+            .map(|d| (d.destination.as_str(), d.requests))
+            .collect();
+        assert_eq!(vec![("a", 2), ("c", 2)], top);
+    }
+}
@@ -1,6 +1,7 @@
-use chrono::prelude::*;
-use cpuprofiler::PROFILER;
+use csa::query::milliseconds;
 use csa::*;
+use serde::Serialize;
+use std::time::Instant;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -27,16 +28,252 @@ struct Opt {
         default_value = "."
     )]
     input: String,
+
+    #[structopt(long = "json", help = "Print the results as JSON")]
+    json: bool,
+
+    #[structopt(
+        long = "random",
+        help = "Also runs this many random queries, between stop areas weighted by their connections",
+        default_value = "0"
+    )]
+    random: usize,
+
+    #[structopt(
+        long = "replay",
+        help = "Only replays the queries logged by the server with --query-log (csa-replay mode)"
+    )]
+    replay: Option<String>,
+
+    #[structopt(
+        long = "seed",
+        help = "The seed of the random queries, the same seed gives the same queries",
+        default_value = "1"
+    )]
+    seed: u64,
+
+    #[structopt(
+        long = "landmarks",
+        help = "Prunes the random and replayed queries with the lower bounds of this many landmark stops",
+        default_value = "0"
+    )]
+    landmarks: usize,
+}
+
+// A single run: the criterion benchmarks are the reference for the regressions
+#[derive(Serialize)]
+struct StopAreaResult {
+    stop_area: String,
+    ms: f64,
+}
+
+#[derive(Serialize)]
+struct QueriesResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    queries: usize,
+    // Found over all the queries
+    journeys: usize,
+    connections_scanned: usize,
+    connections_pruned: usize,
+    total_ms: f64,
+    mean_ms: f64,
+}
+
+impl QueriesResult {
+    // The landmarks are computed before, their precomputation is not counted
+    fn run(
+        timetable: &structures::Timetable,
+        queries: &[query::Query],
+        seed: Option<u64>,
+        landmarks: Option<&landmarks::Landmarks>,
+    ) -> Self {
+        let start = Instant::now();
+        let (mut journeys, mut connections_scanned, mut connections_pruned) = (0, 0, 0);
+        for q in queries {
+            let mut q = q.clone();
+            if let Some(landmarks) = landmarks {
+                landmarks.prune(&mut q);
+            }
+            let (found, stats) = q.journeys_with_stats(timetable);
+            journeys += found.len();
+            connections_scanned += stats.scan.connections_scanned;
+            connections_pruned += stats.scan.connections_pruned;
+        }
+        let total_ms = milliseconds(start.elapsed());
+        Self {
+            seed,
+            queries: queries.len(),
+            journeys,
+            connections_scanned,
+            connections_pruned,
+            total_ms,
+            mean_ms: total_ms / queries.len().max(1) as f64,
+        }
+    }
+
+    fn print(&self, name: &str) {
+        println!(
+            "{} {} queries in {:.0} ms, {:.1} ms each, {} journeys, {} connections scanned and {} pruned",
+            self.queries,
+            name,
+            self.total_ms,
+            self.mean_ms,
+            self.journeys,
+            self.connections_scanned,
+            self.connections_pruned
+        );
+    }
+}
+
+fn landmarks(timetable: &structures::Timetable, opt: &Opt) -> Option<landmarks::Landmarks> {
+    if opt.landmarks == 0 {
+        return None;
+    }
+    let start = Instant::now();
+    let landmarks = landmarks::Landmarks::new(timetable, opt.landmarks);
+    if !opt.json {
+        println!(
+            "{} landmarks computed in {:.0} ms",
+            landmarks.stops.len(),
+            milliseconds(start.elapsed())
+        );
+    }
+    Some(landmarks)
+}
+
+#[derive(Serialize)]
+struct ReplayResult {
+    first_day: String,
+    horizon: u16,
+    // Minus the ones whose stops are not in the timetable
+    logged: usize,
+    replayed: QueriesResult,
+}
+
+#[derive(Serialize)]
+struct MemoryUsage {
+    // Peak resident set size of the process, only available on Linux
+    peak_rss_bytes: Option<u64>,
+    connections_bytes: usize,
+    footpaths_bytes: usize,
+    profiles_bytes: usize,
+    profiles: usize,
+}
+
+impl MemoryUsage {
+    fn new(timetable: &structures::Timetable, profiles: &[Vec<algo::Profile>]) -> Self {
+        Self {
+            peak_rss_bytes: peak_rss(),
+            connections_bytes: timetable.connections.capacity()
+                * std::mem::size_of::<structures::Connection>(),
+            footpaths_bytes: timetable
+                .footpaths
+                .iter()
+                .map(|f| {
+                    std::mem::size_of::<Vec<structures::Footpath>>()
+                        + f.capacity() * std::mem::size_of::<structures::Footpath>()
+                })
+                .sum(),
+            profiles_bytes: profiles
+                .iter()
+                .map(|p| {
+                    std::mem::size_of::<Vec<algo::Profile>>()
+                        + p.capacity() * std::mem::size_of::<algo::Profile>()
+                })
+                .sum(),
+            profiles: profiles.iter().map(Vec::len).sum(),
+        }
+    }
+
+    fn print(&self) {
+        let mb = |bytes: usize| bytes as f64 / (1024. * 1024.);
+        println!("Memory usage: ");
+        match self.peak_rss_bytes {
+            Some(rss) => println!("  Peak RSS: {:.1} MB", mb(rss as usize)),
+            None => println!("  Peak RSS: unavailable on this platform"),
+        }
+        println!("  Connections: {:.1} MB", mb(self.connections_bytes));
+        println!("  Footpaths: {:.1} MB", mb(self.footpaths_bytes));
+        println!(
+            "  Profiles: {:.1} MB ({} profiles)",
+            mb(self.profiles_bytes),
+            self.profiles
+        );
+    }
+}
+
+// VmHWM is the peak resident set size, reported in kB
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find(|line| line.starts_with("VmHWM:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
+
+#[derive(Serialize)]
+struct BenchmarkResult {
+    first_day: String,
+    horizon: u16,
+    stops: usize,
+    connections: usize,
+    conversion_ms: i64,
+    total_ms: f64,
+    stop_areas: Vec<StopAreaResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    random_queries: Option<QueriesResult>,
+    memory: MemoryUsage,
+}
+
+#[cfg(feature = "profiling")]
+fn write_flamegraph(guard: &pprof::ProfilerGuard, path: &str) {
+    let report = guard.report().build().expect("Could not build the profile");
+    let file = std::fs::File::create(path).expect("Could not create the flamegraph file");
+    report
+        .flamegraph(file)
+        .expect("Could not write the flamegraph");
+}
+
+// Only runs the queries of a log of the server, instead of the fixed stop areas
+fn replay(timetable: &structures::Timetable, path: &str, opt: &Opt) {
+    let file = std::fs::File::open(path)
+        .unwrap_or_else(|e| panic!("Could not open the query log {}: {}", path, e));
+    let logged = query_log::read_log(std::io::BufReader::new(file))
+        .unwrap_or_else(|e| panic!("Could not read the query log {}: {}", path, e));
+    let queries: Vec<_> = logged.iter().filter_map(|q| q.query(timetable)).collect();
+    let landmarks = landmarks(timetable, opt);
+    let replayed = QueriesResult::run(timetable, &queries, None, landmarks.as_ref());
+    if opt.json {
+        let result = ReplayResult {
+            first_day: opt.first_day.to_owned(),
+            horizon: opt.horizon,
+            logged: logged.len(),
+            replayed,
+        };
+        println!("{}", serde_json::to_string(&result).unwrap());
+    } else {
+        println!("{} logged queries", logged.len());
+        replayed.print("replayed");
+    }
 }
 
 fn main() {
+    csa::cli::completions(Opt::clap());
     let opt = Opt::from_args();
     let gtfs = gtfs_structures::Gtfs::new(&opt.input).unwrap();
-    gtfs.print_stats();
     let timetable = structures::Timetable::from_gtfs(&gtfs, &opt.first_day, opt.horizon);
-    timetable.print_stats();
+    if !opt.json {
+        gtfs.print_stats();
+        timetable.print_stats();
+    }
+    if let Some(path) = &opt.replay {
+        replay(&timetable, path, &opt);
+        return;
+    }
 
-    let runs = 5;
     let chatelet_les_halles = "StopArea:8775860";
     let gare_de_provins = "StopArea:8711616";
     let gare_de_mantes = "StopArea:8738150";
@@ -48,19 +285,61 @@ fn main() {
         gare_de_provins,
         vignoles,
     ];
-    let now = Utc::now();
-    PROFILER.lock().unwrap().start("./bench.profile").unwrap();
+    let now = Instant::now();
+    let mut results = Vec::new();
+    let mut profiles = Vec::new();
+    #[cfg(feature = "profiling")]
+    let guard = pprof::ProfilerGuard::new(100).expect("Could not start the profiler");
     for sa in stop_areas {
-        for _ in 0..runs {
-            let to = timetable.stop_index_by_stop_area_id(sa);
-            algo::compute(&timetable, &to);
-        }
+        let start = Instant::now();
+        let to = timetable.stop_index_by_stop_area_id(sa);
+        profiles = algo::compute(&timetable, &to);
+        results.push(StopAreaResult {
+            stop_area: sa.to_string(),
+            ms: milliseconds(start.elapsed()),
+        });
     }
-    PROFILER.lock().unwrap().stop().unwrap();
+    let random_queries = if opt.random > 0 {
+        let queries: Vec<_> = sampling::QuerySampler::new(&timetable, opt.seed)
+            .take(opt.random)
+            .collect();
+        let landmarks = landmarks(&timetable, &opt);
+        Some(QueriesResult::run(
+            &timetable,
+            &queries,
+            Some(opt.seed),
+            landmarks.as_ref(),
+        ))
+    } else {
+        None
+    };
+    #[cfg(feature = "profiling")]
+    write_flamegraph(&guard, "flamegraph.svg");
+    let total_ms = milliseconds(now.elapsed());
+    let memory = MemoryUsage::new(&timetable, &profiles);
 
-    println!(
-        "Benchmark done. Computed in {} ms and {} runs",
-        Utc::now().signed_duration_since(now).num_milliseconds(),
-        runs * stop_areas.len()
-    );
+    if opt.json {
+        let result = BenchmarkResult {
+            first_day: opt.first_day,
+            horizon: opt.horizon,
+            stops: timetable.stops.len(),
+            connections: timetable.connections.len(),
+            conversion_ms: timetable.transform_duration,
+            total_ms,
+            stop_areas: results,
+            random_queries,
+            memory,
+        };
+        println!("{}", serde_json::to_string(&result).unwrap());
+    } else {
+        println!(
+            "Benchmark done. Computed in {:.0} ms for {} stop areas",
+            total_ms,
+            stop_areas.len()
+        );
+        if let Some(random) = &random_queries {
+            random.print(&format!("random (seed {})", opt.seed));
+        }
+        memory.print();
+    }
 }
@@ -0,0 +1,242 @@
+use csa::query::{Algorithm, Query};
+use csa::*;
+use serde::Serialize;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use structopt::StructOpt;
+
+// Counts the allocated bytes, to measure the peak memory of every algorithm on its own
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// The bytes allocated at the peak of `f`, above what was allocated before
+fn peak_bytes<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    PEAK.store(before, Ordering::Relaxed);
+    let result = f();
+    (result, PEAK.load(Ordering::Relaxed) - before)
+}
+
+// Not a Vec for structopt, that would take the flag several times
+type Algorithms = Vec<Algorithm>;
+
+fn parse_algorithms(s: &str) -> Result<Algorithms, String> {
+    s.split(',').map(|a| a.trim().parse()).collect()
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "csa-compare",
+    about = "Runs the same queries with every algorithm, checks that they agree and compares their performance."
+)]
+struct Opt {
+    #[structopt(help = "The first day of the timetable")]
+    first_day: String,
+
+    #[structopt(
+        short = "h",
+        long = "horizon",
+        help = "How many days are loaded",
+        default_value = "1"
+    )]
+    horizon: u16,
+
+    #[structopt(
+        short = "i",
+        long = "input",
+        help = "Folder where the GTFS files are",
+        default_value = "."
+    )]
+    input: String,
+
+    #[structopt(
+        long = "random",
+        help = "How many random queries, between stop areas weighted by their connections",
+        default_value = "100"
+    )]
+    random: usize,
+
+    #[structopt(
+        long = "seed",
+        help = "The seed of the random queries, the same seed gives the same queries",
+        default_value = "1"
+    )]
+    seed: u64,
+
+    #[structopt(
+        long = "replay",
+        help = "Runs the queries logged by the server with --query-log instead of random ones"
+    )]
+    replay: Option<String>,
+
+    #[structopt(
+        long = "algorithms",
+        help = "The algorithms to compare, separated by commas, the first one is the reference",
+        default_value = "profile,earliest-arrival",
+        parse(try_from_str = parse_algorithms)
+    )]
+    algorithms: Algorithms,
+
+    #[structopt(long = "json", help = "Print the results as JSON")]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct AlgorithmResult {
+    algorithm: String,
+    journeys: usize,
+    connections_scanned: usize,
+    total_ms: f64,
+    mean_ms: f64,
+    // Allocated while answering a single query, above the loaded timetable
+    max_peak_bytes: usize,
+    mean_peak_bytes: f64,
+}
+
+#[derive(Serialize)]
+struct Mismatch {
+    origin: Vec<String>,
+    destination: Vec<String>,
+    departure: chrono::NaiveDateTime,
+    reason: String,
+}
+
+#[derive(Serialize)]
+struct ComparisonResult {
+    queries: usize,
+    algorithms: Vec<AlgorithmResult>,
+    mismatches: Vec<Mismatch>,
+}
+
+fn queries(timetable: &structures::Timetable, opt: &Opt) -> Vec<Query> {
+    match &opt.replay {
+        Some(path) => {
+            let file = std::fs::File::open(path)
+                .unwrap_or_else(|e| panic!("Could not open the query log {}: {}", path, e));
+            query_log::read_log(std::io::BufReader::new(file))
+                .unwrap_or_else(|e| panic!("Could not read the query log {}: {}", path, e))
+                .iter()
+                .filter_map(|q| q.query(timetable))
+                .collect()
+        }
+        None => sampling::QuerySampler::new(timetable, opt.seed)
+            .take(opt.random)
+            .collect(),
+    }
+}
+
+fn main() {
+    csa::cli::completions(Opt::clap());
+    let opt = Opt::from_args();
+    let algorithms = &opt.algorithms;
+    let gtfs = gtfs_structures::Gtfs::new(&opt.input).unwrap();
+    let timetable = structures::Timetable::from_gtfs(&gtfs, &opt.first_day, opt.horizon);
+    let queries = queries(&timetable, &opt);
+
+    let mut results: Vec<_> = algorithms
+        .iter()
+        .map(|algorithm| AlgorithmResult {
+            algorithm: algorithm.to_string(),
+            journeys: 0,
+            connections_scanned: 0,
+            total_ms: 0.,
+            mean_ms: 0.,
+            max_peak_bytes: 0,
+            mean_peak_bytes: 0.,
+        })
+        .collect();
+    let mut mismatches = Vec::new();
+    for query in &queries {
+        let mut outcomes = Vec::new();
+        for (&algorithm, result) in algorithms.iter().zip(&mut results) {
+            let start = Instant::now();
+            let (outcome, peak) =
+                peak_bytes(|| comparison::Outcome::run(&timetable, query, algorithm));
+            result.total_ms += query::milliseconds(start.elapsed());
+            result.journeys += outcome.journeys.len();
+            result.connections_scanned += outcome.stats.scan.connections_scanned;
+            result.max_peak_bytes = result.max_peak_bytes.max(peak);
+            result.mean_peak_bytes += peak as f64;
+            outcomes.push(outcome);
+        }
+        if let Some(reason) = comparison::mismatch(query, &outcomes) {
+            let ids = |stops: &[usize]| {
+                stops
+                    .iter()
+                    .map(|&s| timetable.stops[s].id.to_owned())
+                    .collect()
+            };
+            mismatches.push(Mismatch {
+                origin: ids(&query.origin),
+                destination: ids(&query.destination),
+                departure: query.departure_datetime(&timetable),
+                reason,
+            });
+        }
+    }
+    for result in &mut results {
+        result.mean_ms = result.total_ms / queries.len().max(1) as f64;
+        result.mean_peak_bytes /= queries.len().max(1) as f64;
+    }
+
+    let failed = !mismatches.is_empty();
+    if opt.json {
+        let result = ComparisonResult {
+            queries: queries.len(),
+            algorithms: results,
+            mismatches,
+        };
+        println!("{}", serde_json::to_string(&result).unwrap());
+    } else {
+        println!("{} queries", queries.len());
+        for result in &results {
+            println!(
+                "{}: {:.1} ms, {:.2} ms each, {} journeys, {} connections scanned, peak {:.1} kB at most and {:.1} kB on average",
+                result.algorithm,
+                result.total_ms,
+                result.mean_ms,
+                result.journeys,
+                result.connections_scanned,
+                result.max_peak_bytes as f64 / 1024.,
+                result.mean_peak_bytes / 1024.
+            );
+        }
+        for mismatch in &mismatches {
+            println!(
+                "Mismatch from {} to {} at {}: {}",
+                mismatch.origin.join(","),
+                mismatch.destination.join(","),
+                mismatch.departure,
+                mismatch.reason
+            );
+        }
+        if !failed {
+            println!("All the algorithms agree");
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
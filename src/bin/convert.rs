@@ -0,0 +1,152 @@
+use csa::structures::Timetable;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "csa-convert",
+    about = "Converts a GTFS into a binary timetable, that can be loaded by the JavaScript bindings"
+)]
+struct Opt {
+    #[structopt(help = "The first day of the timetable")]
+    first_day: String,
+
+    #[structopt(
+        short = "h",
+        long = "horizon",
+        help = "How many days are loaded",
+        default_value = "1"
+    )]
+    horizon: u16,
+
+    #[structopt(
+        short = "i",
+        long = "input",
+        help = "Folder where the GTFS files are",
+        default_value = "."
+    )]
+    input: String,
+
+    #[structopt(
+        short = "o",
+        long = "output",
+        help = "Where the binary timetable is written",
+        default_value = "timetable.bin"
+    )]
+    output: String,
+
+    #[structopt(
+        long = "cluster",
+        help = "Groups the stops without parent station with the same name closer than these meters into stop areas, for the feeds without stations"
+    )]
+    cluster: Option<f64>,
+
+    #[structopt(
+        long = "footpath-providers",
+        help = "How the footpaths are generated, combining parent (the stop areas, by default), radius:<meters> and transfers (transfers.txt), the later ones replacing the durations of the earlier ones"
+    )]
+    footpath_providers: Option<String>,
+
+    #[structopt(
+        long = "subnetwork",
+        help = "Only keeps a part of the network: bbox:<min lat>,<min lon>,<max lat>,<max lon>, routes:<ids> or agencies:<ids>, the ids separated by commas"
+    )]
+    subnetwork: Option<csa::subnetwork::Selection>,
+
+    #[structopt(
+        long = "connections",
+        help = "Also exports the connections as CSV, like the academic CSA implementations"
+    )]
+    connections: Option<String>,
+
+    #[structopt(
+        long = "gtfs",
+        help = "Also exports the processed timetable as a GTFS folder, with the exact trips and footpaths that are routed"
+    )]
+    gtfs: Option<String>,
+
+    #[structopt(
+        long = "service",
+        help = "Also exports the departures per hour, first and last departures and routes of every stop (as JSON with a .json extension, CSV otherwise)"
+    )]
+    service: Option<String>,
+
+    #[structopt(
+        long = "service-day",
+        help = "The day of the service statistics, defaults to the first day"
+    )]
+    service_day: Option<String>,
+
+    #[cfg(feature = "osm")]
+    #[structopt(
+        long = "osm",
+        help = "An .osm.pbf extract to compute the walking durations between nearby stops"
+    )]
+    osm: Option<String>,
+}
+
+fn main() {
+    csa::cli::completions(Opt::clap());
+    let opt = Opt::from_args();
+    let gtfs = gtfs_structures::Gtfs::new(&opt.input).unwrap();
+    let continuous = csa::gtfs::ContinuousStops::read(&opt.input)
+        .unwrap_or_else(|e| panic!("Could not read the continuous stopping fields: {}", e));
+    let mut timetable =
+        Timetable::from_gtfs_with_continuous(&gtfs, &continuous, &opt.first_day, opt.horizon);
+    timetable
+        .read_translations(&opt.input)
+        .unwrap_or_else(|e| panic!("Could not read the translations: {}", e));
+    timetable
+        .read_fare_zones(&opt.input)
+        .unwrap_or_else(|e| panic!("Could not read the fare zones: {}", e));
+    timetable
+        .read_blocks(&opt.input)
+        .unwrap_or_else(|e| panic!("Could not read the blocks: {}", e));
+    if let Some(radius) = opt.cluster {
+        let areas = timetable.cluster_stop_areas(radius);
+        println!("{} stop areas created by clustering", areas);
+    }
+    if let Some(spec) = &opt.footpath_providers {
+        let providers = csa::gtfs::footpath_providers(spec, &opt.input)
+            .unwrap_or_else(|e| panic!("Invalid footpath providers: {}", e));
+        let providers: Vec<_> = providers.iter().map(|p| p.as_ref()).collect();
+        timetable.set_footpaths(&providers);
+    }
+    #[cfg(feature = "osm")]
+    {
+        if let Some(osm) = &opt.osm {
+            let graph =
+                csa::osm::WalkingGraph::from_pbf(osm).expect("Could not read the OSM extract");
+            timetable.set_osm_footpaths(&graph, &csa::osm::WalkingOptions::default());
+        }
+    }
+    if let Some(selection) = &opt.subnetwork {
+        timetable = timetable.filter(selection);
+    }
+    timetable.print_stats();
+    std::fs::write(&opt.output, timetable.to_bytes()).expect("Could not write the timetable");
+    if let Some(path) = opt.connections {
+        timetable
+            .export_connections(path)
+            .expect("Could not write the connections");
+    }
+    if let Some(path) = opt.gtfs {
+        timetable
+            .write_gtfs(path)
+            .expect("Could not write the GTFS");
+    }
+    if let Some(path) = opt.service {
+        let day = opt.service_day.as_ref().unwrap_or(&opt.first_day);
+        let day = day
+            .parse::<chrono::NaiveDate>()
+            .unwrap_or_else(|e| panic!("Invalid service day {}: {}", day, e));
+        let services = csa::analysis::stop_services(&timetable, day)
+            .unwrap_or_else(|| panic!("The service day {} is before the first day", day));
+        let file = std::fs::File::create(&path).expect("Could not create the service file");
+        if path.ends_with(".json") {
+            csa::analysis::write_stop_services_json(&services, file)
+        } else {
+            csa::analysis::write_stop_services_csv(&services, file)
+        }
+        .expect("Could not write the service statistics");
+    }
+}
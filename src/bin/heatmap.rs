@@ -0,0 +1,72 @@
+use csa::structures::Timetable;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "csa-heatmap",
+    about = "Writes the travel time from every stop to a stop area, to be loaded in QGIS or kepler.gl"
+)]
+struct Opt {
+    #[structopt(help = "The first day of the timetable")]
+    first_day: String,
+
+    #[structopt(help = "The destination stop area or stop")]
+    to: String,
+
+    #[structopt(
+        short = "h",
+        long = "horizon",
+        help = "How many days are loaded",
+        default_value = "1"
+    )]
+    horizon: u16,
+
+    #[structopt(
+        short = "i",
+        long = "input",
+        help = "Folder where the GTFS files are",
+        default_value = "."
+    )]
+    input: String,
+
+    #[structopt(
+        short = "t",
+        long = "time",
+        help = "The departure time on the first day, as HH:MM",
+        default_value = "08:00"
+    )]
+    time: String,
+
+    #[structopt(
+        short = "o",
+        long = "output",
+        help = "Where the stop_id,lat,lon,minutes CSV is written",
+        default_value = "heatmap.csv"
+    )]
+    output: String,
+}
+
+fn main() {
+    csa::cli::completions(Opt::clap());
+    let opt = Opt::from_args();
+    let gtfs = gtfs_structures::Gtfs::new(&opt.input).unwrap();
+    let continuous = csa::gtfs::ContinuousStops::read(&opt.input)
+        .unwrap_or_else(|e| panic!("Could not read the continuous stopping fields: {}", e));
+    let timetable =
+        Timetable::from_gtfs_with_continuous(&gtfs, &continuous, &opt.first_day, opt.horizon);
+
+    let destinations = timetable.stop_indices(&opt.to);
+    if destinations.is_empty() {
+        panic!("Unknown stop or stop area {}", opt.to);
+    }
+    let time = chrono::NaiveTime::parse_from_str(&opt.time, "%H:%M")
+        .unwrap_or_else(|e| panic!("Invalid time {}: {}", opt.time, e));
+    let departure = timetable
+        .time(timetable.start_date.and_time(time))
+        .expect("The departure is before the timetable");
+
+    let travel_times = csa::analysis::travel_times(&timetable, &destinations, departure);
+    let file = std::fs::File::create(&opt.output).expect("Could not create the heatmap file");
+    csa::analysis::write_heatmap_csv(&timetable, &travel_times, file)
+        .expect("Could not write the heatmap");
+}
@@ -1,17 +1,32 @@
-use actix_web::{web, App, HttpRequest, HttpServer, Responder};
-use csa::structures::Timetable;
-use serde::Serialize;
+use actix_web::dev::Service;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use csa::algo::Profile;
+use csa::analytics::Analytics;
+use csa::formats::navitia::{NavitiaError, NavitiaJourney};
+use csa::formats::protobuf;
+use csa::journey::{Journey, Leg, LegKind};
+use csa::poi::DestinationSets;
+use csa::query::{milliseconds, Algorithm, QueryError, Stats};
+use csa::query_log::QueryLog;
+use csa::realtime::{Realtime, TimetableView};
+use csa::structures::{LocationType, Network, Timetable};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(name = "csa-server", about = "Runs a web server to request routes")]
 struct Opt {
-    #[structopt(help = "The first day of the timetable")]
+    #[structopt(help = "The first day of the timetable", env = "CSA_FIRST_DAY")]
     first_day: String,
 
     #[structopt(
         short = "h",
         long = "horizon",
+        env = "CSA_HORIZON",
         help = "How many days are loaded",
         default_value = "1"
     )]
@@ -20,75 +35,1032 @@ struct Opt {
     #[structopt(
         short = "i",
         long = "input",
+        env = "CSA_INPUT",
         help = "Folder where the GTFS files are",
         default_value = "."
     )]
     input: String,
+
+    #[cfg(feature = "netex")]
+    #[structopt(
+        long = "netex",
+        help = "The input is a NeTEx file or folder, or CSA_NETEX=true"
+    )]
+    netex: bool,
+
+    #[structopt(
+        long = "connections",
+        help = "The input is a connection CSV (dep_stop,arr_stop,dep_time,arr_time,trip), or CSA_CONNECTIONS=true"
+    )]
+    connections: bool,
+
+    #[structopt(
+        long = "footpaths",
+        env = "CSA_FOOTPATHS",
+        help = "With --connections, a CSV of the footpaths (from_stop,to_stop,duration)"
+    )]
+    footpaths: Option<String>,
+
+    #[structopt(
+        long = "cluster",
+        env = "CSA_CLUSTER",
+        help = "Groups the stops without parent station with the same name closer than these meters into stop areas, for the feeds without stations"
+    )]
+    cluster: Option<f64>,
+
+    #[structopt(
+        long = "footpath-providers",
+        env = "CSA_FOOTPATH_PROVIDERS",
+        help = "How the footpaths are generated, combining parent (the stop areas, by default), radius:<meters> and transfers (transfers.txt), the later ones replacing the durations of the earlier ones"
+    )]
+    footpath_providers: Option<String>,
+
+    #[structopt(
+        long = "subnetwork",
+        env = "CSA_SUBNETWORK",
+        help = "Only keeps a part of the network: bbox:<min lat>,<min lon>,<max lat>,<max lon>, routes:<ids> or agencies:<ids>, the ids separated by commas"
+    )]
+    subnetwork: Option<csa::subnetwork::Selection>,
+
+    #[structopt(
+        long = "algorithm",
+        env = "CSA_ALGORITHM",
+        help = "How the journeys are computed when the request does not say: profile (the next optimal journeys) or earliest-arrival (a single journey, much faster)",
+        default_value = "profile"
+    )]
+    algorithm: Algorithm,
+
+    #[structopt(
+        long = "query-log",
+        env = "CSA_QUERY_LOG",
+        help = "Appends the valid /journeys and /plan queries to this file, without who made them, to replay them with the benchmark"
+    )]
+    query_log: Option<String>,
+
+    #[structopt(
+        long = "realtime-snapshot",
+        env = "CSA_REALTIME_SNAPSHOT",
+        help = "Where the realtime delays and cancellations are saved, and restored from at startup"
+    )]
+    realtime_snapshot: Option<String>,
+
+    #[structopt(
+        long = "snapshot-interval",
+        env = "CSA_SNAPSHOT_INTERVAL",
+        help = "How many seconds between two realtime snapshots",
+        default_value = "60"
+    )]
+    snapshot_interval: u64,
+
+    #[structopt(
+        long = "shutdown-timeout",
+        env = "CSA_SHUTDOWN_TIMEOUT",
+        help = "How many seconds the running requests have to finish after SIGTERM or SIGINT",
+        default_value = "30"
+    )]
+    shutdown_timeout: u64,
+
+    #[structopt(
+        long = "stats-token",
+        env = "CSA_STATS_TOKEN",
+        help = "Records the usage of the server and serves it on /stats to the requests with the header Authorization: Bearer <token>, that the realtime updates and the changes of the destination sets also require"
+    )]
+    stats_token: Option<String>,
+
+    #[structopt(
+        long = "bind",
+        env = "CSA_BIND",
+        help = "The address and port the server listens to",
+        default_value = "127.0.0.1:8000"
+    )]
+    bind: String,
+}
+
+// A first day outside of the feed loads an empty timetable, that would never find a journey
+fn timetable(opt: &Opt) -> Timetable {
+    let mut timetable = load(opt);
+    if let Some(selection) = &opt.subnetwork {
+        timetable = timetable.filter(selection);
+    }
+    if timetable.connections.is_empty() {
+        panic!(
+            "No trip runs during the {} days from {}, is the first day within the feed?",
+            opt.horizon, opt.first_day
+        );
+    }
+    timetable
+}
+
+fn load(opt: &Opt) -> Timetable {
+    #[cfg(feature = "netex")]
+    {
+        if opt.netex {
+            return Timetable::from_netex(&opt.input, &opt.first_day, opt.horizon)
+                .unwrap_or_else(|e| panic!("{}", e));
+        }
+    }
+    if opt.connections {
+        let footpaths = opt.footpaths.as_ref().map(std::path::Path::new);
+        return Timetable::from_connections_csv(&opt.input, footpaths)
+            .unwrap_or_else(|e| panic!("Could not read the connections: {}", e));
+    }
+    let gtfs = gtfs_structures::Gtfs::new(&opt.input).unwrap();
+    gtfs.print_stats();
+    let continuous = csa::gtfs::ContinuousStops::read(&opt.input)
+        .unwrap_or_else(|e| panic!("Could not read the continuous stopping fields: {}", e));
+    let mut timetable =
+        Timetable::from_gtfs_with_continuous(&gtfs, &continuous, &opt.first_day, opt.horizon);
+    timetable
+        .read_translations(&opt.input)
+        .unwrap_or_else(|e| panic!("Could not read the translations: {}", e));
+    timetable
+        .read_fare_zones(&opt.input)
+        .unwrap_or_else(|e| panic!("Could not read the fare zones: {}", e));
+    timetable
+        .read_blocks(&opt.input)
+        .unwrap_or_else(|e| panic!("Could not read the blocks: {}", e));
+    if let Some(radius) = opt.cluster {
+        let areas = timetable.cluster_stop_areas(radius);
+        println!("{} stop areas created by clustering", areas);
+    }
+    if let Some(spec) = &opt.footpath_providers {
+        let providers = csa::gtfs::footpath_providers(spec, &opt.input)
+            .unwrap_or_else(|e| panic!("Invalid footpath providers: {}", e));
+        let providers: Vec<_> = providers.iter().map(|p| p.as_ref()).collect();
+        timetable.set_footpaths(&providers);
+    }
+    timetable
+}
+
+// The realtime view answering the queries, replaced at every update while the base is shared
+struct Live {
+    base: Arc<Timetable>,
+    view: RwLock<Arc<TimetableView>>,
+    // By trip id and day
+    trips: HashMap<(String, u16), usize>,
+    // For the requests without an algorithm parameter
+    algorithm: Algorithm,
+    query_log: Option<QueryLog>,
+    // Registered by the clients, like all the hospitals
+    destinations: RwLock<DestinationSets>,
+    // Only recorded with a token to read them
+    analytics: Option<Mutex<Analytics>>,
+    stats_token: Option<String>,
+}
+
+impl Live {
+    fn new(
+        base: Timetable,
+        realtime: Realtime,
+        algorithm: Algorithm,
+        query_log: Option<QueryLog>,
+    ) -> Self {
+        let base = Arc::new(base);
+        let trips = base
+            .trips
+            .iter()
+            .enumerate()
+            .map(|(index, trip)| ((trip.id.to_owned(), trip.day), index))
+            .collect();
+        let view = TimetableView::new(base.clone(), realtime);
+        Self {
+            base,
+            view: RwLock::new(Arc::new(view)),
+            trips,
+            algorithm,
+            query_log,
+            destinations: RwLock::new(DestinationSets::new()),
+            analytics: None,
+            stats_token: None,
+        }
+    }
+
+    fn view(&self) -> Arc<TimetableView> {
+        self.view.read().expect("Poisoned realtime view").clone()
+    }
+
+    fn with_analytics(mut self, token: Option<String>) -> Self {
+        self.analytics = token.as_ref().map(|_| Mutex::new(Analytics::new()));
+        self.stats_token = token;
+        self
+    }
+
+    // The queries are only parsed for the query log and the analytics
+    fn records_queries(&self) -> bool {
+        self.query_log.is_some() || self.analytics.is_some()
+    }
+
+    // A query log that can not be written does not prevent answering
+    fn log(&self, query: &csa::query::Query, destination: &str) {
+        if let Some(log) = &self.query_log {
+            if let Err(e) = log.log(&self.base, query) {
+                eprintln!("Could not log the query: {}", e);
+            }
+        }
+        if let Some(analytics) = &self.analytics {
+            analytics
+                .lock()
+                .expect("Poisoned analytics")
+                .record_destination(destination);
+        }
+    }
+
+    fn record_request(&self, endpoint: &str, status: u16, latency: Duration) {
+        if let Some(analytics) = &self.analytics {
+            analytics
+                .lock()
+                .expect("Poisoned analytics")
+                .record_request(endpoint, status, latency);
+        }
+    }
+}
+
+// The analytics are grouped by route, whatever the stop area or set in the path
+const ENDPOINTS: [&str; 9] = [
+    "/to/{stop_area}",
+    "/plan",
+    "/stops",
+    "/journeys",
+    "/realtime",
+    "/destinations",
+    "/destinations/{name}",
+    "/destinations/{name}/journeys",
+    "/stats",
+];
+
+fn endpoint(path: &str) -> &'static str {
+    let segments: Vec<_> = path.trim_end_matches('/').split('/').collect();
+    ENDPOINTS
+        .iter()
+        .find(|endpoint| {
+            let pattern: Vec<_> = endpoint.split('/').collect();
+            pattern.len() == segments.len()
+                && pattern
+                    .iter()
+                    .zip(&segments)
+                    .all(|(p, s)| p == s || (p.starts_with('{') && !s.is_empty()))
+        })
+        .copied()
+        .unwrap_or("unknown")
+}
+
+// Saves the realtime state when it changed, so that a restart does not lose it
+// Shared by the periodic saves and the one at shutdown, that never write at the same time
+struct Snapshots {
+    path: String,
+    // The last saved state
+    saved: Mutex<Realtime>,
+}
+
+impl Snapshots {
+    fn new(live: &Live, path: String) -> Self {
+        Self {
+            path,
+            saved: Mutex::new(live.view().realtime().clone()),
+        }
+    }
+
+    fn save(&self, live: &Live) {
+        let mut saved = self.saved.lock().expect("Poisoned realtime snapshot");
+        let view = live.view();
+        if view.realtime() != &*saved {
+            match view.realtime().save(&live.base, &self.path) {
+                Ok(()) => *saved = view.realtime().clone(),
+                Err(e) => eprintln!("Could not save the realtime snapshot {}: {}", self.path, e),
+            }
+        }
+    }
+}
+
+fn save_snapshots(live: web::Data<Live>, snapshots: Arc<Snapshots>, interval: Duration) {
+    loop {
+        std::thread::sleep(interval);
+        snapshots.save(&live);
+    }
+}
+
+// Stops the server gracefully on SIGTERM or SIGINT (Ctrl-C): no new connection is accepted and the
+// running requests have `--shutdown-timeout` seconds to finish
+fn stop_on_signals(server: actix_web::dev::Server) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use actix_rt::signal::unix::{signal, SignalKind};
+        for kind in [SignalKind::terminate(), SignalKind::interrupt()] {
+            let mut signals = signal(kind)?;
+            let server = server.clone();
+            actix_rt::spawn(async move {
+                if signals.recv().await.is_some() {
+                    server.stop(true).await;
+                }
+            });
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        actix_rt::spawn(async move {
+            if actix_rt::signal::ctrl_c().await.is_ok() {
+                server.stop(true).await;
+            }
+        });
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct SummaryLeg {
+    // Either "vehicle" or "walk"
+    mode: &'static str,
+    // The stop ids
+    from: String,
+    to: String,
+    departure: chrono::NaiveDateTime,
+    arrival: chrono::NaiveDateTime,
+    // Only for the vehicle legs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trip_id: Option<String>,
+}
+
+impl SummaryLeg {
+    fn from(leg: &Leg, timetable: &Timetable) -> Self {
+        let trip_id = match leg.kind {
+            LegKind::Vehicle { trip } => Some(timetable.trips[trip].id.to_owned()),
+            LegKind::Walk => None,
+        };
+        Self {
+            mode: if leg.is_walk() { "walk" } else { "vehicle" },
+            from: timetable.stops[leg.from].id.to_owned(),
+            to: timetable.stops[leg.to].id.to_owned(),
+            departure: timetable.datetime(leg.dep_time),
+            arrival: timetable.datetime(leg.arr_time),
+            trip_id,
+        }
+    }
 }
 
 #[derive(Serialize)]
 struct Summary {
     departure: chrono::NaiveDateTime,
     arrival: chrono::NaiveDateTime,
+    // Staying seated when the vehicle continues as another trip is not a transfer
     transfers: usize,
+    // The stop id of the reached destination
+    destination: Option<String>,
+    legs: Vec<SummaryLeg>,
 }
 
 impl Summary {
-    fn from(
-        connections: &[&csa::structures::Connection],
-        timetable: &csa::structures::Timetable,
-    ) -> Self {
-        let departure = connections.first().expect("Missing departure in connexion");
-        let arrival = connections.last().expect("Missing arrival in connexion");
-        let trips: std::collections::HashSet<_> = connections.iter().map(|c| c.trip).collect();
-        let dep_time = chrono::NaiveTime::from_hms(0, 0, 0)
-            + chrono::Duration::seconds(departure.dep_time as i64); //chrono::NaiveTime::from_num_seconds_from_midnight(departure.dep_time, 0);
-        let arr_time = chrono::NaiveTime::from_hms(0, 0, 0)
-            + chrono::Duration::seconds(arrival.arr_time as i64);
-
+    // The times of the profile include the walk to the first connection
+    fn from(journey: &Journey, profile: &Profile, timetable: &Timetable) -> Self {
         Self {
-            departure: timetable.start_date.and_time(dep_time),
-            arrival: timetable.start_date.and_time(arr_time),
-            transfers: trips.len(),
+            departure: timetable.datetime(profile.dep_time),
+            arrival: timetable.datetime(profile.arr_time),
+            transfers: journey.transfers(),
+            destination: profile
+                .destination
+                .map(|stop| timetable.stops[stop].id.to_owned()),
+            legs: journey
+                .legs
+                .iter()
+                .map(|leg| SummaryLeg::from(leg, timetable))
+                .collect(),
         }
     }
 }
 
-async fn compute(req: HttpRequest, timetable: web::Data<Timetable>) -> impl Responder {
-    // Chatelet les halles
+// The merged profiles of all the stops of a stop area
+#[derive(Serialize)]
+struct StopArea {
+    name: String,
+    profiles: Vec<Summary>,
+}
+
+// The stops of every stop area, by stop area id
+// The stops without parent station are their own stop area
+fn stop_area_stops(timetable: &Timetable) -> BTreeMap<String, Vec<usize>> {
+    let mut stops: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (index, stop) in timetable.stops.iter().enumerate() {
+        let area = stop.parent_station.as_deref().unwrap_or(&stop.id);
+        stops.entry(area.to_owned()).or_default().push(index);
+    }
+    stops
+}
+
+fn stop_area<N: Network>(
+    network: &N,
+    profiles: &[Vec<Profile>],
+    destinations: &[usize],
+    area: &str,
+    indices: &[usize],
+) -> StopArea {
+    let timetable = network.timetable();
+    let name = indices
+        .iter()
+        .map(|&i| &timetable.stops[i])
+        .find(|stop| stop.id == area)
+        .unwrap_or(&timetable.stops[indices[0]])
+        .name
+        .to_owned();
+    let summaries = csa::algo::merge_profiles(indices.iter().map(|&i| &profiles[i]))
+        .iter()
+        .map(|profile| {
+            // The stop of the area where the merged profile comes from
+            let origin = indices
+                .iter()
+                .copied()
+                .find(|&i| {
+                    profiles[i].iter().any(|p| {
+                        p.out_connection == profile.out_connection && p.dep_time == profile.dep_time
+                    })
+                })
+                .unwrap_or(indices[0]);
+            let route = profile.route(profiles, network);
+            let journey = Journey::new(timetable, origin, &route, destinations);
+            Summary::from(&journey, profile, timetable)
+        })
+        .collect();
+    StopArea {
+        name,
+        profiles: summaries,
+    }
+}
+
+// Keyed by stop area id
+fn stop_areas<N: Network>(
+    network: &N,
+    profiles: &[Vec<Profile>],
+    destinations: &[usize],
+) -> BTreeMap<String, StopArea> {
+    stop_area_stops(network.timetable())
+        .into_iter()
+        .map(|(area, indices)| {
+            let stop_area = stop_area(network, profiles, destinations, &area, &indices);
+            (area, stop_area)
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct StopAreaRecord<'a> {
+    stop_area: &'a str,
+    #[serde(flatten)]
+    profiles: StopArea,
+}
+
+// One JSON line by stop area, serialized when the client reads it: only the profiles are kept in
+// memory, not the whole response
+fn ndjson_stop_areas(
+    view: Arc<TimetableView>,
+    profiles: Vec<Vec<Profile>>,
+    destinations: Vec<usize>,
+) -> impl futures::Stream<Item = Result<web::Bytes, actix_web::Error>> {
+    let areas = stop_area_stops(view.timetable());
+    futures::stream::iter(areas.into_iter().map(move |(area, indices)| {
+        let record = StopAreaRecord {
+            stop_area: &area,
+            profiles: stop_area(view.as_ref(), &profiles, &destinations, &area, &indices),
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        Ok(web::Bytes::from(line))
+    }))
+}
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+fn accepts_ndjson(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(actix_web::http::header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains(NDJSON_CONTENT_TYPE))
+        .unwrap_or(false)
+}
+
+fn accepts_protobuf(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(actix_web::http::header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains(protobuf::CONTENT_TYPE))
+        .unwrap_or(false)
+}
+
+// The languages accepted by the client, when the request does not set one
+fn accept_language(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(actix_web::http::header::ACCEPT_LANGUAGE)
+        .and_then(|accept| accept.to_str().ok())
+        .map(str::to_owned)
+}
+
+#[derive(Deserialize)]
+struct ProfilesRequest {
+    // Adds the statistics of the query to the response
+    debug: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct ProfilesError {
+    error: QueryError,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct DebugProfiles {
+    profiles: BTreeMap<String, StopArea>,
+    debug: Stats,
+}
+
+async fn compute(
+    req: HttpRequest,
+    params: web::Query<ProfilesRequest>,
+    live: web::Data<Live>,
+) -> HttpResponse {
+    let view = live.view();
+    let timetable = view.timetable();
+    // Either a stop area or a single stop, Chatelet les halles by default
     let stop_area = req
         .match_info()
         .get("stop_area")
         .unwrap_or("StopArea:8775860");
 
-    let to = timetable.stop_index_by_stop_area_id(stop_area);
-    let result = csa::algo::compute(&timetable, &to);
-    let mut output = Vec::<Vec<_>>::new();
+    let to = timetable.stop_indices(stop_area);
+    if to.is_empty() {
+        return HttpResponse::NotFound().json(ProfilesError {
+            error: QueryError::NoDestination,
+            message: format!("Unknown stop area or stop {}", stop_area),
+        });
+    }
+    if let Some(analytics) = &live.analytics {
+        analytics
+            .lock()
+            .expect("Poisoned analytics")
+            .record_destination(stop_area);
+    }
+    let start = Instant::now();
+    let (result, scan) =
+        csa::algo::compute_with_stats(view.as_ref(), &to, 0, &csa::query::QueryOptions::default());
+    let scan_ms = milliseconds(start.elapsed());
+    if accepts_protobuf(&req) {
+        return HttpResponse::Ok()
+            .content_type(protobuf::CONTENT_TYPE)
+            .body(protobuf::profiles(timetable, &result));
+    }
 
-    for i in 0..timetable.stops.len() {
-        let routes = result[i]
-            .iter()
-            .map(|profile| Summary::from(&profile.route(result.as_slice(), &timetable), &timetable))
-            .collect();
-        output.push(routes);
+    if accepts_ndjson(&req) {
+        return HttpResponse::Ok()
+            .content_type(NDJSON_CONTENT_TYPE)
+            .streaming(ndjson_stop_areas(view, result, to));
+    }
+
+    let start = Instant::now();
+    let output = stop_areas(view.as_ref(), &result, &to);
+    if params.debug == Some(true) {
+        let debug = Stats {
+            scan,
+            scan_ms,
+            reconstruction_ms: milliseconds(start.elapsed()),
+        };
+        return HttpResponse::Ok().json(DebugProfiles {
+            profiles: output,
+            debug,
+        });
+    }
+    HttpResponse::Ok().json(output)
+}
+
+async fn navitia_journeys(
+    req: HttpRequest,
+    params: web::Query<csa::formats::navitia::JourneysRequest>,
+    live: web::Data<Live>,
+) -> HttpResponse {
+    let view = live.view();
+    let timetable = view.timetable();
+    let mut params = params.into_inner();
+    if params.lang.is_none() {
+        params.lang = accept_language(&req);
+    }
+    if params.algorithm.is_none() {
+        params.algorithm = Some(live.algorithm.to_string());
+    }
+    let query = match csa::formats::navitia::query(timetable, &params) {
+        Ok(query) => query,
+        Err(error) if accepts_protobuf(&req) => return HttpResponse::BadRequest().json(error),
+        Err(error) => {
+            return HttpResponse::Ok().json(csa::formats::navitia::JourneysResponse::invalid(error))
+        }
+    };
+    if live.records_queries() {
+        live.log(&query, &params.to);
+    }
+    if accepts_protobuf(&req) {
+        return HttpResponse::Ok()
+            .content_type(protobuf::CONTENT_TYPE)
+            .body(protobuf::journeys(
+                timetable,
+                &query.journeys(view.as_ref()),
+            ));
+    }
+    HttpResponse::Ok().json(csa::formats::navitia::journeys_of_query(
+        view.as_ref(),
+        &params,
+        &query,
+    ))
+}
+
+async fn plan(
+    req: HttpRequest,
+    params: web::Query<csa::formats::otp::PlanRequest>,
+    live: web::Data<Live>,
+) -> impl Responder {
+    let mut params = params.into_inner();
+    if params.locale.is_none() {
+        params.locale = accept_language(&req);
     }
-    serde_json::to_string(&output)
+    if params.algorithm.is_none() {
+        params.algorithm = Some(live.algorithm.to_string());
+    }
+    let view = live.view();
+    let response = match csa::formats::otp::query(view.timetable(), &params) {
+        Ok(query) => {
+            if live.records_queries() {
+                live.log(&query, &params.to_place);
+            }
+            csa::formats::otp::plan_of_query(view.as_ref(), &params, &query)
+        }
+        Err(error) => csa::formats::otp::PlanResponse::invalid(error),
+    };
+    web::Json(response)
+}
+
+#[derive(Deserialize)]
+struct TripUpdate {
+    trip_id: String,
+    // The day the trip runs
+    date: chrono::NaiveDate,
+    // In seconds, negative when running early
+    delay: Option<i32>,
+    cancelled: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct UpdateResult {
+    applied: usize,
+    // The trip ids that do not run on that date
+    unknown: Vec<String>,
+}
+
+// The updates are added to the current realtime state
+async fn update_realtime(
+    req: HttpRequest,
+    updates: web::Json<Vec<TripUpdate>>,
+    live: web::Data<Live>,
+) -> HttpResponse {
+    if !authorized(&req, &live) {
+        return unauthorized();
+    }
+    let mut view = live.view.write().expect("Poisoned realtime view");
+    let mut realtime = view.realtime().clone();
+    let mut result = UpdateResult {
+        applied: 0,
+        unknown: Vec::new(),
+    };
+    for update in updates.iter() {
+        let day = (update.date - live.base.start_date).num_days();
+        let trip = u16::try_from(day)
+            .ok()
+            .and_then(|day| live.trips.get(&(update.trip_id.to_owned(), day)));
+        let trip = match trip {
+            Some(&trip) => trip,
+            None => {
+                result.unknown.push(update.trip_id.to_owned());
+                continue;
+            }
+        };
+        if let Some(delay) = update.delay {
+            realtime.delay(trip, delay);
+        }
+        match update.cancelled {
+            Some(true) => realtime.cancel(trip),
+            Some(false) => {
+                realtime.cancellations.remove(&trip);
+            }
+            None => (),
+        }
+        result.applied += 1;
+    }
+    *view = Arc::new(TimetableView::new(live.base.clone(), realtime));
+    HttpResponse::Ok().json(result)
+}
+
+// Back to the static schedule
+async fn reset_realtime(req: HttpRequest, live: web::Data<Live>) -> HttpResponse {
+    if !authorized(&req, &live) {
+        return unauthorized();
+    }
+    let view = TimetableView::new(live.base.clone(), Realtime::default());
+    *live.view.write().expect("Poisoned realtime view") = Arc::new(view);
+    HttpResponse::NoContent().finish()
+}
+
+// The page size when the request does not set a limit, and its maximum
+const DEFAULT_STOPS_LIMIT: usize = 1000;
+const MAX_STOPS_LIMIT: usize = 10_000;
+
+#[derive(Deserialize)]
+struct StopsRequest {
+    // The index of the first stop, in the order of the timetable
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct StopRecord<'a> {
+    id: &'a str,
+    name: &'a str,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    parent_station: Option<&'a str>,
+    location_type: LocationType,
+}
+
+#[derive(Serialize)]
+struct StopsPage<'a> {
+    // All the stops of the timetable, to know how many pages there are
+    total: usize,
+    offset: usize,
+    stops: Vec<StopRecord<'a>>,
+}
+
+// Every stop, station and entrance, to draw them on a map without reading the GTFS
+async fn stops(params: web::Query<StopsRequest>, live: web::Data<Live>) -> HttpResponse {
+    let timetable = &live.base;
+    let offset = params.offset.unwrap_or(0);
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_STOPS_LIMIT)
+        .min(MAX_STOPS_LIMIT);
+    let stops = timetable
+        .stops
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|stop| StopRecord {
+            id: &stop.id,
+            name: &stop.name,
+            latitude: stop.latitude,
+            longitude: stop.longitude,
+            parent_station: stop.parent_station.as_deref(),
+            location_type: stop.location_type,
+        })
+        .collect();
+    HttpResponse::Ok().json(StopsPage {
+        total: timetable.stops.len(),
+        offset,
+        stops,
+    })
+}
+
+#[derive(Deserialize)]
+struct StatsRequest {
+    // How many destinations are listed, 20 by default
+    top: Option<usize>,
+}
+
+// Not found when the server was started without a token
+async fn stats(
+    req: HttpRequest,
+    params: web::Query<StatsRequest>,
+    live: web::Data<Live>,
+) -> HttpResponse {
+    let analytics = match &live.analytics {
+        Some(analytics) => analytics,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    if !authorized(&req, &live) {
+        return unauthorized();
+    }
+    let report = analytics
+        .lock()
+        .expect("Poisoned analytics")
+        .report(params.top.unwrap_or(20));
+    HttpResponse::Ok().json(report)
+}
+
+// With the header Authorization: Bearer <token>, never when the server has no token
+fn authorized(req: &HttpRequest, live: &Live) -> bool {
+    let token = match &live.stats_token {
+        Some(token) => token,
+        None => return false,
+    };
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .header(actix_web::http::header::WWW_AUTHENTICATE, "Bearer")
+        .finish()
+}
+
+// Does not tell how many first bytes of the token were right by answering sooner
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn destination_sets(live: web::Data<Live>) -> HttpResponse {
+    let sets = live.destinations.read().expect("Poisoned destination sets");
+    HttpResponse::Ok().json(sets.iter().collect::<BTreeMap<_, _>>())
+}
+
+#[derive(Serialize)]
+struct UnknownMembers {
+    unknown: Vec<String>,
+}
+
+// The body lists the stop or stop area ids of the set
+async fn put_destination_set(
+    req: HttpRequest,
+    name: web::Path<String>,
+    members: web::Json<Vec<String>>,
+    live: web::Data<Live>,
+) -> HttpResponse {
+    if !authorized(&req, &live) {
+        return unauthorized();
+    }
+    let mut sets = live
+        .destinations
+        .write()
+        .expect("Poisoned destination sets");
+    match sets.insert(&live.base, &name, members.into_inner()) {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(unknown) => HttpResponse::BadRequest().json(UnknownMembers { unknown }),
+    }
+}
+
+async fn delete_destination_set(
+    req: HttpRequest,
+    name: web::Path<String>,
+    live: web::Data<Live>,
+) -> HttpResponse {
+    if !authorized(&req, &live) {
+        return unauthorized();
+    }
+    let mut sets = live
+        .destinations
+        .write()
+        .expect("Poisoned destination sets");
+    if sets.remove(&name) {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+#[derive(Serialize)]
+struct NearestJourney {
+    // The member of the set where the journey ends
+    reached: String,
+    #[serde(flatten)]
+    journey: NavitiaJourney,
+}
+
+#[derive(Serialize)]
+struct NearestResponse {
+    journeys: Vec<NearestJourney>,
+}
+
+// Like /journeys, towards any member of the set instead of `to`
+async fn nearest_destination(
+    req: HttpRequest,
+    name: web::Path<String>,
+    params: web::Query<csa::formats::navitia::JourneysRequest>,
+    live: web::Data<Live>,
+) -> HttpResponse {
+    let view = live.view();
+    let timetable = view.timetable();
+    let mut params = params.into_inner();
+    if params.lang.is_none() {
+        params.lang = accept_language(&req);
+    }
+    if params.algorithm.is_none() {
+        params.algorithm = Some(live.algorithm.to_string());
+    }
+    let sets = live.destinations.read().expect("Poisoned destination sets");
+    let stops = match sets.stops(timetable, &name) {
+        Some(stops) => stops,
+        None => {
+            return HttpResponse::NotFound().json(NavitiaError {
+                id: "unknown_object",
+                message: format!("Unknown destination set {}", name),
+            })
+        }
+    };
+    let query = match csa::formats::navitia::query_to(timetable, &params, stops) {
+        Ok(query) => query,
+        Err(error) => return HttpResponse::BadRequest().json(error),
+    };
+    live.log(&query, &format!("destinations/{}", name));
+    let language = params
+        .lang
+        .as_deref()
+        .and_then(|lang| timetable.translations.language(lang));
+    let journeys = sets
+        .nearest(view.as_ref(), &name, &query)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(journey, member)| NearestJourney {
+            reached: member.to_owned(),
+            journey: csa::formats::navitia::journey(timetable, &journey, query.departure, language),
+        })
+        .collect();
+    HttpResponse::Ok().json(NearestResponse { journeys })
+}
+
+// 1 or true, in any case
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
 }
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    let opt = Opt::from_args();
-    let gtfs = gtfs_structures::Gtfs::new(&opt.input).unwrap();
-    gtfs.print_stats();
-    let timetable = Timetable::from_gtfs(&gtfs, &opt.first_day.clone(), opt.horizon);
-    let data = web::Data::new(timetable);
+    csa::cli::completions(Opt::clap());
+    let mut opt = Opt::from_args();
+    // structopt only reads the environment for the options with a value
+    opt.connections |= env_flag("CSA_CONNECTIONS");
+    #[cfg(feature = "netex")]
+    {
+        opt.netex |= env_flag("CSA_NETEX");
+    }
+    let timetable = timetable(&opt);
+    let realtime = match &opt.realtime_snapshot {
+        Some(path) if std::path::Path::new(path).exists() => Realtime::load(&timetable, path)
+            .unwrap_or_else(|e| panic!("Could not restore the realtime snapshot {}: {}", path, e)),
+        _ => Realtime::default(),
+    };
+    let query_log = opt.query_log.as_ref().map(|path| {
+        QueryLog::open(path)
+            .unwrap_or_else(|e| panic!("Could not open the query log {}: {}", path, e))
+    });
+    let live = Live::new(timetable, realtime, opt.algorithm, query_log)
+        .with_analytics(opt.stats_token.clone());
+    let data = web::Data::new(live);
+    let snapshots = opt.realtime_snapshot.clone().map(|path| {
+        let snapshots = Arc::new(Snapshots::new(&data, path));
+        let (live, shared) = (data.clone(), snapshots.clone());
+        let interval = Duration::from_secs(opt.snapshot_interval.max(1));
+        std::thread::spawn(move || save_snapshots(live, shared, interval));
+        snapshots
+    });
 
-    HttpServer::new(move || {
+    let live = data.clone();
+    let server = HttpServer::new(move || {
+        let live = data.clone();
         App::new()
             .app_data(data.clone())
+            .wrap_fn(move |req, service| {
+                let (live, start) = (live.clone(), Instant::now());
+                let endpoint = endpoint(req.path());
+                let response = service.call(req);
+                async move {
+                    let response = response.await;
+                    let status = match &response {
+                        Ok(response) => response.status().as_u16(),
+                        Err(_) => 500,
+                    };
+                    live.record_request(endpoint, status, start.elapsed());
+                    response
+                }
+            })
             .route("/to/{stop_area}", web::get().to(compute))
+            .route("/plan", web::get().to(plan))
+            .route("/stops", web::get().to(stops))
+            .route("/stats", web::get().to(stats))
+            .route("/journeys", web::get().to(navitia_journeys))
+            .route("/realtime", web::post().to(update_realtime))
+            .route("/realtime", web::delete().to(reset_realtime))
+            .route("/destinations", web::get().to(destination_sets))
+            .route("/destinations/{name}", web::put().to(put_destination_set))
+            .route(
+                "/destinations/{name}",
+                web::delete().to(delete_destination_set),
+            )
+            .route(
+                "/destinations/{name}/journeys",
+                web::get().to(nearest_destination),
+            )
     })
-    .bind("127.0.0.1:8000")?
-    .run()
-    .await
+    .bind(&opt.bind)?
+    .shutdown_timeout(opt.shutdown_timeout)
+    .disable_signals()
+    .run();
+    stop_on_signals(server.clone())?;
+    server.await?;
+
+    // The realtime updates received until the end are not lost
+    if let Some(snapshots) = snapshots {
+        snapshots.save(&live);
+    }
+    Ok(())
 }
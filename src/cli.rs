@@ -0,0 +1,131 @@
+// Shared by the binaries: `<binary> completions <shell>` prints the completions of bash, zsh,
+// fish, powershell or elvish, and `<binary> completions man` a man page, both generated from the
+// structopt definitions so that they follow the options
+use std::io::Write;
+use structopt::clap::{App, Shell};
+
+// Exits once printed, does nothing when the first argument is not `completions`
+pub fn completions(mut app: App<'_, '_>) {
+    let mut args = std::env::args();
+    let binary = args
+        .next()
+        .as_deref()
+        .and_then(|path| std::path::Path::new(path).file_name()?.to_str())
+        .unwrap_or_else(|| app.get_name())
+        .to_owned();
+    if args.next().as_deref() != Some("completions") {
+        return;
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    match args.next().as_deref() {
+        Some("man") => {
+            let page = man_page(app, &binary).expect("Could not generate the man page");
+            out.write_all(page.as_bytes())
+                .expect("Could not write the man page");
+        }
+        Some(shell) => match shell.parse::<Shell>() {
+            Ok(shell) => app.gen_completions_to(binary, shell, &mut out),
+            Err(valid) => {
+                eprintln!("Unknown shell {} {}", shell, valid);
+                std::process::exit(2);
+            }
+        },
+        None => {
+            eprintln!(
+                "Usage: {} completions <bash|zsh|fish|powershell|elvish|man>",
+                binary
+            );
+            std::process::exit(2);
+        }
+    }
+    std::process::exit(0);
+}
+
+// The lines starting with a dot or a quote would be read as requests
+fn escape(line: &str) -> String {
+    let line = line.replace('\\', "\\e");
+    if line.starts_with('.') || line.starts_with('\'') {
+        format!("\\&{}", line)
+    } else {
+        line
+    }
+}
+
+// The long help as roff: the sections of the help (USAGE:, OPTIONS:…) become the sections of the
+// page, their content is kept as formatted by clap
+fn man_page(app: App<'_, '_>, binary: &str) -> std::io::Result<String> {
+    let mut help = Vec::new();
+    app.set_term_width(80)
+        .write_long_help(&mut help)
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let help = String::from_utf8_lossy(&help);
+    let mut lines = help.lines();
+    // The name with the version, then the about until an empty line
+    let version = lines.next().unwrap_or_default();
+    let about = lines
+        .by_ref()
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{}\"\n.SH NAME\n{} \\- {}\n",
+        binary.to_uppercase(),
+        escape(version),
+        binary,
+        escape(&about)
+    );
+    let mut preformatted = false;
+    for line in lines.map(str::trim_end) {
+        match line.strip_suffix(':') {
+            Some(section) if !section.starts_with(' ') && !section.is_empty() => {
+                if preformatted {
+                    page.push_str(".fi\n");
+                }
+                page.push_str(&format!(".SH {}\n.nf\n", section));
+                preformatted = true;
+            }
+            _ if preformatted => {
+                page.push_str(&escape(line));
+                page.push('\n');
+            }
+            _ => (),
+        }
+    }
+    if preformatted {
+        page.push_str(".fi\n");
+    }
+    Ok(page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::clap::Arg;
+
+    #[test]
+    fn man() {
+        let app = App::new("csa-test")
+            .version("1.0")
+            .about("Tests the man page")
+            .arg(Arg::with_name("first_day").help("The first day of the timetable"))
+            .arg(
+                Arg::with_name("input")
+                    .long("input")
+                    .takes_value(true)
+                    .help(".gtfs folder with \\ in it"),
+            );
+        let page = man_page(app, "test").unwrap();
+        assert!(page.starts_with(
+            ".TH TEST 1 \"\" \"csa-test 1.0\"\n.SH NAME\ntest \\- Tests the man page\n"
+        ));
+        assert!(page.contains(".SH USAGE\n.nf\n"));
+        assert!(page.contains(".SH ARGS\n"));
+        assert!(page.contains("    <first_day>\n            The first day of the timetable\n"));
+        assert!(page.contains("\\e in it"));
+        assert!(page.ends_with(".fi\n"));
+        assert_eq!(page.matches(".nf").count(), page.matches(".fi").count());
+    }
+}
@@ -0,0 +1,157 @@
+// Synthesizes the stop areas of the feeds without parent_station, so that transfers between
+// the platforms of a same place get footpaths
+use crate::footpaths::ParentStationProvider;
+use crate::geo::distance;
+use crate::structures::{LocationType, Stop, Timetable};
+use std::collections::{HashMap, HashSet};
+
+// Lowercase, the punctuation and repeated spaces are ignored: "Gare de l'Est" is "gare de l est"
+fn normalize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .flat_map(char::to_lowercase)
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn coord(stop: &Stop) -> Option<(f64, f64)> {
+    Some((stop.latitude?, stop.longitude?))
+}
+
+// Every group of stops with the same name, each stop closer than `radius` meters to another one
+fn clusters(stops: &[Stop], radius: f64) -> Vec<Vec<usize>> {
+    let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+    for (index, stop) in stops.iter().enumerate() {
+        if stop.parent_station.is_none()
+            && stop.location_type == LocationType::StopPoint
+            && coord(stop).is_some()
+        {
+            by_name
+                .entry(normalize(&stop.name))
+                .or_default()
+                .push(index);
+        }
+    }
+
+    let mut result = Vec::new();
+    for (_, indices) in by_name {
+        let mut clusters: Vec<Vec<usize>> = Vec::new();
+        for index in indices {
+            let point = coord(&stops[index]).unwrap_or_default();
+            let close = |cluster: &Vec<usize>| {
+                cluster.iter().any(|&other| {
+                    coord(&stops[other]).is_some_and(|other| distance(point, other) <= radius)
+                })
+            };
+            // The stop can bring together clusters that were too far apart
+            let (merged, others): (Vec<_>, Vec<_>) = clusters.into_iter().partition(close);
+            let mut cluster: Vec<usize> = merged.into_iter().flatten().collect();
+            cluster.push(index);
+            clusters = others;
+            clusters.push(cluster);
+        }
+        result.extend(clusters.into_iter().filter(|c| c.len() > 1));
+    }
+    // In the order of the stops, not of the hash map
+    for cluster in &mut result {
+        cluster.sort_unstable();
+    }
+    result.sort_unstable();
+    result
+}
+
+impl Timetable {
+    // The stops without parent station with the same name, closer than `radius` meters, become the
+    // children of a new stop area, placed at their centroid
+    // The footpaths of the new stop areas are added to the existing ones
+    // It returns how many stop areas were created
+    pub fn cluster_stop_areas(&mut self, radius: f64) -> usize {
+        let clusters = clusters(&self.stops, radius);
+        let mut ids: HashSet<String> = self.stops.iter().map(|s| s.id.to_owned()).collect();
+        for cluster in &clusters {
+            let first = &self.stops[cluster[0]];
+            let mut id = format!("cluster:{}", first.id);
+            while ids.contains(&id) {
+                id.push('_');
+            }
+            let coords: Vec<_> = cluster
+                .iter()
+                .filter_map(|&i| coord(&self.stops[i]))
+                .collect();
+            let count = coords.len() as f64;
+            let area = Stop {
+                id: id.to_owned(),
+                name: first.name.to_owned(),
+                location_type: LocationType::StopArea,
+                latitude: Some(coords.iter().map(|c| c.0).sum::<f64>() / count),
+                longitude: Some(coords.iter().map(|c| c.1).sum::<f64>() / count),
+                ..Default::default()
+            };
+            for &stop in cluster {
+                self.stops[stop].parent_station = Some(id.to_owned());
+            }
+            self.stops.push(area);
+            self.footpaths.push(Vec::new());
+            ids.insert(id);
+        }
+
+        self.add_footpaths(&ParentStationProvider);
+        clusters.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_names() {
+        assert_eq!("gare de l est", normalize("Gare de l'Est "));
+        assert_eq!("gare de l est", normalize("GARE DE L EST"));
+        assert_eq!("république", normalize("République"));
+    }
+
+    #[test]
+    fn clustered_stops() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("c", "0:40")
+            .s("d", "0:50");
+        let mut t = b.build();
+        let places = [
+            ("Mairie", 48.85),
+            ("Gare", 48.86),
+            ("gare", 48.8605),
+            ("Mairie", 48.87),
+        ];
+        for (stop, (name, latitude)) in t.stops.iter_mut().zip(&places) {
+            stop.name = name.to_string();
+            stop.latitude = Some(*latitude);
+            stop.longitude = Some(2.35);
+        }
+        assert_eq!(
+            0,
+            crate::query::Query::new(vec![0], vec![3], 0)
+                .journeys(&t)
+                .len()
+        );
+
+        // The two Mairie are 2 km apart
+        assert_eq!(1, t.cluster_stop_areas(200.));
+        assert_eq!(5, t.stops.len());
+        assert_eq!("cluster:b", t.stops[4].id);
+        assert_eq!(LocationType::StopArea, t.stops[4].location_type);
+        assert_eq!(Some("cluster:b"), t.stops[2].parent_station.as_deref());
+        assert_eq!(None, t.stops[0].parent_station);
+        assert_eq!(vec![1, 2], t.stop_indices("cluster:b"));
+
+        let journeys = crate::query::Query::new(vec![0], vec![3], 0).journeys(&t);
+        assert_eq!(1, journeys.len());
+        assert_eq!(1, journeys[0].transfers());
+    }
+}
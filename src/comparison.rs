@@ -0,0 +1,151 @@
+// Runs the same queries with several algorithms and checks that they agree, to trust a new backend
+// They must find a journey for the same queries, and the earliest arrivals must be the same:
+// the first optimal profile arrives as early as the earliest-arrival journey
+// With penalties or a walking reluctance the algorithms do not minimize the same cost, only the
+// reachability is compared
+use crate::journey::Journey;
+use crate::query::{Algorithm, Query, Stats};
+use crate::structures::Network;
+
+pub struct Outcome {
+    pub algorithm: Algorithm,
+    pub journeys: Vec<Journey>,
+    pub stats: Stats,
+}
+
+impl Outcome {
+    pub fn run<N: Network>(network: &N, query: &Query, algorithm: Algorithm) -> Self {
+        let query = Query {
+            algorithm,
+            ..query.clone()
+        };
+        let (journeys, stats) = query.journeys_with_stats(network);
+        Self {
+            algorithm,
+            journeys,
+            stats,
+        }
+    }
+
+    fn earliest_arrival(&self) -> Option<u32> {
+        self.journeys.iter().filter_map(Journey::arr_time).min()
+    }
+}
+
+// The legs follow each other from an origin to a destination, leaving after the departure
+fn invalid_journey(query: &Query, journey: &Journey) -> Option<String> {
+    let (first, last) = (journey.legs.first()?, journey.legs.last()?);
+    if !query.origin.contains(&first.from) {
+        return Some(format!("starts at stop {}, not an origin", first.from));
+    }
+    if !query.destination.contains(&last.to) {
+        return Some(format!("ends at stop {}, not a destination", last.to));
+    }
+    if first.dep_time < query.departure {
+        return Some(format!(
+            "leaves at {}, before the departure",
+            first.dep_time
+        ));
+    }
+    journey.legs.windows(2).find_map(|legs| {
+        let (a, b) = (&legs[0], &legs[1]);
+        if a.to != b.from || a.arr_time > b.dep_time {
+            Some(format!(
+                "leg to stop {} at {} followed by a leg from stop {} at {}",
+                a.to, a.arr_time, b.from, b.dep_time
+            ))
+        } else {
+            None
+        }
+    })
+}
+
+// A description of the first difference between the outcomes of a query, None if they agree
+pub fn mismatch(query: &Query, outcomes: &[Outcome]) -> Option<String> {
+    for outcome in outcomes {
+        if let Some(reason) = outcome
+            .journeys
+            .iter()
+            .find_map(|journey| invalid_journey(query, journey))
+        {
+            return Some(format!("{}: a journey {}", outcome.algorithm, reason));
+        }
+    }
+    let same_cost = query.options.walking_reluctance == 0.
+        && query.options.agency_penalties.is_empty()
+        && query.options.default_agency_penalty == 0
+        && query.options.route_penalties.is_empty();
+    let (reference, others) = outcomes.split_first()?;
+    others.iter().find_map(|outcome| {
+        let (expected, found) = (reference.earliest_arrival(), outcome.earliest_arrival());
+        let differ = if same_cost {
+            expected != found
+        } else {
+            expected.is_some() != found.is_some()
+        };
+        differ.then(|| {
+            format!(
+                "earliest arrival {:?} with {}, {:?} with {}",
+                expected, reference.algorithm, found, outcome.algorithm
+            )
+        })
+    })
+}
+
+// The outcomes of every algorithm, and whether they agree
+pub fn compare<N: Network>(
+    network: &N,
+    query: &Query,
+    algorithms: &[Algorithm],
+) -> (Vec<Outcome>, Option<String>) {
+    let outcomes: Vec<_> = algorithms
+        .iter()
+        .map(|&algorithm| Outcome::run(network, query, algorithm))
+        .collect();
+    let mismatch = mismatch(query, &outcomes);
+    (outcomes, mismatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::Timetable;
+
+    fn timetable() -> Timetable {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "1:00").s("b", "2:00").s("c", "3:00");
+        b.trip().s("b", "2:10").s("c", "2:50");
+        b.trip().s("c", "4:00").s("d", "5:00");
+        b.trip().s("a", "6:00").s("d", "7:00");
+        b.build()
+    }
+
+    #[test]
+    fn algorithms_agree() {
+        let t = timetable();
+        let queries: Vec<_> = crate::sampling::QuerySampler::new(&t, 5).take(30).collect();
+        for query in &queries {
+            let (outcomes, mismatch) = compare(&t, query, &Algorithm::ALL);
+            assert_eq!(None, mismatch);
+            assert_eq!(Algorithm::ALL.len(), outcomes.len());
+        }
+    }
+
+    #[test]
+    fn detected_mismatch() {
+        let t = timetable();
+        let query = Query::new(vec![0], vec![3], 0);
+        let (mut outcomes, _) = compare(&t, &query, &Algorithm::ALL);
+        assert_eq!(Some(300), outcomes[1].earliest_arrival());
+        outcomes[1].journeys.clear();
+        assert!(mismatch(&query, &outcomes)
+            .unwrap()
+            .starts_with("earliest arrival Some(300)"));
+
+        outcomes[0].journeys[0].legs[0].from = 2;
+        assert_eq!(
+            Some("profile: a journey starts at stop 2, not an origin".to_owned()),
+            mismatch(&query, &outcomes)
+        );
+    }
+}
@@ -0,0 +1,223 @@
+// The plain connection CSV used by academic CSA implementations:
+// one connection per line as `dep_stop,arr_stop,dep_time,arr_time,trip`
+// When exporting, stops and trips are the indices in the timetable and the connections are sorted by increasing departure
+// The footpaths are given as `from_stop,to_stop,duration`
+use crate::structures::{Connection, Stop, Timetable, Trip};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const HEADER: &str = "dep_stop,arr_stop,dep_time,arr_time,trip";
+
+impl Timetable {
+    pub fn write_connections<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        writeln!(writer, "{}", HEADER)?;
+        // The connections are stored by decreasing departure time
+        for c in self.connections.iter().rev() {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                c.dep_stop, c.arr_stop, c.dep_time, c.arr_time, c.trip
+            )?;
+        }
+        writer.flush()
+    }
+
+    pub fn export_connections<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.write_connections(std::fs::File::create(path)?)
+    }
+
+    // Times are in seconds since 1970-01-01 (in UTC), unix timestamps can be used directly
+    // Every stop is its own stop area, so that it can be requested by the server
+    pub fn from_connections_csv<P: AsRef<Path>>(
+        path: P,
+        footpaths_path: Option<&Path>,
+    ) -> std::io::Result<Timetable> {
+        let mut reader = ConnectionsReader::default();
+        reader.read_connections(BufReader::new(std::fs::File::open(path)?))?;
+        if let Some(footpaths_path) = footpaths_path {
+            reader.read_footpaths(BufReader::new(std::fs::File::open(footpaths_path)?))?;
+        }
+        Ok(reader.into_timetable())
+    }
+}
+
+fn invalid_data(line: usize, message: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("line {}: {}", line + 1, message),
+    )
+}
+
+// The fields of every line, skipping the header (if any) and the empty lines
+fn records<R: BufRead>(
+    reader: R,
+    fields: usize,
+) -> impl Iterator<Item = std::io::Result<(usize, Vec<String>)>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(move |(line_number, line)| {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            let record: Vec<_> = line.split(',').map(|f| f.trim().to_owned()).collect();
+            // In both files, the third field is a number (the departure or the duration)
+            let is_header =
+                line_number == 0 && record.get(2).is_some_and(|f| f.parse::<u32>().is_err());
+            if line.trim().is_empty() || is_header {
+                None
+            } else if record.len() != fields {
+                Some(Err(invalid_data(
+                    line_number,
+                    &format!("expected {} fields, got {}", fields, record.len()),
+                )))
+            } else {
+                Some(Ok((line_number, record)))
+            }
+        })
+}
+
+fn parse_u32(line: usize, field: &str) -> std::io::Result<u32> {
+    field
+        .parse()
+        .map_err(|_| invalid_data(line, &format!("invalid number {}", field)))
+}
+
+#[derive(Default)]
+struct ConnectionsReader {
+    stops: Vec<Stop>,
+    stop_indices: HashMap<String, usize>,
+    trips: Vec<Trip>,
+    trip_indices: HashMap<String, usize>,
+    connections: Vec<Connection>,
+    footpaths: Vec<(usize, usize, u32)>,
+}
+
+impl ConnectionsReader {
+    fn stop(&mut self, id: &str) -> usize {
+        let stops = &mut self.stops;
+        *self.stop_indices.entry(id.to_owned()).or_insert_with(|| {
+            stops.push(Stop {
+                id: id.to_owned(),
+                name: id.to_owned(),
+                parent_station: Some(id.to_owned()),
+                ..Default::default()
+            });
+            stops.len() - 1
+        })
+    }
+
+    fn trip(&mut self, id: &str) -> usize {
+        let trips = &mut self.trips;
+        *self.trip_indices.entry(id.to_owned()).or_insert_with(|| {
+            trips.push(Trip {
+                id: id.to_owned(),
+                day: 0,
+                route: None,
+                block_id: None,
+            });
+            trips.len() - 1
+        })
+    }
+
+    fn read_connections<R: BufRead>(&mut self, reader: R) -> std::io::Result<()> {
+        for record in records(reader, 5) {
+            let (line, record) = record?;
+            let dep_time = parse_u32(line, &record[2])?;
+            let arr_time = parse_u32(line, &record[3])?;
+            if arr_time < dep_time {
+                return Err(invalid_data(line, "arrival before departure"));
+            }
+            let connection = Connection {
+                dep_stop: self.stop(&record[0]),
+                arr_stop: self.stop(&record[1]),
+                dep_time,
+                arr_time,
+                trip: self.trip(&record[4]),
+            };
+            self.connections.push(connection);
+        }
+        Ok(())
+    }
+
+    fn read_footpaths<R: BufRead>(&mut self, reader: R) -> std::io::Result<()> {
+        for record in records(reader, 3) {
+            let (line, record) = record?;
+            let footpath = (
+                self.stop(&record[0]),
+                self.stop(&record[1]),
+                parse_u32(line, &record[2])?,
+            );
+            self.footpaths.push(footpath);
+        }
+        Ok(())
+    }
+
+    fn into_timetable(mut self) -> Timetable {
+        // We want the connections by decreasing departure time
+        self.connections
+            .sort_by_key(|c| std::cmp::Reverse((c.dep_time, c.arr_time)));
+        let mut timetable = Timetable {
+            start_date: chrono::NaiveDate::from_ymd(1970, 1, 1),
+            timezone: chrono_tz::UTC,
+            transform_duration: 0,
+            translations: Default::default(),
+            fare_rules: Vec::new(),
+            connections: self.connections,
+            footpaths: vec![Vec::new(); self.stops.len()],
+            stops: self.stops,
+            trips: self.trips,
+            routes: Vec::new(),
+        };
+        for (from, to, duration) in self.footpaths {
+            timetable.add_footpath(from, to, duration);
+        }
+        timetable
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("b", "0:30")
+            .s("c", "0:40");
+        let mut output = Vec::new();
+        b.build().write_connections(&mut output).unwrap();
+        assert_eq!(
+            "dep_stop,arr_stop,dep_time,arr_time,trip\n0,1,10,20,0\n1,2,30,40,1\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn import() {
+        let mut reader = ConnectionsReader::default();
+        let connections =
+            "dep_stop,arr_stop,dep_time,arr_time,trip\na,b,10,20,t1\n\nb,c,30,40,t2\n";
+        reader.read_connections(connections.as_bytes()).unwrap();
+        reader.read_footpaths("c,a,5\n".as_bytes()).unwrap();
+        let t = reader.into_timetable();
+        assert_eq!(3, t.stops.len());
+        assert_eq!(2, t.trips.len());
+        assert_eq!(30, t.connections[0].dep_time);
+        assert_eq!(Some(5), t.footpath_duration(2, 0));
+        assert_eq!(vec![0], t.stop_index_by_stop_area_id("a"));
+
+        let mut reader = ConnectionsReader::default();
+        let error = reader
+            .read_connections("a,b,10,20,t1\na,b,ten,20,t1\n".as_bytes())
+            .unwrap_err();
+        assert_eq!("line 2: invalid number ten", error.to_string());
+    }
+}
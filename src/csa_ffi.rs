@@ -0,0 +1,243 @@
+// C bindings, the header is include/csa.h (generated with `cbindgen --output include/csa.h`)
+// Every function returning a pointer returns null on error
+// and everything that is returned must be released with the matching *_free function
+use crate::journey::{Journey, LegKind};
+use crate::query::Query;
+use crate::structures::Timetable;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsaLegKind {
+    Vehicle,
+    Walk,
+}
+
+// Stops and trips are indices in the timetable, see csa_stop_id and csa_trip_id
+#[repr(C)]
+#[derive(Debug)]
+pub struct CsaLeg {
+    pub kind: CsaLegKind,
+    pub from: usize,
+    pub to: usize,
+    pub dep_time: u32,
+    pub arr_time: u32,
+    // Only meaningful for a vehicle leg
+    pub trip: usize,
+}
+
+#[repr(C)]
+pub struct CsaJourney {
+    pub legs: *mut CsaLeg,
+    pub legs_len: usize,
+}
+
+#[repr(C)]
+pub struct CsaJourneys {
+    pub journeys: *mut CsaJourney,
+    pub len: usize,
+}
+
+unsafe fn str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        None
+    } else {
+        CStr::from_ptr(s).to_str().ok()
+    }
+}
+
+fn into_raw_slice<T>(v: Vec<T>) -> (*mut T, usize) {
+    let boxed = v.into_boxed_slice();
+    let len = boxed.len();
+    (Box::into_raw(boxed) as *mut T, len)
+}
+
+unsafe fn free_raw_slice<T>(data: *mut T, len: usize) -> Vec<T> {
+    Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)).into_vec()
+}
+
+fn journey(journey: &Journey) -> CsaJourney {
+    let legs = journey
+        .legs
+        .iter()
+        .map(|leg| {
+            let (kind, trip) = match leg.kind {
+                LegKind::Vehicle { trip } => (CsaLegKind::Vehicle, trip),
+                LegKind::Walk => (CsaLegKind::Walk, 0),
+            };
+            CsaLeg {
+                kind,
+                from: leg.from,
+                to: leg.to,
+                dep_time: leg.dep_time,
+                arr_time: leg.arr_time,
+                trip,
+            }
+        })
+        .collect();
+    let (legs, legs_len) = into_raw_slice(legs);
+    CsaJourney { legs, legs_len }
+}
+
+/// Loads a binary timetable, as written by the convert binary
+///
+/// # Safety
+///
+/// `path` must be a valid NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn csa_timetable_load(path: *const c_char) -> *mut Timetable {
+    let timetable = str(path)
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|bytes| Timetable::from_bytes(&bytes).ok());
+    match timetable {
+        Some(timetable) => Box::into_raw(Box::new(timetable)),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `timetable` must come from csa_timetable_load, or be null
+#[no_mangle]
+pub unsafe extern "C" fn csa_timetable_free(timetable: *mut Timetable) {
+    if !timetable.is_null() {
+        drop(Box::from_raw(timetable));
+    }
+}
+
+/// The journeys between two stop areas (or stops), leaving after `departure`
+/// (in seconds since the midnight of the first day of the timetable)
+///
+/// # Safety
+///
+/// `timetable` must come from csa_timetable_load, `from` and `to` must be NUL terminated strings
+#[no_mangle]
+pub unsafe extern "C" fn csa_plan(
+    timetable: *const Timetable,
+    from: *const c_char,
+    to: *const c_char,
+    departure: u32,
+    count: usize,
+) -> *mut CsaJourneys {
+    let timetable = match timetable.as_ref() {
+        Some(timetable) => timetable,
+        None => return std::ptr::null_mut(),
+    };
+    let (from, to) = match (str(from), str(to)) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return std::ptr::null_mut(),
+    };
+    let origin = timetable.stop_indices(from);
+    let destination = timetable.stop_indices(to);
+    if origin.is_empty() || destination.is_empty() {
+        return std::ptr::null_mut();
+    }
+
+    let mut query = Query::new(origin, destination, departure);
+    query.count = count;
+    let journeys = query.journeys(timetable).iter().map(journey).collect();
+    let (journeys, len) = into_raw_slice(journeys);
+    Box::into_raw(Box::new(CsaJourneys { journeys, len }))
+}
+
+/// # Safety
+///
+/// `journeys` must come from csa_plan, or be null
+#[no_mangle]
+pub unsafe extern "C" fn csa_journeys_free(journeys: *mut CsaJourneys) {
+    if journeys.is_null() {
+        return;
+    }
+    let journeys = Box::from_raw(journeys);
+    for journey in free_raw_slice(journeys.journeys, journeys.len) {
+        free_raw_slice(journey.legs, journey.legs_len);
+    }
+}
+
+fn c_string(s: &str) -> *mut c_char {
+    CString::new(s)
+        .map(CString::into_raw)
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// The id of a stop, to be released with csa_string_free
+///
+/// # Safety
+///
+/// `timetable` must come from csa_timetable_load
+#[no_mangle]
+pub unsafe extern "C" fn csa_stop_id(timetable: *const Timetable, stop: usize) -> *mut c_char {
+    match timetable.as_ref().and_then(|t| t.stops.get(stop)) {
+        Some(stop) => c_string(&stop.id),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// The id of a trip, to be released with csa_string_free
+///
+/// # Safety
+///
+/// `timetable` must come from csa_timetable_load
+#[no_mangle]
+pub unsafe extern "C" fn csa_trip_id(timetable: *const Timetable, trip: usize) -> *mut c_char {
+    match timetable.as_ref().and_then(|t| t.trips.get(trip)) {
+        Some(trip) => c_string(&trip.id),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `s` must come from csa_stop_id or csa_trip_id, or be null
+#[no_mangle]
+pub unsafe extern "C" fn csa_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("b", "0:30")
+            .s("c", "0:40");
+        let mut t = b.build();
+        for stop in t.stops.iter_mut() {
+            stop.parent_station = Some(stop.id.to_owned());
+        }
+        let path = std::env::temp_dir().join(format!("csa_ffi_plan_{}.bin", std::process::id()));
+        std::fs::write(&path, t.to_bytes()).unwrap();
+        let path = CString::new(path.to_str().unwrap()).unwrap();
+        let from = CString::new("a").unwrap();
+        let to = CString::new("c").unwrap();
+
+        unsafe {
+            let timetable = csa_timetable_load(path.as_ptr());
+            assert!(!timetable.is_null());
+            let journeys = csa_plan(timetable, from.as_ptr(), to.as_ptr(), 0, 3);
+            assert_eq!(1, (*journeys).len);
+            let journey = &*(*journeys).journeys;
+            assert_eq!(2, journey.legs_len);
+            let leg = &*journey.legs.add(1);
+            assert_eq!(CsaLegKind::Vehicle, leg.kind);
+            assert_eq!(40, leg.arr_time);
+
+            let trip = csa_trip_id(timetable, leg.trip);
+            assert_eq!("trip1", CStr::from_ptr(trip).to_str().unwrap());
+            csa_string_free(trip);
+            csa_journeys_free(journeys);
+
+            assert!(csa_plan(timetable, from.as_ptr(), std::ptr::null(), 0, 3).is_null());
+            csa_timetable_free(timetable);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+// The strategies generating the footpaths, that can be combined when building the timetable
+// The GTFS transfers.txt and the OpenStreetMap providers are in their own modules
+use crate::structures::{
+    insert_footpath, walking_duration, Footpath, LocationType, Stop, Timetable,
+};
+
+// One meter in degrees of latitude
+pub(crate) const DEGREES_PER_METER: f64 = 1. / 111_195.;
+
+pub trait FootpathProvider {
+    // The footpaths arriving at every stop, indexed like the stops
+    fn footpaths(&self, stops: &[Stop]) -> Vec<Vec<Footpath>>;
+}
+
+// Between the stop points, entrances and station of a same stop area, the default
+pub struct ParentStationProvider;
+
+impl FootpathProvider for ParentStationProvider {
+    fn footpaths(&self, stops: &[Stop]) -> Vec<Vec<Footpath>> {
+        let indices = stops
+            .iter()
+            .enumerate()
+            .map(|(index, stop)| (stop.id.to_owned(), index))
+            .collect();
+        Timetable::footpaths(stops, &indices)
+    }
+}
+
+// Between all the stop points closer than `radius` meters, walking as the crow flies
+pub struct RadiusProvider {
+    pub radius: f64,
+}
+
+impl FootpathProvider for RadiusProvider {
+    fn footpaths(&self, stops: &[Stop]) -> Vec<Vec<Footpath>> {
+        let mut result = vec![Vec::new(); stops.len()];
+        let mut located: Vec<_> = stops
+            .iter()
+            .enumerate()
+            .filter(|(_, stop)| stop.location_type == LocationType::StopPoint)
+            .filter_map(|(index, stop)| Some((index, (stop.latitude?, stop.longitude?))))
+            .collect();
+        // Only the stops within the radius in latitude are compared
+        located.sort_by(|a, b| a.1 .0.total_cmp(&b.1 .0));
+        let max_latitude = self.radius * DEGREES_PER_METER;
+        for (position, &(a, coord_a)) in located.iter().enumerate() {
+            for &(b, coord_b) in located[position + 1..]
+                .iter()
+                .take_while(|(_, coord)| coord.0 - coord_a.0 <= max_latitude)
+            {
+                if crate::geo::distance(coord_a, coord_b) <= self.radius {
+                    let duration = walking_duration(&stops[a], &stops[b]);
+                    for (from, to) in [(a, b), (b, a)] {
+                        result[to].push(Footpath {
+                            from,
+                            duration,
+                            stairs: false,
+                        });
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Timetable {
+    // The footpaths of the provider replace the durations of the existing ones between the same stops
+    pub fn add_footpaths(&mut self, provider: &dyn FootpathProvider) {
+        for (existing, footpaths) in self
+            .footpaths
+            .iter_mut()
+            .zip(provider.footpaths(&self.stops))
+        {
+            for footpath in footpaths {
+                insert_footpath(existing, footpath);
+            }
+        }
+    }
+
+    // Replaces all the footpaths by the ones of the providers, the later ones taking precedence
+    pub fn set_footpaths(&mut self, providers: &[&dyn FootpathProvider]) {
+        self.footpaths = self.stops.iter().map(|_| Vec::new()).collect();
+        for provider in providers {
+            self.add_footpaths(*provider);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timetable() -> Timetable {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("b", "0:20");
+        b.trip().s("c", "0:30").s("d", "0:40");
+        let mut t = b.build();
+        let coords = [(48.85, 2.35), (48.851, 2.35), (48.852, 2.35), (48.9, 2.35)];
+        for (stop, coord) in t.stops.iter_mut().zip(&coords) {
+            stop.latitude = Some(coord.0);
+            stop.longitude = Some(coord.1);
+        }
+        t
+    }
+
+    #[test]
+    fn radius() {
+        let mut t = timetable();
+        t.set_footpaths(&[&RadiusProvider { radius: 150. }]);
+        // 111 m at 1.12 m/s
+        assert_eq!(Some(99), t.footpath_duration(0, 1));
+        assert_eq!(Some(99), t.footpath_duration(2, 1));
+        assert_eq!(None, t.footpath_duration(0, 2));
+        assert!(t.footpaths[3].is_empty());
+    }
+
+    #[test]
+    fn combined_providers() {
+        let mut t = timetable();
+        t.stops[3].parent_station = Some("b".to_owned());
+        t.stops[1].location_type = LocationType::StopArea;
+        t.set_footpaths(&[&RadiusProvider { radius: 250. }, &ParentStationProvider]);
+        // Only between stop points
+        assert_eq!(Some(199), t.footpath_duration(0, 2));
+        assert_eq!(None, t.footpath_duration(0, 1));
+        // The station and its far away stop point
+        assert_eq!(Some(4865), t.footpath_duration(1, 3));
+        assert_eq!(Some(4865), t.footpath_duration(3, 1));
+
+        // The parent station footpaths are not added twice
+        t.add_footpaths(&ParentStationProvider);
+        assert_eq!(1, t.footpaths[3].len());
+    }
+}
@@ -0,0 +1,4 @@
+// Response formats compatible with other journey planners
+pub mod navitia;
+pub mod otp;
+pub mod protobuf;
@@ -0,0 +1,626 @@
+// Responses following the /journeys API of navitia
+use crate::journey::{Journey, Leg, LegKind};
+use crate::query::{Query, QueryError, Stats};
+use crate::structures::{Network, RouteType, Timetable};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const DATETIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+// The walking speed of navitia, in meters per second
+const DEFAULT_WALKING_SPEED: f64 = 1.12;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct JourneysRequest {
+    pub from: String,
+    // Not needed towards a set of destinations
+    #[serde(default)]
+    pub to: String,
+    // Formatted as 20171128T083000, defaults to the first day of the timetable
+    pub datetime: Option<String>,
+    pub count: Option<usize>,
+    // Only step-free footpaths are used
+    pub wheelchair: Option<bool>,
+    // In meters per second
+    pub walking_speed: Option<f64>,
+    // In seconds
+    pub max_duration: Option<u32>,
+    // The perceived extra seconds of every walked second, see QueryOptions
+    pub walking_reluctance: Option<f64>,
+    // The only fare zones to travel through, separated by commas
+    pub allowed_zones: Option<String>,
+    // The perceived extra seconds of every leg on a route, like `RER-B:600,T3:120`
+    pub route_penalties: Option<String>,
+    // Either profile (the default) or earliest-arrival, for a single journey
+    pub algorithm: Option<String>,
+    // In milliseconds, the journeys found so far are returned when the computation takes longer
+    pub budget_ms: Option<u64>,
+    // Adds the statistics of the query to the response
+    pub debug: Option<bool>,
+    // The language of the names, with the syntax of Accept-Language
+    pub lang: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JourneysResponse {
+    pub journeys: Vec<NavitiaJourney>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<NavitiaError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<Stats>,
+    // The budget of the request was exceeded, the journeys leaving the earliest might be missing
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NavitiaError {
+    pub id: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NavitiaJourney {
+    pub departure_date_time: String,
+    pub arrival_date_time: String,
+    pub requested_date_time: String,
+    pub duration: u32,
+    pub nb_transfers: usize,
+    pub durations: Durations,
+    pub distances: Distances,
+    pub sections: Vec<Section>,
+    // The fare zones traversed, in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Durations {
+    pub total: u32,
+    pub walking: u32,
+}
+
+// In meters
+#[derive(Debug, Serialize)]
+pub struct Distances {
+    pub walking: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Section {
+    #[serde(rename = "type")]
+    pub section_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transfer_type: Option<&'static str>,
+    pub departure_date_time: String,
+    pub arrival_date_time: String,
+    pub duration: u32,
+    // In meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<Place>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<Place>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_informations: Option<DisplayInformations>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Place {
+    pub id: String,
+    pub name: String,
+    pub embedded_type: &'static str,
+    pub stop_point: StopPoint,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StopPoint {
+    pub id: String,
+    pub name: String,
+    pub coord: Coord,
+}
+
+// navitia serializes coordinates as strings
+#[derive(Debug, Serialize)]
+pub struct Coord {
+    pub lat: String,
+    pub lon: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DisplayInformations {
+    pub commercial_mode: &'static str,
+    pub physical_mode: &'static str,
+    pub network: String,
+    pub code: String,
+    pub name: String,
+    pub label: String,
+    pub headsign: String,
+    pub trip_id: String,
+}
+
+impl JourneysResponse {
+    fn error(id: &'static str, message: String) -> Self {
+        Self {
+            journeys: Vec::new(),
+            error: Some(NavitiaError { id, message }),
+            debug: None,
+            partial: false,
+        }
+    }
+
+    // The request could not be parsed into a query
+    pub fn invalid(error: NavitiaError) -> Self {
+        Self {
+            journeys: Vec::new(),
+            error: Some(error),
+            debug: None,
+            partial: false,
+        }
+    }
+}
+
+fn format_datetime(timetable: &Timetable, time: u32) -> String {
+    timetable.datetime(time).format(DATETIME_FORMAT).to_string()
+}
+
+fn mode(route_type: RouteType) -> &'static str {
+    match route_type {
+        RouteType::Tramway => "Tramway",
+        RouteType::Subway => "Metro",
+        RouteType::Rail => "Train",
+        RouteType::Ferry => "Ferry",
+        RouteType::CableCar | RouteType::Gondola | RouteType::Funicular => "Funicular",
+        RouteType::Coach => "Coach",
+        RouteType::Air => "Air",
+        RouteType::Taxi => "Taxi",
+        RouteType::Bus | RouteType::Other(_) => "Bus",
+    }
+}
+
+fn place(timetable: &Timetable, index: usize, language: Option<&str>) -> Place {
+    let stop = &timetable.stops[index];
+    let name = timetable.stop_name(index, language);
+    let coord = Coord {
+        lat: stop.latitude.map(|l| l.to_string()).unwrap_or_default(),
+        lon: stop.longitude.map(|l| l.to_string()).unwrap_or_default(),
+    };
+    Place {
+        id: stop.id.to_owned(),
+        name: name.to_owned(),
+        embedded_type: "stop_point",
+        stop_point: StopPoint {
+            id: stop.id.to_owned(),
+            name: name.to_owned(),
+            coord,
+        },
+    }
+}
+
+fn display_informations(
+    timetable: &Timetable,
+    trip: usize,
+    language: Option<&str>,
+) -> DisplayInformations {
+    let trip = &timetable.trips[trip];
+    let route = trip.route.map(|r| &timetable.routes[r]);
+    let commercial_mode = route.map(|r| mode(r.route_type)).unwrap_or("Bus");
+    let code = trip
+        .route
+        .map(|r| timetable.route_short_name(r, language).to_owned())
+        .unwrap_or_default();
+    DisplayInformations {
+        commercial_mode,
+        physical_mode: commercial_mode,
+        network: route
+            .and_then(|r| r.agency_name.to_owned())
+            .unwrap_or_default(),
+        name: trip
+            .route
+            .map(|r| timetable.route_long_name(r, language).to_owned())
+            .unwrap_or_default(),
+        label: code.to_owned(),
+        code,
+        headsign: trip.id.to_owned(),
+        trip_id: trip.id.to_owned(),
+    }
+}
+
+fn section(timetable: &Timetable, leg: &Leg, is_transfer: bool, language: Option<&str>) -> Section {
+    let (section_type, mode, transfer_type, display_informations) = match leg.kind {
+        LegKind::Vehicle { trip } => (
+            "public_transport",
+            None,
+            None,
+            Some(display_informations(timetable, trip, language)),
+        ),
+        LegKind::Walk if is_transfer => ("transfer", None, Some("walking"), None),
+        LegKind::Walk => ("street_network", Some("walking"), None, None),
+    };
+    Section {
+        section_type,
+        mode,
+        transfer_type,
+        departure_date_time: format_datetime(timetable, leg.dep_time),
+        arrival_date_time: format_datetime(timetable, leg.arr_time),
+        duration: leg.duration(),
+        length: leg.distance,
+        from: Some(place(timetable, leg.from, language)),
+        to: Some(place(timetable, leg.to, language)),
+        display_informations,
+    }
+}
+
+fn waiting(timetable: &Timetable, from: u32, to: u32) -> Section {
+    Section {
+        section_type: "waiting",
+        mode: None,
+        transfer_type: None,
+        departure_date_time: format_datetime(timetable, from),
+        arrival_date_time: format_datetime(timetable, to),
+        duration: to - from,
+        length: None,
+        from: None,
+        to: None,
+        display_informations: None,
+    }
+}
+
+// The names are translated in `language` when available
+pub fn journey(
+    timetable: &Timetable,
+    journey: &Journey,
+    requested: u32,
+    language: Option<&str>,
+) -> NavitiaJourney {
+    let mut sections = Vec::new();
+    let last = journey.legs.len().saturating_sub(1);
+    for (i, leg) in journey.legs.iter().enumerate() {
+        if i > 0 {
+            let previous_arrival = journey.legs[i - 1].arr_time;
+            if previous_arrival < leg.dep_time {
+                sections.push(waiting(timetable, previous_arrival, leg.dep_time));
+            }
+        }
+        sections.push(section(timetable, leg, i != 0 && i != last, language));
+    }
+
+    let dep_time = journey.dep_time().unwrap_or(requested);
+    let arr_time = journey.arr_time().unwrap_or(dep_time);
+    NavitiaJourney {
+        departure_date_time: format_datetime(timetable, dep_time),
+        arrival_date_time: format_datetime(timetable, arr_time),
+        requested_date_time: format_datetime(timetable, requested),
+        duration: arr_time - dep_time,
+        nb_transfers: journey.transfers(),
+        durations: Durations {
+            total: arr_time - dep_time,
+            walking: journey.walking_time(),
+        },
+        distances: Distances {
+            walking: journey.walking_distance(),
+        },
+        sections,
+        zones: journey
+            .zones(timetable)
+            .into_iter()
+            .map(str::to_owned)
+            .collect(),
+    }
+}
+
+impl NavitiaError {
+    fn new(id: &'static str, message: String) -> Self {
+        Self { id, message }
+    }
+}
+
+// Validates the request and builds the corresponding query
+pub fn query(timetable: &Timetable, request: &JourneysRequest) -> Result<Query, NavitiaError> {
+    query_to(timetable, request, timetable.stop_indices(&request.to))
+}
+
+// Like `query`, towards the given stops instead of `to`
+pub fn query_to(
+    timetable: &Timetable,
+    request: &JourneysRequest,
+    destination: Vec<usize>,
+) -> Result<Query, NavitiaError> {
+    let datetime = match &request.datetime {
+        Some(datetime) => {
+            NaiveDateTime::parse_from_str(datetime, DATETIME_FORMAT).map_err(|_| {
+                NavitiaError::new("bad_format", format!("Invalid datetime {}", datetime))
+            })?
+        }
+        None => timetable.start_date.and_hms(0, 0, 0),
+    };
+    let departure = timetable.time(datetime).ok_or_else(|| {
+        NavitiaError::new(
+            "date_out_of_bounds",
+            format!("{} is before the first day of the timetable", datetime),
+        )
+    })?;
+
+    let origin = timetable.stop_indices(&request.from);
+    if origin.is_empty() {
+        return Err(NavitiaError::new(
+            "unknown_object",
+            format!("Unknown object {}", request.from),
+        ));
+    }
+    if destination.is_empty() {
+        return Err(NavitiaError::new(
+            "unknown_object",
+            format!("Unknown object {}", request.to),
+        ));
+    }
+
+    let mut query = Query::new(origin, destination, departure);
+    if let Some(count) = request.count {
+        query.count = count;
+    }
+    query.options.step_free = request.wheelchair.unwrap_or(false);
+    if let Some(speed) = request.walking_speed.filter(|s| *s > 0.) {
+        query.options.walking_speed = speed / DEFAULT_WALKING_SPEED;
+    }
+    query.options.max_duration = request.max_duration;
+    if let Some(reluctance) = request.walking_reluctance.filter(|r| *r > 0.) {
+        query.options.walking_reluctance = reluctance;
+    }
+    if let Some(zones) = &request.allowed_zones {
+        let zones = zones.split(',').map(str::trim).filter(|z| !z.is_empty());
+        query.options.allowed_zones = Some(zones.map(str::to_owned).collect());
+    }
+    if let Some(penalties) = &request.route_penalties {
+        for penalty in penalties.split(',').filter(|p| !p.trim().is_empty()) {
+            // The route ids can contain colons, the seconds can not
+            let (route, seconds) = penalty
+                .rsplit_once(':')
+                .and_then(|(route, seconds)| Some((route.trim(), seconds.trim().parse().ok()?)))
+                .ok_or_else(|| {
+                    NavitiaError::new("bad_format", format!("Invalid route penalty {}", penalty))
+                })?;
+            query
+                .options
+                .route_penalties
+                .insert(route.to_owned(), seconds);
+        }
+    }
+    query.options.budget = request.budget_ms.map(Duration::from_millis);
+    if let Some(algorithm) = &request.algorithm {
+        query.algorithm = algorithm
+            .parse()
+            .map_err(|message| NavitiaError::new("bad_format", message))?;
+    }
+    query.validate(timetable).map_err(|error| {
+        let id = match error {
+            QueryError::OutsideHorizon(_) => "date_out_of_bounds",
+            QueryError::NoOrigin | QueryError::NoDestination | QueryError::UnknownStop(_) => {
+                "unknown_object"
+            }
+            QueryError::InvertedTimeWindow { .. } => "bad_format",
+        };
+        NavitiaError::new(id, error.to_string())
+    })?;
+    Ok(query)
+}
+
+// The journeys are computed in the network (like a realtime view), the names come from its timetable
+pub fn journeys<N: Network>(network: &N, request: &JourneysRequest) -> JourneysResponse {
+    match query(network.timetable(), request) {
+        Ok(query) => journeys_of_query(network, request, &query),
+        Err(error) => JourneysResponse::invalid(error),
+    }
+}
+
+// With the query already parsed from the request, like when the server also logs it
+pub fn journeys_of_query<N: Network>(
+    network: &N,
+    request: &JourneysRequest,
+    query: &Query,
+) -> JourneysResponse {
+    let timetable = network.timetable();
+    // The clock is only read when needed, it is not available in WebAssembly
+    let (journeys, stats) = if request.debug == Some(true) || query.options.budget.is_some() {
+        let (journeys, stats) = query.journeys_with_stats(network);
+        (journeys, Some(stats))
+    } else {
+        (query.journeys(network), None)
+    };
+    let partial = stats.as_ref().is_some_and(|stats| stats.scan.partial);
+    let stats = stats.filter(|_| request.debug == Some(true));
+    if journeys.is_empty() {
+        let mut response = JourneysResponse::error(
+            "no_solution",
+            "no solution found for this journey".to_owned(),
+        );
+        response.debug = stats;
+        response.partial = partial;
+        return response;
+    }
+
+    let language = request
+        .lang
+        .as_deref()
+        .and_then(|lang| timetable.translations.language(lang));
+    JourneysResponse {
+        journeys: journeys
+            .iter()
+            .map(|j| journey(timetable, j, query.departure, language))
+            .collect(),
+        error: None,
+        debug: stats,
+        partial,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_zones() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("a", "0:15")
+            .s("b", "0:50");
+        let mut t = b.build();
+        for (stop, zone) in t.stops.iter_mut().zip(&["1", "2"]) {
+            stop.parent_station = Some(stop.id.to_owned());
+            stop.zone_id = Some(zone.to_string());
+        }
+
+        let mut request = JourneysRequest {
+            from: "a".to_owned(),
+            to: "b".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(vec!["1", "2"], journeys(&t, &request).journeys[0].zones);
+        request.allowed_zones = Some("1, 3".to_owned());
+        assert!(journeys(&t, &request).journeys.is_empty());
+    }
+
+    #[test]
+    fn sections() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("c", "0:30")
+            .s("d", "0:40");
+        let mut t = b.build();
+        t.add_footpath(1, 2, 3);
+        for stop in t.stops.iter_mut() {
+            stop.parent_station = Some(stop.id.to_owned());
+        }
+
+        let request = JourneysRequest {
+            from: "a".to_owned(),
+            to: "d".to_owned(),
+            ..Default::default()
+        };
+        let response = journeys(&t, &request);
+        assert!(response.error.is_none());
+        let journey = &response.journeys[0];
+        let types: Vec<_> = journey.sections.iter().map(|s| s.section_type).collect();
+        assert_eq!(
+            vec![
+                "public_transport",
+                "transfer",
+                "waiting",
+                "public_transport"
+            ],
+            types
+        );
+        assert_eq!(1, journey.nb_transfers);
+        assert_eq!(3, journey.durations.walking);
+        assert_eq!("20190211T000010", journey.departure_date_time);
+        assert!(response.debug.is_none());
+
+        let request = JourneysRequest {
+            from: "a".to_owned(),
+            to: "d".to_owned(),
+            debug: Some(true),
+            ..Default::default()
+        };
+        let stats = journeys(&t, &request).debug.unwrap();
+        assert_eq!(2, stats.scan.connections_scanned);
+    }
+
+    #[test]
+    fn translated_names() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("b", "0:20");
+        let mut t = b.build();
+        for stop in t.stops.iter_mut() {
+            stop.parent_station = Some(stop.id.to_owned());
+        }
+        t.translations
+            .stop_names
+            .entry("fr".to_owned())
+            .or_default()
+            .insert(1, "bé".to_owned());
+
+        let mut request = JourneysRequest {
+            from: "a".to_owned(),
+            to: "b".to_owned(),
+            ..Default::default()
+        };
+        let name = |response: JourneysResponse| {
+            let to = response.journeys[0].sections[0].to.as_ref().unwrap();
+            to.stop_point.name.to_owned()
+        };
+        assert_eq!("b", name(journeys(&t, &request)));
+        request.lang = Some("fr-FR,en;q=0.5".to_owned());
+        assert_eq!("bé", name(journeys(&t, &request)));
+        request.lang = Some("de".to_owned());
+        assert_eq!("b", name(journeys(&t, &request)));
+    }
+
+    #[test]
+    fn errors() {
+        let t = Timetable::builder().build();
+        let request = JourneysRequest {
+            from: "a".to_owned(),
+            to: "d".to_owned(),
+            datetime: Some("yesterday".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!("bad_format", journeys(&t, &request).error.unwrap().id);
+
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("d", "0:20");
+        let mut t = b.build();
+        for stop in t.stops.iter_mut() {
+            stop.parent_station = Some(stop.id.to_owned());
+        }
+        let request = JourneysRequest {
+            from: "a".to_owned(),
+            to: "d".to_owned(),
+            datetime: Some("20190212T000000".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "date_out_of_bounds",
+            journeys(&t, &request).error.unwrap().id
+        );
+
+        let request = JourneysRequest {
+            from: "a".to_owned(),
+            to: "d".to_owned(),
+            route_penalties: Some("IDFM:C01743:600,T3".to_owned()),
+            ..Default::default()
+        };
+        let error = journeys(&t, &request).error.unwrap();
+        assert_eq!("Invalid route penalty T3", error.message);
+
+        let request = JourneysRequest {
+            from: "a".to_owned(),
+            to: "d".to_owned(),
+            algorithm: Some("raptor".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!("bad_format", journeys(&t, &request).error.unwrap().id);
+        let request = JourneysRequest {
+            algorithm: Some("earliest-arrival".to_owned()),
+            ..request
+        };
+        assert_eq!(1, journeys(&t, &request).journeys.len());
+
+        // The budget is exceeded before scanning anything
+        let request = JourneysRequest {
+            budget_ms: Some(0),
+            ..request
+        };
+        let response = journeys(&t, &request);
+        assert_eq!("no_solution", response.error.unwrap().id);
+        assert!(response.partial);
+    }
+}
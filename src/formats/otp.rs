@@ -0,0 +1,516 @@
+// Responses following the /plan API of OpenTripPlanner
+// so that existing OTP frontends can use this router
+use crate::journey::{Journey, LegKind};
+use crate::query::{Query, QueryError, Stats};
+use crate::structures::{Network, RouteType, Timetable};
+use chrono::{NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+// The walking speed of OpenTripPlanner, in meters per second
+const DEFAULT_WALK_SPEED: f64 = 1.33;
+// In seconds, like otherThanPreferredRoutesPenalty and useUnpreferredRoutesPenalty of OpenTripPlanner
+const UNPREFERRED_PENALTY: u32 = 300;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanRequest {
+    pub from_place: String,
+    pub to_place: String,
+    // Either YYYY-MM-DD or MM-DD-YYYY, defaults to the first day of the timetable
+    pub date: Option<String>,
+    // Either HH:MM[:SS] or h:mm(am|pm), defaults to midnight
+    pub time: Option<String>,
+    pub num_itineraries: Option<usize>,
+    // Only step-free footpaths are used
+    pub wheelchair: Option<bool>,
+    // In meters per second
+    pub walk_speed: Option<f64>,
+    // How much worse walking is than riding, 1 being as bad
+    pub walk_reluctance: Option<f64>,
+    // Agency ids separated by commas, the other agencies are not banned but penalized
+    pub preferred_agencies: Option<String>,
+    pub unpreferred_agencies: Option<String>,
+    // Route ids separated by commas
+    pub unpreferred_routes: Option<String>,
+    // Either profile (the default) or earliest-arrival, for a single itinerary
+    pub algorithm: Option<String>,
+    // In milliseconds, the itineraries found so far are returned when the computation takes longer
+    pub budget_ms: Option<u64>,
+    // Adds the statistics of the query to the response
+    pub debug: Option<bool>,
+    // The language of the names, with the syntax of Accept-Language
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<Plan>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<PlannerError>,
+    // Named like the timings of OpenTripPlanner
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_output: Option<Stats>,
+    // The budget of the request was exceeded, the itineraries leaving the earliest might be missing
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Plan {
+    pub date: i64,
+    pub from: Place,
+    pub to: Place,
+    pub itineraries: Vec<Itinerary>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Itinerary {
+    pub duration: u32,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub walk_time: u32,
+    pub transit_time: u32,
+    pub waiting_time: u32,
+    pub walk_distance: f64,
+    pub transfers: usize,
+    pub legs: Vec<Leg>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Leg {
+    pub start_time: i64,
+    pub end_time: i64,
+    pub duration: f64,
+    pub distance: f64,
+    pub mode: &'static str,
+    pub transit_leg: bool,
+    pub real_time: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route_short_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route_long_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agency_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trip_id: Option<String>,
+    pub from: Place,
+    pub to: Place,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Place {
+    pub name: String,
+    pub stop_id: String,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrival: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub departure: Option<i64>,
+    pub vertex_type: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannerError {
+    pub id: u32,
+    pub msg: String,
+    pub message: &'static str,
+}
+
+impl PlannerError {
+    fn new(id: u32, msg: String, message: &'static str) -> Self {
+        Self { id, msg, message }
+    }
+}
+
+impl PlanResponse {
+    fn error(id: u32, msg: String, message: &'static str) -> Self {
+        Self {
+            plan: None,
+            error: Some(PlannerError::new(id, msg, message)),
+            debug_output: None,
+            partial: false,
+        }
+    }
+
+    // The request could not be parsed into a query
+    pub fn invalid(error: PlannerError) -> Self {
+        Self {
+            plan: None,
+            error: Some(error),
+            debug_output: None,
+            partial: false,
+        }
+    }
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%m-%d-%Y"))
+        .ok()
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    let lowercase = time.trim().to_lowercase();
+    ["%H:%M:%S", "%H:%M", "%I:%M%p", "%I:%M %p", "%I:%M:%S%p"]
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(&lowercase, format).ok())
+}
+
+fn mode(route_type: RouteType) -> &'static str {
+    match route_type {
+        RouteType::Tramway => "TRAM",
+        RouteType::Subway => "SUBWAY",
+        RouteType::Rail => "RAIL",
+        RouteType::Ferry => "FERRY",
+        RouteType::CableCar => "CABLE_CAR",
+        RouteType::Gondola => "GONDOLA",
+        RouteType::Funicular => "FUNICULAR",
+        RouteType::Air => "AIRPLANE",
+        RouteType::Bus | RouteType::Coach | RouteType::Taxi | RouteType::Other(_) => "BUS",
+    }
+}
+
+fn place(
+    timetable: &Timetable,
+    index: usize,
+    arrival: Option<u32>,
+    departure: Option<u32>,
+    language: Option<&str>,
+) -> Place {
+    let stop = &timetable.stops[index];
+    Place {
+        name: timetable.stop_name(index, language).to_owned(),
+        stop_id: stop.id.to_owned(),
+        lat: stop.latitude,
+        lon: stop.longitude,
+        arrival: arrival.map(|t| timetable.timestamp_millis(t)),
+        departure: departure.map(|t| timetable.timestamp_millis(t)),
+        vertex_type: "TRANSIT",
+    }
+}
+
+// The requested place, that is not necessarily a stop point
+fn requested_place(timetable: &Timetable, id: &str, language: Option<&str>) -> Place {
+    let index = timetable.stops.iter().position(|stop| stop.id == id);
+    let stop = index.map(|i| &timetable.stops[i]);
+    Place {
+        name: index
+            .map(|i| timetable.stop_name(i, language).to_owned())
+            .unwrap_or_default(),
+        stop_id: id.to_owned(),
+        lat: stop.and_then(|s| s.latitude),
+        lon: stop.and_then(|s| s.longitude),
+        arrival: None,
+        departure: None,
+        vertex_type: "TRANSIT",
+    }
+}
+
+fn leg(timetable: &Timetable, leg: &crate::journey::Leg, language: Option<&str>) -> Leg {
+    let from = place(timetable, leg.from, None, Some(leg.dep_time), language);
+    let to = place(timetable, leg.to, Some(leg.arr_time), None, language);
+    let mut result = Leg {
+        start_time: timetable.timestamp_millis(leg.dep_time),
+        end_time: timetable.timestamp_millis(leg.arr_time),
+        duration: f64::from(leg.duration()),
+        distance: leg.distance.map(f64::from).unwrap_or(0.),
+        mode: "WALK",
+        transit_leg: false,
+        real_time: false,
+        route: None,
+        route_id: None,
+        route_short_name: None,
+        route_long_name: None,
+        agency_name: None,
+        trip_id: None,
+        from,
+        to,
+    };
+
+    if let LegKind::Vehicle { trip } = leg.kind {
+        let trip = &timetable.trips[trip];
+        result.transit_leg = true;
+        result.mode = "BUS";
+        result.trip_id = Some(trip.id.to_owned());
+        if let Some(index) = trip.route {
+            let route = &timetable.routes[index];
+            let short_name = timetable.route_short_name(index, language);
+            result.mode = mode(route.route_type);
+            result.route = Some(short_name.to_owned());
+            result.route_id = Some(route.id.to_owned());
+            result.route_short_name = Some(short_name.to_owned());
+            result.route_long_name = Some(timetable.route_long_name(index, language).to_owned());
+            result.agency_name = route.agency_name.to_owned();
+        }
+    }
+    result
+}
+
+// The names are translated in `language` when available
+pub fn itinerary(timetable: &Timetable, journey: &Journey, language: Option<&str>) -> Itinerary {
+    let start = journey.dep_time().unwrap_or(0);
+    let end = journey.arr_time().unwrap_or(start);
+    let walk_time = journey.walking_time();
+    let transit_time = journey.transit_time();
+    Itinerary {
+        duration: end - start,
+        start_time: timetable.timestamp_millis(start),
+        end_time: timetable.timestamp_millis(end),
+        walk_time,
+        transit_time,
+        waiting_time: (end - start).saturating_sub(walk_time + transit_time),
+        walk_distance: f64::from(journey.walking_distance()),
+        transfers: journey.transfers(),
+        legs: journey
+            .legs
+            .iter()
+            .map(|l| leg(timetable, l, language))
+            .collect(),
+    }
+}
+
+// Validates the request and builds the corresponding query
+pub fn query(timetable: &Timetable, request: &PlanRequest) -> Result<Query, PlannerError> {
+    let date = match &request.date {
+        Some(date) => match parse_date(date) {
+            Some(date) => date,
+            None => {
+                return Err(PlannerError::new(
+                    400,
+                    format!("Invalid date {}", date),
+                    "BOGUS_PARAMETER",
+                ))
+            }
+        },
+        None => timetable.start_date,
+    };
+    let time = match &request.time {
+        Some(time) => match parse_time(time) {
+            Some(time) => time,
+            None => {
+                return Err(PlannerError::new(
+                    400,
+                    format!("Invalid time {}", time),
+                    "BOGUS_PARAMETER",
+                ))
+            }
+        },
+        None => NaiveTime::from_hms(0, 0, 0),
+    };
+    let departure = match timetable.time(date.and_time(time)) {
+        Some(departure) => departure,
+        None => {
+            return Err(PlannerError::new(
+                400,
+                format!("The date {} is before the first day of the timetable", date),
+                "OUTSIDE_BOUNDS",
+            ))
+        }
+    };
+
+    let origin = timetable.stop_indices(&request.from_place);
+    if origin.is_empty() {
+        return Err(PlannerError::new(
+            440,
+            format!("Unknown origin {}", request.from_place),
+            "GEOCODE_FROM_NOT_FOUND",
+        ));
+    }
+    let destination = timetable.stop_indices(&request.to_place);
+    if destination.is_empty() {
+        return Err(PlannerError::new(
+            450,
+            format!("Unknown destination {}", request.to_place),
+            "GEOCODE_TO_NOT_FOUND",
+        ));
+    }
+
+    let mut query = Query::new(origin, destination, departure);
+    if let Some(count) = request.num_itineraries {
+        query.count = count;
+    }
+    query.options.step_free = request.wheelchair.unwrap_or(false);
+    if let Some(speed) = request.walk_speed.filter(|s| *s > 0.) {
+        query.options.walking_speed = speed / DEFAULT_WALK_SPEED;
+    }
+    // The walking time already counts once in the arrival
+    if let Some(reluctance) = request.walk_reluctance.filter(|r| *r > 1.) {
+        query.options.walking_reluctance = reluctance - 1.;
+    }
+    let ids = |ids: &Option<String>| -> Vec<String> {
+        let ids = ids.iter().flat_map(|ids| ids.split(','));
+        ids.map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_owned)
+            .collect()
+    };
+    let preferred = ids(&request.preferred_agencies);
+    if !preferred.is_empty() {
+        query.options.default_agency_penalty = UNPREFERRED_PENALTY;
+        let penalties = preferred.into_iter().map(|agency| (agency, 0));
+        query.options.agency_penalties.extend(penalties);
+    }
+    for agency in ids(&request.unpreferred_agencies) {
+        query
+            .options
+            .agency_penalties
+            .insert(agency, UNPREFERRED_PENALTY);
+    }
+    for route in ids(&request.unpreferred_routes) {
+        query
+            .options
+            .route_penalties
+            .insert(route, UNPREFERRED_PENALTY);
+    }
+    query.options.budget = request.budget_ms.map(std::time::Duration::from_millis);
+    if let Some(algorithm) = &request.algorithm {
+        match algorithm.parse() {
+            Ok(algorithm) => query.algorithm = algorithm,
+            Err(message) => return Err(PlannerError::new(400, message, "BOGUS_PARAMETER")),
+        }
+    }
+    if let Err(error) = query.validate(timetable) {
+        let message = match error {
+            QueryError::OutsideHorizon(_) => "OUTSIDE_BOUNDS",
+            _ => "BOGUS_PARAMETER",
+        };
+        return Err(PlannerError::new(400, error.to_string(), message));
+    }
+    Ok(query)
+}
+
+// The itineraries are computed in the network (like a realtime view), the names come from its timetable
+pub fn plan<N: Network>(network: &N, request: &PlanRequest) -> PlanResponse {
+    match query(network.timetable(), request) {
+        Ok(query) => plan_of_query(network, request, &query),
+        Err(error) => PlanResponse::invalid(error),
+    }
+}
+
+// With the query already parsed from the request, like when the server also logs it
+pub fn plan_of_query<N: Network>(
+    network: &N,
+    request: &PlanRequest,
+    query: &Query,
+) -> PlanResponse {
+    let timetable = network.timetable();
+    // The clock is only read when needed, it is not available in WebAssembly
+    let (journeys, stats) = if request.debug == Some(true) || query.options.budget.is_some() {
+        let (journeys, stats) = query.journeys_with_stats(network);
+        (journeys, Some(stats))
+    } else {
+        (query.journeys(network), None)
+    };
+    let partial = stats.as_ref().is_some_and(|stats| stats.scan.partial);
+    let stats = stats.filter(|_| request.debug == Some(true));
+    if journeys.is_empty() {
+        let mut response = PlanResponse::error(404, "No trip found".to_owned(), "PATH_NOT_FOUND");
+        response.debug_output = stats;
+        response.partial = partial;
+        return response;
+    }
+
+    let language = request
+        .locale
+        .as_deref()
+        .and_then(|locale| timetable.translations.language(locale));
+    PlanResponse {
+        plan: Some(Plan {
+            date: timetable.timestamp_millis(query.departure),
+            from: requested_place(timetable, &request.from_place, language),
+            to: requested_place(timetable, &request.to_place, language),
+            itineraries: journeys
+                .iter()
+                .map(|j| itinerary(timetable, j, language))
+                .collect(),
+        }),
+        error: None,
+        debug_output: stats,
+        partial,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "gtfs")]
+    fn request(from: &str, to: &str, date: &str, time: &str) -> PlanRequest {
+        PlanRequest {
+            from_place: from.to_owned(),
+            to_place: to.to_owned(),
+            date: Some(date.to_owned()),
+            time: Some(time.to_owned()),
+            num_itineraries: None,
+            wheelchair: None,
+            walk_speed: None,
+            walk_reluctance: None,
+            preferred_agencies: None,
+            unpreferred_agencies: None,
+            unpreferred_routes: None,
+            algorithm: None,
+            budget_ms: None,
+            debug: None,
+            locale: None,
+        }
+    }
+
+    #[cfg(feature = "gtfs")]
+    fn fixtures() -> Timetable {
+        let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
+        let mut timetable = Timetable::from_gtfs(&gtfs, "2017-1-1", 10);
+        // Make stop2 a stop area so that it can be requested
+        for stop in timetable.stops.iter_mut() {
+            if stop.id == "stop2" {
+                stop.parent_station = Some("stop2".to_owned());
+            }
+        }
+        timetable
+    }
+
+    #[test]
+    fn parse_dates_and_times() {
+        assert_eq!(parse_date("01-07-2017"), parse_date("2017-01-07"));
+        assert_eq!(Some(NaiveTime::from_hms(13, 5, 0)), parse_time("1:05pm"));
+        assert_eq!(Some(NaiveTime::from_hms(13, 5, 0)), parse_time("13:05"));
+        assert_eq!(None, parse_time("noon"));
+    }
+
+    #[test]
+    #[cfg(feature = "gtfs")]
+    fn plan_itinerary() {
+        let t = fixtures();
+        let response = plan(&t, &request("stop2", "stop1", "2017-01-07", "13:00"));
+        assert!(response.error.is_none());
+        let plan = response.plan.unwrap();
+        // The trip runs on saturdays and sundays
+        assert_eq!(2, plan.itineraries.len());
+        let itinerary = &plan.itineraries[0];
+        assert_eq!(0, itinerary.transfers);
+        assert_eq!("BUS", itinerary.legs[0].mode);
+        assert_eq!("stop3", itinerary.legs[0].to.stop_id);
+        // 2017-01-07 14:00 in Europe/Paris
+        assert_eq!(1_483_794_000_000, itinerary.start_time);
+    }
+
+    #[test]
+    #[cfg(feature = "gtfs")]
+    fn plan_errors() {
+        let t = fixtures();
+        let response = plan(&t, &request("stop2", "stop1", "2017-01-08", "15:00"));
+        assert_eq!("PATH_NOT_FOUND", response.error.unwrap().message);
+        let response = plan(&t, &request("unknown", "stop1", "2017-01-07", "13:00"));
+        assert_eq!("GEOCODE_FROM_NOT_FOUND", response.error.unwrap().message);
+    }
+}
@@ -0,0 +1,176 @@
+// Binary responses, the messages mirror proto/csa.proto
+use crate::algo::Profile;
+use crate::journey::{self, Journey as CsaJourney, LegKind};
+use crate::structures::Timetable;
+use prost::Message;
+
+pub const CONTENT_TYPE: &str = "application/x-protobuf";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum Kind {
+    Vehicle = 0,
+    Walk = 1,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Leg {
+    #[prost(enumeration = "Kind", tag = "1")]
+    pub kind: i32,
+    #[prost(string, tag = "2")]
+    pub from_stop_id: String,
+    #[prost(string, tag = "3")]
+    pub to_stop_id: String,
+    #[prost(int64, tag = "4")]
+    pub departure: i64,
+    #[prost(int64, tag = "5")]
+    pub arrival: i64,
+    #[prost(string, tag = "6")]
+    pub trip_id: String,
+    #[prost(string, tag = "7")]
+    pub route_id: String,
+    #[prost(uint32, optional, tag = "8")]
+    pub distance: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Journey {
+    #[prost(int64, tag = "1")]
+    pub departure: i64,
+    #[prost(int64, tag = "2")]
+    pub arrival: i64,
+    #[prost(uint32, tag = "3")]
+    pub transfers: u32,
+    #[prost(message, repeated, tag = "4")]
+    pub legs: Vec<Leg>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Journeys {
+    #[prost(message, repeated, tag = "1")]
+    pub journeys: Vec<Journey>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Summary {
+    #[prost(int64, tag = "1")]
+    pub departure: i64,
+    #[prost(int64, tag = "2")]
+    pub arrival: i64,
+    #[prost(uint32, tag = "3")]
+    pub transfers: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct StopProfiles {
+    #[prost(string, tag = "1")]
+    pub stop_id: String,
+    #[prost(message, repeated, tag = "2")]
+    pub summaries: Vec<Summary>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Profiles {
+    #[prost(message, repeated, tag = "1")]
+    pub stops: Vec<StopProfiles>,
+}
+
+fn leg(timetable: &Timetable, leg: &journey::Leg) -> Leg {
+    let mut result = Leg {
+        kind: Kind::Walk as i32,
+        from_stop_id: timetable.stops[leg.from].id.to_owned(),
+        to_stop_id: timetable.stops[leg.to].id.to_owned(),
+        departure: timetable.timestamp_millis(leg.dep_time),
+        arrival: timetable.timestamp_millis(leg.arr_time),
+        trip_id: String::new(),
+        route_id: String::new(),
+        distance: leg.distance,
+    };
+    if let LegKind::Vehicle { trip } = leg.kind {
+        let trip = &timetable.trips[trip];
+        result.kind = Kind::Vehicle as i32;
+        result.trip_id = trip.id.to_owned();
+        if let Some(route) = trip.route {
+            result.route_id = timetable.routes[route].id.to_owned();
+        }
+    }
+    result
+}
+
+pub fn journey(timetable: &Timetable, journey: &CsaJourney) -> Journey {
+    Journey {
+        departure: journey
+            .dep_time()
+            .map(|t| timetable.timestamp_millis(t))
+            .unwrap_or_default(),
+        arrival: journey
+            .arr_time()
+            .map(|t| timetable.timestamp_millis(t))
+            .unwrap_or_default(),
+        transfers: journey.transfers() as u32,
+        legs: journey.legs.iter().map(|l| leg(timetable, l)).collect(),
+    }
+}
+
+pub fn journeys(timetable: &Timetable, journeys: &[CsaJourney]) -> Vec<u8> {
+    Journeys {
+        journeys: journeys.iter().map(|j| journey(timetable, j)).collect(),
+    }
+    .encode_to_vec()
+}
+
+// The summaries of all the profiles from every stop towards the destinations
+pub fn profiles(timetable: &Timetable, profiles: &[Vec<Profile>]) -> Vec<u8> {
+    let stops = profiles
+        .iter()
+        .enumerate()
+        .map(|(stop, stop_profiles)| StopProfiles {
+            stop_id: timetable.stops[stop].id.to_owned(),
+            summaries: stop_profiles
+                .iter()
+                .filter(|p| p.out_connection.is_some())
+                .map(|p| Summary {
+                    departure: timetable.timestamp_millis(p.dep_time),
+                    arrival: timetable.timestamp_millis(p.arr_time),
+                    transfers: u32::from(p.transfers),
+                })
+                .collect(),
+        })
+        .collect();
+    Profiles { stops }.encode_to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::compute;
+
+    #[test]
+    fn roundtrip() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("b", "0:30")
+            .s("c", "0:40");
+        let t = b.build();
+        let p = compute(&t, &[2]);
+
+        let decoded = Profiles::decode(profiles(&t, &p).as_slice()).unwrap();
+        assert_eq!(3, decoded.stops.len());
+        assert_eq!("a", decoded.stops[0].stop_id);
+        assert_eq!(1, decoded.stops[0].summaries.len());
+        assert_eq!(1, decoded.stops[0].summaries[0].transfers);
+        assert_eq!(
+            30_000,
+            decoded.stops[0].summaries[0].arrival - decoded.stops[0].summaries[0].departure
+        );
+
+        let route = p[0][0].route(&p, &t);
+        let j = CsaJourney::new(&t, 0, &route, &[2]);
+        let decoded = Journeys::decode(journeys(&t, &[j]).as_slice()).unwrap();
+        assert_eq!(2, decoded.journeys[0].legs.len());
+        assert_eq!("trip1", decoded.journeys[0].legs[1].trip_id);
+    }
+}
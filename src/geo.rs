@@ -0,0 +1,57 @@
+// Distances between coordinates, in meters
+const EARTH_RADIUS: f64 = 6_371_000.;
+
+// The great-circle distance between two (latitude, longitude) in degrees
+pub fn distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lon2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.).sin().powi(2);
+    2. * EARTH_RADIUS * a.sqrt().asin()
+}
+
+// The distance from a point to the segment [a, b], with the position of the closest point
+// (0 at a, 1 at b), None if a and b are the same point
+// The earth is considered flat, which is fine for a few kilometers
+pub fn distance_to_segment(point: (f64, f64), a: (f64, f64), b: (f64, f64)) -> Option<(f64, f64)> {
+    let scale = a.0.to_radians().cos();
+    let project = |p: (f64, f64)| {
+        (
+            (p.1 - a.1).to_radians() * scale * EARTH_RADIUS,
+            (p.0 - a.0).to_radians() * EARTH_RADIUS,
+        )
+    };
+    let (bx, by) = project(b);
+    let (px, py) = project(point);
+    let length = bx * bx + by * by;
+    if length == 0. {
+        return None;
+    }
+    let position = ((px * bx + py * by) / length).clamp(0., 1.);
+    let (dx, dy) = (px - position * bx, py - position * by);
+    Some(((dx * dx + dy * dy).sqrt(), position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paris_london() {
+        let d = distance((48.8566, 2.3522), (51.5074, -0.1278));
+        assert!((d - 343_500.).abs() < 1_000.);
+        assert_eq!(0., distance((48.8566, 2.3522), (48.8566, 2.3522)));
+    }
+
+    #[test]
+    fn segment() {
+        let (a, b) = ((48.8, 2.35), (48.81, 2.35));
+        let (d, position) = distance_to_segment((48.805, 2.351), a, b).unwrap();
+        assert!((d - 73.).abs() < 1.);
+        assert!((position - 0.5).abs() < 0.01);
+        let (d, position) = distance_to_segment((48.82, 2.35), a, b).unwrap();
+        assert!((d - 1112.).abs() < 5.);
+        assert_eq!(1., position);
+        assert!(distance_to_segment((48.82, 2.35), a, a).is_none());
+    }
+}
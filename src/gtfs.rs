@@ -0,0 +1,979 @@
+// Reads GTFS timetables
+use crate::footpaths::{
+    FootpathProvider, ParentStationProvider, RadiusProvider, DEGREES_PER_METER,
+};
+use crate::geo;
+use crate::structures::{
+    walking_duration, Connection, FareRule, Footpath, LocationType, Route, RouteType, Stop,
+    Timetable, Trip,
+};
+use chrono::prelude::{NaiveDate, Utc};
+use chrono::Duration;
+use gtfs_structures::Exception;
+use itertools::Itertools;
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+// The stops closer to the road of a hail-and-ride segment can be used to board or alight
+const CONTINUOUS_MAX_DISTANCE: f64 = 100.;
+// In degrees, about a kilometer in latitude
+const GRID_CELL: f64 = 0.01;
+
+// If travellers can be picked up and dropped off anywhere between two stops
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Continuous {
+    pub pickup: bool,
+    pub drop_off: bool,
+}
+
+// 1 or empty is no continuous stopping, the others are variants of continuous stopping
+fn continuous(value: &Option<u8>) -> Option<bool> {
+    value.map(|v| v != 1)
+}
+
+#[derive(Deserialize)]
+struct RawRoute {
+    route_id: String,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    continuous_pickup: Option<u8>,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    continuous_drop_off: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct RawStopTime {
+    trip_id: String,
+    stop_sequence: u16,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    continuous_pickup: Option<u8>,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    continuous_drop_off: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct RawTranslation {
+    table_name: String,
+    field_name: String,
+    language: String,
+    translation: String,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    record_id: Option<String>,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    field_value: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawStopZone {
+    stop_id: String,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    zone_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawTripBlock {
+    trip_id: String,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    block_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawTransfer {
+    from_stop_id: String,
+    to_stop_id: String,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    transfer_type: Option<u8>,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    min_transfer_time: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct RawFareRule {
+    fare_id: String,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    route_id: Option<String>,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    origin_id: Option<String>,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    destination_id: Option<String>,
+    #[serde(default, deserialize_with = "csv::invalid_option")]
+    contains_id: Option<String>,
+}
+
+// The continuous_pickup and continuous_drop_off fields, that gtfs-structures does not read
+#[derive(Debug, Default)]
+pub struct ContinuousStops {
+    // By route_id
+    routes: HashMap<String, Continuous>,
+    // By (trip_id, stop_sequence), for the segment leaving that stop
+    stop_times: HashMap<(String, u16), (Option<bool>, Option<bool>)>,
+}
+
+impl ContinuousStops {
+    // Only GTFS folders are read, a missing file or column means no continuous stopping
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, csv::Error> {
+        let mut result = Self::default();
+        let routes = path.as_ref().join("routes.txt");
+        if routes.is_file() {
+            for route in csv::Reader::from_path(routes)?.deserialize() {
+                let route: RawRoute = route?;
+                let value = Continuous {
+                    pickup: continuous(&route.continuous_pickup).unwrap_or(false),
+                    drop_off: continuous(&route.continuous_drop_off).unwrap_or(false),
+                };
+                if value != Continuous::default() {
+                    result.routes.insert(route.route_id, value);
+                }
+            }
+        }
+        let stop_times = path.as_ref().join("stop_times.txt");
+        if stop_times.is_file() {
+            for stop_time in csv::Reader::from_path(stop_times)?.deserialize() {
+                let stop_time: RawStopTime = stop_time?;
+                let value = (
+                    continuous(&stop_time.continuous_pickup),
+                    continuous(&stop_time.continuous_drop_off),
+                );
+                if value != (None, None) {
+                    result
+                        .stop_times
+                        .insert((stop_time.trip_id, stop_time.stop_sequence), value);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    // The stop times override the route
+    pub fn segment(&self, trip: &gtfs_structures::Trip, stop_sequence: u16) -> Continuous {
+        let route = self.routes.get(&trip.route_id).copied().unwrap_or_default();
+        match self.stop_times.get(&(trip.id.to_owned(), stop_sequence)) {
+            Some((pickup, drop_off)) => Continuous {
+                pickup: pickup.unwrap_or(route.pickup),
+                drop_off: drop_off.unwrap_or(route.drop_off),
+            },
+            None => route,
+        }
+    }
+}
+
+// The footpaths of transfers.txt, between different stops
+// Without a min_transfer_time, the transfer is walked as the crow flies
+#[derive(Debug, Default)]
+pub struct TransfersTxtProvider {
+    transfers: Vec<(String, String, Option<u32>)>,
+}
+
+impl TransfersTxtProvider {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, csv::Error> {
+        let path = path.as_ref().join("transfers.txt");
+        let mut transfers = Vec::new();
+        if path.is_file() {
+            for transfer in csv::Reader::from_path(path)?.deserialize() {
+                let transfer: RawTransfer = transfer?;
+                // 3 is a transfer that is not possible
+                if transfer.transfer_type != Some(3) && transfer.from_stop_id != transfer.to_stop_id
+                {
+                    transfers.push((
+                        transfer.from_stop_id,
+                        transfer.to_stop_id,
+                        transfer.min_transfer_time,
+                    ));
+                }
+            }
+        }
+        Ok(TransfersTxtProvider { transfers })
+    }
+}
+
+impl FootpathProvider for TransfersTxtProvider {
+    fn footpaths(&self, stops: &[Stop]) -> Vec<Vec<Footpath>> {
+        let indices: HashMap<&str, usize> = stops
+            .iter()
+            .enumerate()
+            .map(|(index, stop)| (stop.id.as_str(), index))
+            .collect();
+        let mut result = vec![Vec::new(); stops.len()];
+        for (from, to, duration) in &self.transfers {
+            if let (Some(&from), Some(&to)) = (indices.get(from.as_str()), indices.get(to.as_str()))
+            {
+                result[to].push(Footpath {
+                    from,
+                    duration: duration
+                        .unwrap_or_else(|| walking_duration(&stops[from], &stops[to])),
+                    stairs: false,
+                });
+            }
+        }
+        result
+    }
+}
+
+// The footpath providers of a comma-separated list, like "parent,radius:150,transfers"
+// The transfers are read in the GTFS folder
+pub fn footpath_providers<P: AsRef<Path>>(
+    spec: &str,
+    path: P,
+) -> Result<Vec<Box<dyn FootpathProvider>>, String> {
+    spec.split(',')
+        .map(|provider| -> Result<Box<dyn FootpathProvider>, String> {
+            match provider.trim() {
+                "parent" => Ok(Box::new(ParentStationProvider)),
+                "transfers" => match TransfersTxtProvider::read(&path) {
+                    Ok(provider) => Ok(Box::new(provider)),
+                    Err(e) => Err(format!("Could not read the transfers: {}", e)),
+                },
+                other => match other.strip_prefix("radius:").map(str::parse) {
+                    Some(Ok(radius)) => Ok(Box::new(RadiusProvider { radius })),
+                    Some(Err(_)) => Err(format!("Invalid radius in {}", other)),
+                    None => Err(format!("Unknown footpath provider {}", other)),
+                },
+            }
+        })
+        .collect()
+}
+
+// The active days of every service over the horizon, one bit per day
+// It is computed once per service instead of once per trip
+#[derive(Debug, Default)]
+pub struct ServiceDays {
+    days: HashMap<String, Vec<u64>>,
+}
+
+impl ServiceDays {
+    pub fn new(gtfs: &gtfs_structures::Gtfs, start_date: NaiveDate, horizon: u16) -> Self {
+        let words = usize::from(horizon).div_ceil(64);
+        let mut days: HashMap<String, Vec<u64>> = HashMap::new();
+        for (service_id, calendar) in &gtfs.calendar {
+            let bits = days
+                .entry(service_id.to_owned())
+                .or_insert_with(|| vec![0; words]);
+            for day in 0..horizon {
+                let date = start_date + Duration::days(i64::from(day));
+                if calendar.start_date <= date
+                    && date <= calendar.end_date
+                    && calendar.valid_weekday(date)
+                {
+                    bits[usize::from(day / 64)] |= 1 << (day % 64);
+                }
+            }
+        }
+        for (service_id, dates) in &gtfs.calendar_dates {
+            let bits = days
+                .entry(service_id.to_owned())
+                .or_insert_with(|| vec![0; words]);
+            for date in dates {
+                let day = date.date.signed_duration_since(start_date).num_days();
+                if day < 0 || day >= i64::from(horizon) {
+                    continue;
+                }
+                let (word, bit) = (day as usize / 64, 1 << (day % 64));
+                match date.exception_type {
+                    Exception::Added => bits[word] |= bit,
+                    Exception::Deleted => bits[word] &= !bit,
+                }
+            }
+        }
+        ServiceDays { days }
+    }
+
+    pub fn is_active(&self, service_id: &str, day: u16) -> bool {
+        self.days.get(service_id).is_some_and(|bits| {
+            bits.get(usize::from(day / 64))
+                .is_some_and(|word| word & (1 << (day % 64)) != 0)
+        })
+    }
+
+    // The days of the service, in increasing order
+    pub fn days<'a>(&'a self, service_id: &str) -> impl Iterator<Item = u16> + 'a {
+        let bits = self.days.get(service_id).map(Vec::as_slice).unwrap_or(&[]);
+        bits.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| (index * 64 + bit) as u16)
+        })
+    }
+}
+
+// The located stop points by cell of latitude and longitude, built once for all the segments
+struct StopGrid {
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+fn coord(stop: &Stop) -> Option<(f64, f64)> {
+    Some((stop.latitude?, stop.longitude?))
+}
+
+fn cell(coord: (f64, f64)) -> (i32, i32) {
+    (
+        (coord.0 / GRID_CELL).floor() as i32,
+        (coord.1 / GRID_CELL).floor() as i32,
+    )
+}
+
+impl StopGrid {
+    fn new(stops: &[Stop]) -> Self {
+        let mut cells: HashMap<_, Vec<_>> = HashMap::new();
+        for (index, stop) in stops.iter().enumerate() {
+            if stop.location_type == LocationType::StopPoint {
+                if let Some(coord) = coord(stop) {
+                    cells.entry(cell(coord)).or_default().push(index);
+                }
+            }
+        }
+        Self { cells }
+    }
+
+    // The stops in the bounding box of [a, b] widened by `margin` meters, by index
+    fn candidates(&self, a: (f64, f64), b: (f64, f64), margin: f64) -> Vec<usize> {
+        let latitude_margin = margin * DEGREES_PER_METER;
+        let longitude_margin = latitude_margin / a.0.to_radians().cos().max(0.01);
+        let min = cell((
+            a.0.min(b.0) - latitude_margin,
+            a.1.min(b.1) - longitude_margin,
+        ));
+        let max = cell((
+            a.0.max(b.0) + latitude_margin,
+            a.1.max(b.1) + longitude_margin,
+        ));
+        let mut result: Vec<_> = (min.0..=max.0)
+            .flat_map(|x| (min.1..=max.1).filter_map(move |y| self.cells.get(&(x, y))))
+            .flatten()
+            .copied()
+            .collect();
+        result.sort_unstable();
+        result
+    }
+}
+
+// The stops along the road between two stops, with their position (0 at `from`, 1 at `to`)
+fn stops_along(stops: &[Stop], grid: &StopGrid, from: usize, to: usize) -> Vec<(usize, f64)> {
+    let (a, b) = match (coord(&stops[from]), coord(&stops[to])) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return Vec::new(),
+    };
+    grid.candidates(a, b, CONTINUOUS_MAX_DISTANCE)
+        .into_iter()
+        .filter(|&index| index != from && index != to)
+        .filter_map(|index| {
+            let (d, position) = geo::distance_to_segment(coord(&stops[index])?, a, b)?;
+            if d <= CONTINUOUS_MAX_DISTANCE && position > 0. && position < 1. {
+                Some((index, position))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+impl From<gtfs_structures::LocationType> for LocationType {
+    fn from(location_type: gtfs_structures::LocationType) -> Self {
+        use gtfs_structures::LocationType::*;
+        match location_type {
+            StopPoint => LocationType::StopPoint,
+            StopArea => LocationType::StopArea,
+            StationEntrance => LocationType::StationEntrance,
+            GenericNode => LocationType::GenericNode,
+            BoardingArea => LocationType::BoardingArea,
+        }
+    }
+}
+
+impl From<gtfs_structures::RouteType> for RouteType {
+    fn from(route_type: gtfs_structures::RouteType) -> Self {
+        use gtfs_structures::RouteType::*;
+        match route_type {
+            Tramway => RouteType::Tramway,
+            Subway => RouteType::Subway,
+            Rail => RouteType::Rail,
+            Bus => RouteType::Bus,
+            Ferry => RouteType::Ferry,
+            CableCar => RouteType::CableCar,
+            Gondola => RouteType::Gondola,
+            Funicular => RouteType::Funicular,
+            Coach => RouteType::Coach,
+            Air => RouteType::Air,
+            Taxi => RouteType::Taxi,
+            Other(route_type) => RouteType::Other(route_type),
+        }
+    }
+}
+
+impl From<&std::sync::Arc<gtfs_structures::Stop>> for Stop {
+    fn from(stop: &std::sync::Arc<gtfs_structures::Stop>) -> Self {
+        Self {
+            id: stop.id.to_owned(),
+            name: stop.name.to_owned(),
+            parent_station: stop.parent_station.to_owned(),
+            location_type: stop.location_type.into(),
+            latitude: stop.latitude,
+            longitude: stop.longitude,
+            // gtfs-structures does not read it, see `read_fare_zones`
+            zone_id: None,
+        }
+    }
+}
+
+impl Route {
+    fn from(route: &gtfs_structures::Route, agencies: &[gtfs_structures::Agency]) -> Self {
+        // agency_id is optional when there is only one agency
+        let agency = match &route.agency_id {
+            Some(agency_id) => agencies.iter().find(|a| a.id.as_ref() == Some(agency_id)),
+            None => agencies.first(),
+        };
+        Self {
+            id: route.id.to_owned(),
+            short_name: route.short_name.to_owned(),
+            long_name: route.long_name.to_owned(),
+            route_type: route.route_type.into(),
+            agency_id: agency.and_then(|a| a.id.to_owned()),
+            agency_name: agency.map(|a| a.name.to_owned()),
+        }
+    }
+}
+
+impl Timetable {
+    pub fn from_gtfs(
+        gtfs: &gtfs_structures::Gtfs,
+        start_date_str: &str,
+        horizon: u16,
+    ) -> Timetable {
+        let continuous = ContinuousStops::default();
+        Timetable::from_gtfs_with_continuous(gtfs, &continuous, start_date_str, horizon)
+    }
+
+    // Like `from_gtfs`, without parsing the start date
+    pub fn from_gtfs_starting(
+        gtfs: &gtfs_structures::Gtfs,
+        start_date: NaiveDate,
+        horizon: u16,
+    ) -> Timetable {
+        Timetable::load_gtfs(gtfs, &ContinuousStops::default(), start_date, horizon)
+    }
+
+    // Hail-and-ride segments add connections from and to the stops along them
+    pub fn from_gtfs_with_continuous(
+        gtfs: &gtfs_structures::Gtfs,
+        continuous: &ContinuousStops,
+        start_date_str: &str,
+        horizon: u16,
+    ) -> Timetable {
+        let start_date = start_date_str
+            .parse::<NaiveDate>()
+            .unwrap_or_else(|e| panic!("Invalid start date {}: {}", start_date_str, e));
+        Timetable::load_gtfs(gtfs, continuous, start_date, horizon)
+    }
+
+    fn load_gtfs(
+        gtfs: &gtfs_structures::Gtfs,
+        continuous: &ContinuousStops,
+        start_date: NaiveDate,
+        horizon: u16,
+    ) -> Timetable {
+        let stops: Vec<_> = gtfs.stops.values().map(Stop::from).collect();
+
+        let stop_indices = stops
+            .iter()
+            .enumerate()
+            .map(|(index, stop)| (stop.id.to_owned(), index))
+            .collect();
+
+        let routes: Vec<_> = gtfs
+            .routes
+            .values()
+            .map(|r| Route::from(r, &gtfs.agencies))
+            .collect();
+
+        let route_indices: HashMap<_, _> = routes
+            .iter()
+            .enumerate()
+            .map(|(index, route)| (route.id.to_owned(), index))
+            .collect();
+
+        let timezone = gtfs
+            .agencies
+            .first()
+            .map(|agency| {
+                agency
+                    .timezone
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Invalid timezone {}", agency.timezone))
+            })
+            .unwrap_or(chrono_tz::UTC);
+
+        let now = Utc::now();
+        let mut trips = Vec::with_capacity(gtfs.trips.len() * horizon as usize);
+        // The trips of every day are contiguous, from the index of the first day
+        let mut trip_indices = HashMap::new();
+        for (trip_id, gtfs_trip) in &gtfs.trips {
+            trip_indices.insert(trip_id.as_str(), trips.len());
+            for day in 0..horizon {
+                trips.push(Trip {
+                    id: trip_id.to_owned(),
+                    day,
+                    route: route_indices.get(&gtfs_trip.route_id).copied(),
+                    // gtfs-structures does not read it, see `read_blocks`
+                    block_id: None,
+                });
+            }
+        }
+        let services = ServiceDays::new(gtfs, start_date, horizon);
+        let grid = StopGrid::new(&stops);
+        let connections = Timetable::connections(
+            gtfs,
+            continuous,
+            &stops,
+            &grid,
+            &services,
+            &stop_indices,
+            &trip_indices,
+        );
+        let transform_duration = Utc::now().signed_duration_since(now).num_milliseconds();
+
+        Timetable {
+            start_date,
+            timezone,
+            footpaths: Timetable::footpaths(&stops, &stop_indices),
+            stops,
+            connections,
+            transform_duration,
+            trips,
+            routes,
+            translations: Default::default(),
+            fare_rules: Vec::new(),
+        }
+    }
+
+    // Reads the stop and route names of translations.txt, only from a GTFS folder
+    // The translations are either for a record_id, or for all the records with a field_value
+    pub fn read_translations<P: AsRef<Path>>(&mut self, path: P) -> Result<(), csv::Error> {
+        let path = path.as_ref().join("translations.txt");
+        if !path.is_file() {
+            return Ok(());
+        }
+        for translation in csv::Reader::from_path(path)?.deserialize() {
+            let translation: RawTranslation = translation?;
+            let (records, names): (Vec<_>, _) = match (
+                translation.table_name.as_str(),
+                translation.field_name.as_str(),
+            ) {
+                ("stops", "stop_name") => (
+                    self.stops
+                        .iter()
+                        .map(|s| (s.id.as_str(), s.name.as_str()))
+                        .collect(),
+                    &mut self.translations.stop_names,
+                ),
+                ("routes", "route_short_name") => (
+                    self.routes
+                        .iter()
+                        .map(|r| (r.id.as_str(), r.short_name.as_str()))
+                        .collect(),
+                    &mut self.translations.route_short_names,
+                ),
+                ("routes", "route_long_name") => (
+                    self.routes
+                        .iter()
+                        .map(|r| (r.id.as_str(), r.long_name.as_str()))
+                        .collect(),
+                    &mut self.translations.route_long_names,
+                ),
+                _ => continue,
+            };
+            let indices: Vec<usize> = match (&translation.record_id, &translation.field_value) {
+                (Some(id), _) => records.iter().positions(|(i, _)| i == id).collect(),
+                (None, Some(value)) => records.iter().positions(|(_, v)| v == value).collect(),
+                (None, None) => continue,
+            };
+            let names = names.entry(translation.language).or_default();
+            for index in indices {
+                names.insert(index, translation.translation.to_owned());
+            }
+        }
+        Ok(())
+    }
+
+    // The zone_id of stops.txt and the rules of fare_rules.txt, only from a GTFS folder
+    // The rules of an unknown route are ignored
+    pub fn read_fare_zones<P: AsRef<Path>>(&mut self, path: P) -> Result<(), csv::Error> {
+        let stops = path.as_ref().join("stops.txt");
+        if stops.is_file() {
+            let indices: HashMap<_, _> = self
+                .stops
+                .iter()
+                .enumerate()
+                .map(|(index, stop)| (stop.id.to_owned(), index))
+                .collect();
+            for stop in csv::Reader::from_path(stops)?.deserialize() {
+                let stop: RawStopZone = stop?;
+                if let Some(&index) = indices.get(&stop.stop_id) {
+                    self.stops[index].zone_id = stop.zone_id;
+                }
+            }
+        }
+
+        let fare_rules = path.as_ref().join("fare_rules.txt");
+        if fare_rules.is_file() {
+            for rule in csv::Reader::from_path(fare_rules)?.deserialize() {
+                let rule: RawFareRule = rule?;
+                let route = match &rule.route_id {
+                    Some(id) => match self.routes.iter().position(|r| &r.id == id) {
+                        Some(route) => Some(route),
+                        None => continue,
+                    },
+                    None => None,
+                };
+                self.fare_rules.push(FareRule {
+                    fare_id: rule.fare_id,
+                    route,
+                    origin_zone: rule.origin_id,
+                    destination_zone: rule.destination_id,
+                    contains_zone: rule.contains_id,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // The block_id of trips.txt, only from a GTFS folder
+    pub fn read_blocks<P: AsRef<Path>>(&mut self, path: P) -> Result<(), csv::Error> {
+        let path = path.as_ref().join("trips.txt");
+        if !path.is_file() {
+            return Ok(());
+        }
+        let mut blocks = HashMap::new();
+        for trip in csv::Reader::from_path(path)?.deserialize() {
+            let trip: RawTripBlock = trip?;
+            if let Some(block_id) = trip.block_id {
+                blocks.insert(trip.trip_id, block_id);
+            }
+        }
+        for trip in &mut self.trips {
+            trip.block_id = blocks.get(&trip.id).cloned();
+        }
+        Ok(())
+    }
+
+    fn connections(
+        gtfs: &gtfs_structures::Gtfs,
+        continuous: &ContinuousStops,
+        stops: &[Stop],
+        grid: &StopGrid,
+        services: &ServiceDays,
+        stop_indices: &HashMap<String, usize>,
+        trip_indices: &HashMap<&str, usize>,
+    ) -> Vec<Connection> {
+        // The trips are independent, their connections are generated in parallel
+        let mut result: Vec<Connection> = gtfs
+            .trips
+            .par_iter()
+            .flat_map_iter(|(trip_id, gtfs_trip)| {
+                let mut connections = Vec::new();
+                let days: Vec<u16> = services.days(&gtfs_trip.service_id).collect();
+                let first_trip = trip_indices[trip_id.as_str()];
+                let mut last_arrival = None;
+
+                for (departure, arrival) in gtfs_trip.stop_times.iter().tuple_windows() {
+                    let dep_time = departure.departure_time.unwrap_or_else(|| {
+                        last_arrival.unwrap_or_else(|| {
+                            panic!("First departure without time on trip {}", trip_id)
+                        })
+                    });
+
+                    let arr_time = arrival.arrival_time.unwrap_or(dep_time);
+                    last_arrival = Some(arr_time);
+                    let dep_stop = *stop_indices
+                        .get(&departure.stop.id)
+                        .unwrap_or_else(|| panic!("Unknown stop id {}", departure.stop.id));
+
+                    let arr_stop = *stop_indices
+                        .get(&arrival.stop.id)
+                        .unwrap_or_else(|| panic!("Unknown stop id {}", arrival.stop.id));
+
+                    // The extra connections boarding or alighting along the segment
+                    let mut segment = vec![(dep_stop, dep_time, arr_stop, arr_time)];
+                    let hail_and_ride = continuous.segment(gtfs_trip, departure.stop_sequence);
+                    if hail_and_ride != Continuous::default() {
+                        for (stop, position) in stops_along(stops, grid, dep_stop, arr_stop) {
+                            let time =
+                                dep_time + (f64::from(arr_time - dep_time) * position) as u32;
+                            if hail_and_ride.pickup {
+                                segment.push((stop, time, arr_stop, arr_time));
+                            }
+                            if hail_and_ride.drop_off {
+                                segment.push((dep_stop, dep_time, stop, time));
+                            }
+                        }
+                    }
+
+                    for &day in &days {
+                        let trip = first_trip + usize::from(day);
+                        let offset = u32::from(day) * 24 * 60 * 60;
+                        for &(dep_stop, dep_time, arr_stop, arr_time) in &segment {
+                            connections.push(Connection {
+                                trip,
+                                dep_time: dep_time + offset,
+                                arr_time: arr_time + offset,
+                                dep_stop,
+                                arr_stop,
+                            });
+                        }
+                    }
+                }
+                connections
+            })
+            .collect();
+
+        // We want the connections by decreasing departure time
+        result.par_sort_by_key(|c| std::cmp::Reverse((c.dep_time, c.arr_time)));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_gtfs() {
+        let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
+        let timetable = Timetable::from_gtfs(&gtfs, "2017-1-1", 10);
+        assert_eq!(5, timetable.stops.len());
+        assert_eq!(2, timetable.connections.len());
+        assert_eq!(5, timetable.footpaths.len());
+        for i in 0..timetable.stops.len() {
+            match timetable.stops[i].id.as_str() {
+                // The sibling stop point and the station
+                "stop3" | "stop5" => assert_eq!(timetable.footpaths[i].len(), 2),
+                // The station is linked to both its stop points
+                "stop1" => assert_eq!(timetable.footpaths[i].len(), 2),
+                _ => assert!(timetable.footpaths[i].is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn transfers() {
+        let dir = std::env::temp_dir().join(format!("csa_transfers_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("transfers.txt"),
+            "from_stop_id,to_stop_id,transfer_type,min_transfer_time\n\
+             stop2,stop4,2,120\n\
+             stop4,stop2,0,\n\
+             stop2,stop5,3,\n\
+             stop2,unknown,2,60\n",
+        )
+        .unwrap();
+
+        let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
+        let mut timetable = Timetable::from_gtfs(&gtfs, "2017-1-1", 10);
+        let providers = footpath_providers("parent,transfers", &dir).unwrap();
+        let providers: Vec<_> = providers.iter().map(|p| p.as_ref()).collect();
+        timetable.set_footpaths(&providers);
+        let stop = |id| timetable.stops.iter().position(|s| s.id == id).unwrap();
+        assert_eq!(
+            Some(120),
+            timetable.footpath_duration(stop("stop2"), stop("stop4"))
+        );
+        assert!(timetable
+            .footpath_duration(stop("stop4"), stop("stop2"))
+            .is_some());
+        assert_eq!(
+            None,
+            timetable.footpath_duration(stop("stop2"), stop("stop5"))
+        );
+        assert_eq!(
+            Some(5),
+            timetable.footpath_duration(stop("stop3"), stop("stop5"))
+        );
+
+        assert!(footpath_providers("parent,radius:abc", &dir).is_err());
+        assert!(footpath_providers("walk", &dir).is_err());
+        assert_eq!(
+            2,
+            footpath_providers("radius:100, parent", &dir)
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn service_days() {
+        let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
+        let start = NaiveDate::from_ymd_opt(2017, 1, 1).unwrap();
+        // Over several words, the calendar ends on the 15th
+        let services = ServiceDays::new(&gtfs, start, 100);
+        for service in &["service1", "service2", "unknown"] {
+            assert_eq!(
+                gtfs.trip_days(service, start),
+                services.days(service).collect::<Vec<_>>()
+            );
+        }
+        // The 1st is removed, the 7th is a saturday
+        assert!(!services.is_active("service1", 0));
+        assert!(services.is_active("service1", 6));
+        assert!(!services.is_active("service1", 99));
+        assert!(services.is_active("service2", 0));
+
+        // Nothing beyond the horizon
+        let services = ServiceDays::new(&gtfs, start, 7);
+        assert_eq!(vec![6], services.days("service1").collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn continuous_pickup() {
+        let dir =
+            std::env::temp_dir().join(format!("csa_continuous_pickup_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in &[
+            "agency.txt",
+            "calendar.txt",
+            "calendar_dates.txt",
+            "routes.txt",
+            "trips.txt",
+        ] {
+            std::fs::copy(Path::new("fixtures").join(file), dir.join(file)).unwrap();
+        }
+        // stop4 is on the road between stop2 and stop3, stop5 is far away
+        std::fs::write(
+            dir.join("stops.txt"),
+            "stop_id,stop_name,stop_lat,stop_lon,location_type,parent_station\n\
+             stop1,Stop Area,48.8,2.35,1,\n\
+             stop2,StopPoint,48.8,2.35,,\n\
+             stop3,Stop Point child of 1,48.81,2.35,0,stop1\n\
+             stop4,StopPoint2,48.8025,2.3501,,\n\
+             stop5,Stop Point child of 1 bis,48.9,2.35,0,stop1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("stop_times.txt"),
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence,continuous_pickup\n\
+             trip1,14:00:00,14:00:00,stop2,0,0\n\
+             trip1,15:00:00,15:00:00,stop3,1,\n",
+        )
+        .unwrap();
+
+        let gtfs = gtfs_structures::Gtfs::new(dir.to_str().unwrap()).unwrap();
+        let continuous = ContinuousStops::read(&dir).unwrap();
+        let timetable = Timetable::from_gtfs_with_continuous(&gtfs, &continuous, "2017-1-1", 10);
+        // For each of the two days, the pickup at stop4 a quarter of the way
+        assert_eq!(4, timetable.connections.len());
+        let stop4 = timetable
+            .stops
+            .iter()
+            .position(|s| s.id == "stop4")
+            .unwrap();
+        let pickup = timetable
+            .connections
+            .iter()
+            .find(|c| c.dep_stop == stop4)
+            .unwrap();
+        assert_eq!(14 * 3600 + 15 * 60, pickup.dep_time % 86_400);
+        assert_eq!(15 * 3600, pickup.arr_time % 86_400);
+
+        let timetable = Timetable::from_gtfs(&gtfs, "2017-1-1", 10);
+        assert_eq!(2, timetable.connections.len());
+        assert!(ContinuousStops::read("fixtures/")
+            .unwrap()
+            .stop_times
+            .is_empty());
+    }
+
+    #[test]
+    fn translations() {
+        let dir = std::env::temp_dir().join(format!("csa_translations_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("translations.txt"),
+            "table_name,field_name,language,translation,record_id,field_value\n\
+             stops,stop_name,fr,Zone d'arrêt,stop1,\n\
+             stops,stop_name,de,Haltestelle,,StopPoint\n\
+             routes,route_long_name,fr,Cent,1,\n\
+             routes,stop_name,fr,Ignored,1,\n",
+        )
+        .unwrap();
+
+        let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
+        let mut timetable = Timetable::from_gtfs(&gtfs, "2017-1-1", 10);
+        timetable.read_translations(&dir).unwrap();
+        let stop = |id| timetable.stops.iter().position(|s| s.id == id).unwrap();
+        assert_eq!(
+            "Zone d'arrêt",
+            timetable.stop_name(stop("stop1"), Some("fr"))
+        );
+        assert_eq!("Stop Area", timetable.stop_name(stop("stop1"), Some("de")));
+        assert_eq!(
+            "Haltestelle",
+            timetable.stop_name(stop("stop2"), Some("de"))
+        );
+        assert_eq!("Cent", timetable.route_long_name(0, Some("fr")));
+        assert_eq!("100", timetable.route_short_name(0, Some("fr")));
+        assert_eq!(vec!["de", "fr"], timetable.translations.languages());
+    }
+
+    #[test]
+    fn fare_zones() {
+        let dir = std::env::temp_dir().join(format!("csa_fare_zones_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("stops.txt"),
+            "stop_id,stop_name,zone_id\n\
+             stop2,StopPoint,1\n\
+             stop3,Stop Point child of 1,2\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("fare_rules.txt"),
+            "fare_id,route_id,origin_id,destination_id,contains_id\n\
+             short,1,1,1,\n\
+             long,,1,3,2\n\
+             unknown,9,1,1,\n",
+        )
+        .unwrap();
+
+        let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
+        let mut timetable = Timetable::from_gtfs(&gtfs, "2017-1-1", 10);
+        timetable.read_fare_zones(&dir).unwrap();
+        let stop = |id| timetable.stops.iter().position(|s| s.id == id).unwrap();
+        assert_eq!(Some("1"), timetable.stops[stop("stop2")].zone_id.as_deref());
+        assert_eq!(None, timetable.stops[stop("stop4")].zone_id);
+        assert_eq!(2, timetable.fare_rules.len());
+        assert_eq!(Some(0), timetable.fare_rules[0].route);
+        assert_eq!(vec!["1", "2", "3"], timetable.zones());
+    }
+
+    #[test]
+    fn stops_along_segment() {
+        let stop = |latitude, longitude| Stop {
+            latitude: Some(latitude),
+            longitude: Some(longitude),
+            ..Default::default()
+        };
+        // A segment going east across cells of the grid
+        let stops = vec![
+            stop(48.8595, 2.3395),
+            stop(48.8595, 2.3605),
+            // 55 m north of the road, in the cell above the segment
+            stop(48.86, 2.35),
+            // 110 m south
+            stop(48.8585, 2.345),
+            // 1 km after the end, in a cell the grid does not look at
+            stop(48.8595, 2.3705),
+            Stop {
+                location_type: LocationType::StopArea,
+                ..stop(48.8595, 2.35)
+            },
+        ];
+        let grid = StopGrid::new(&stops);
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            grid.candidates(coord(&stops[0]).unwrap(), coord(&stops[1]).unwrap(), 100.)
+        );
+        let along = stops_along(&stops, &grid, 0, 1);
+        assert_eq!(1, along.len());
+        assert_eq!(2, along[0].0);
+        assert!((along[0].1 - 0.5).abs() < 0.01);
+    }
+}
@@ -0,0 +1,363 @@
+// Writes the timetable as it is routed (after the filters, merges and patches) as a GTFS folder
+// The instances of a trip with the same stop times on several days become a single GTFS trip
+// Only the stop times are written: the hail-and-ride connections along a segment are lost
+use crate::analysis::csv_field;
+use crate::structures::{Connection, LocationType, RouteType, Timetable};
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+// For the trips and routes without agency or route, GTFS needs one
+const DEFAULT_ID: &str = "default";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct StopTime {
+    stop: usize,
+    // Since midnight of the service day
+    arrival: u32,
+    departure: u32,
+}
+
+// Every day the trip runs with the same stop times
+struct ExportedTrip<'a> {
+    id: &'a str,
+    route: Option<usize>,
+    block_id: Option<&'a str>,
+    stop_times: Vec<StopTime>,
+    days: Vec<u16>,
+}
+
+fn location_type(location_type: LocationType) -> u8 {
+    match location_type {
+        LocationType::StopPoint => 0,
+        LocationType::StopArea => 1,
+        LocationType::StationEntrance => 2,
+        LocationType::GenericNode => 3,
+        LocationType::BoardingArea => 4,
+    }
+}
+
+fn route_type(route_type: RouteType) -> u16 {
+    match route_type {
+        RouteType::Tramway => 0,
+        RouteType::Subway => 1,
+        RouteType::Rail => 2,
+        RouteType::Bus => 3,
+        RouteType::Ferry => 4,
+        RouteType::CableCar => 5,
+        RouteType::Gondola => 6,
+        RouteType::Funicular => 7,
+        RouteType::Coach => 200,
+        RouteType::Air => 1100,
+        RouteType::Taxi => 1500,
+        RouteType::Other(route_type) => route_type,
+    }
+}
+
+fn format_time(time: u32) -> String {
+    format!("{:02}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60)
+}
+
+fn optional(value: Option<impl ToString>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+// Follows the trip from its first departure, always taking the next departure from the stop
+// it arrived at, and the connection going the furthest from a same stop (the others alight
+// along a hail-and-ride segment)
+fn stop_times(mut connections: Vec<&Connection>, offset: u32) -> Vec<StopTime> {
+    connections.sort_by_key(|c| (c.dep_time, std::cmp::Reverse(c.arr_time)));
+    let mut result: Vec<StopTime> = Vec::new();
+    for c in connections {
+        let (dep_time, arr_time) = (c.dep_time - offset, c.arr_time - offset);
+        match result.last_mut() {
+            None => result.push(StopTime {
+                stop: c.dep_stop,
+                arrival: dep_time,
+                departure: dep_time,
+            }),
+            Some(last) if last.stop == c.dep_stop && last.arrival <= dep_time => {
+                last.departure = dep_time
+            }
+            Some(_) => continue,
+        }
+        result.push(StopTime {
+            stop: c.arr_stop,
+            arrival: arr_time,
+            departure: arr_time,
+        });
+    }
+    result
+}
+
+impl Timetable {
+    fn exported_trips(&self) -> Vec<ExportedTrip<'_>> {
+        let mut by_trip = vec![Vec::new(); self.trips.len()];
+        for c in &self.connections {
+            by_trip[c.trip].push(c);
+        }
+        let mut result: Vec<ExportedTrip> = Vec::new();
+        let mut indices = HashMap::new();
+        for (trip, connections) in self.trips.iter().zip(by_trip) {
+            if connections.is_empty() {
+                continue;
+            }
+            let stop_times = stop_times(connections, u32::from(trip.day) * SECONDS_PER_DAY);
+            let key = (
+                trip.id.as_str(),
+                trip.route,
+                trip.block_id.as_deref(),
+                stop_times,
+            );
+            match indices.get(&key) {
+                Some(&index) => {
+                    let exported: &mut ExportedTrip = &mut result[index];
+                    exported.days.push(trip.day);
+                }
+                None => {
+                    indices.insert(key.clone(), result.len());
+                    result.push(ExportedTrip {
+                        id: key.0,
+                        route: key.1,
+                        block_id: key.2,
+                        stop_times: key.3,
+                        days: vec![trip.day],
+                    });
+                }
+            }
+        }
+        result
+    }
+
+    // Writes agency, stops, routes, trips, stop_times, calendar_dates and transfers (the footpaths)
+    // The trip ids are suffixed when a trip does not have the same stop times every day
+    pub fn write_gtfs<P: AsRef<Path>>(&self, dir: P) -> std::io::Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let create = |file: &str| -> std::io::Result<_> {
+            Ok(BufWriter::new(std::fs::File::create(dir.join(file))?))
+        };
+        let trips = self.exported_trips();
+        let without_route = trips.iter().any(|trip| trip.route.is_none());
+
+        // The timetable does not know the urls of the agencies
+        let mut agencies = Vec::new();
+        for route in &self.routes {
+            let id = route.agency_id.as_deref().unwrap_or(DEFAULT_ID);
+            if !agencies.iter().any(|(other, _)| *other == id) {
+                agencies.push((id, route.agency_name.as_deref().unwrap_or(id)));
+            }
+        }
+        if without_route && !agencies.iter().any(|(id, _)| *id == DEFAULT_ID) {
+            agencies.push((DEFAULT_ID, DEFAULT_ID));
+        }
+        let mut writer = create("agency.txt")?;
+        writeln!(writer, "agency_id,agency_name,agency_url,agency_timezone")?;
+        for (id, name) in agencies {
+            writeln!(
+                writer,
+                "{},{},,{}",
+                csv_field(id),
+                csv_field(name),
+                self.timezone.name()
+            )?;
+        }
+        writer.flush()?;
+
+        let mut writer = create("stops.txt")?;
+        writeln!(
+            writer,
+            "stop_id,stop_name,stop_lat,stop_lon,location_type,parent_station,zone_id"
+        )?;
+        for stop in &self.stops {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                csv_field(&stop.id),
+                csv_field(&stop.name),
+                optional(stop.latitude),
+                optional(stop.longitude),
+                location_type(stop.location_type),
+                csv_field(stop.parent_station.as_deref().unwrap_or_default()),
+                csv_field(stop.zone_id.as_deref().unwrap_or_default())
+            )?;
+        }
+        writer.flush()?;
+
+        let mut writer = create("routes.txt")?;
+        writeln!(
+            writer,
+            "route_id,agency_id,route_short_name,route_long_name,route_type"
+        )?;
+        for route in &self.routes {
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                csv_field(&route.id),
+                csv_field(route.agency_id.as_deref().unwrap_or(DEFAULT_ID)),
+                csv_field(&route.short_name),
+                csv_field(&route.long_name),
+                route_type(route.route_type)
+            )?;
+        }
+        if without_route {
+            writeln!(
+                writer,
+                "{},{},{},,{}",
+                DEFAULT_ID,
+                DEFAULT_ID,
+                DEFAULT_ID,
+                route_type(RouteType::Bus)
+            )?;
+        }
+        writer.flush()?;
+
+        let mut services: HashMap<&[u16], String> = HashMap::new();
+        let mut calendar_dates = create("calendar_dates.txt")?;
+        writeln!(calendar_dates, "service_id,date,exception_type")?;
+        let mut trips_writer = create("trips.txt")?;
+        writeln!(trips_writer, "route_id,service_id,trip_id,block_id")?;
+        let mut stop_times = create("stop_times.txt")?;
+        writeln!(
+            stop_times,
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence"
+        )?;
+        let mut variants: HashMap<&str, usize> = HashMap::new();
+        for trip in &trips {
+            let service = match services.get(trip.days.as_slice()) {
+                Some(service) => service.to_owned(),
+                None => {
+                    let service = format!("service{}", services.len() + 1);
+                    for &day in &trip.days {
+                        let date = self.start_date + chrono::Duration::days(i64::from(day));
+                        writeln!(calendar_dates, "{},{},1", service, date.format("%Y%m%d"))?;
+                    }
+                    services.insert(&trip.days, service.to_owned());
+                    service
+                }
+            };
+
+            let variant = variants.entry(trip.id).or_default();
+            *variant += 1;
+            let id = match *variant {
+                1 => trip.id.to_owned(),
+                variant => format!("{}:{}", trip.id, variant),
+            };
+            let route = trip.route.map(|r| self.routes[r].id.as_str());
+            writeln!(
+                trips_writer,
+                "{},{},{},{}",
+                csv_field(route.unwrap_or(DEFAULT_ID)),
+                service,
+                csv_field(&id),
+                csv_field(trip.block_id.unwrap_or_default())
+            )?;
+            for (sequence, stop_time) in trip.stop_times.iter().enumerate() {
+                writeln!(
+                    stop_times,
+                    "{},{},{},{},{}",
+                    csv_field(&id),
+                    format_time(stop_time.arrival),
+                    format_time(stop_time.departure),
+                    csv_field(&self.stops[stop_time.stop].id),
+                    sequence
+                )?;
+            }
+        }
+        calendar_dates.flush()?;
+        trips_writer.flush()?;
+        stop_times.flush()?;
+
+        let mut writer = create("transfers.txt")?;
+        writeln!(
+            writer,
+            "from_stop_id,to_stop_id,transfer_type,min_transfer_time"
+        )?;
+        for (to, footpaths) in self.footpaths.iter().enumerate() {
+            for footpath in footpaths.iter().filter(|f| f.from != to) {
+                writeln!(
+                    writer,
+                    "{},{},2,{}",
+                    csv_field(&self.stops[footpath.from].id),
+                    csv_field(&self.stops[to].id),
+                    footpath.duration
+                )?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hail_and_ride_stop_times() {
+        let c = |dep_stop, dep_time, arr_stop, arr_time| Connection {
+            trip: 0,
+            dep_time,
+            arr_time,
+            dep_stop,
+            arr_stop,
+        };
+        // From 0 to 1, alighting along the way at 2 or boarding at 3
+        let connections = [
+            c(0, 100, 2, 120),
+            c(3, 130, 1, 160),
+            c(0, 100, 1, 160),
+            c(1, 170, 4, 200),
+        ];
+        let stops: Vec<_> = stop_times(connections.iter().collect(), 100)
+            .iter()
+            .map(|s| (s.stop, s.arrival, s.departure))
+            .collect();
+        assert_eq!(vec![(0, 0, 0), (1, 60, 70), (4, 100, 100)], stops);
+    }
+
+    #[cfg(feature = "gtfs")]
+    #[test]
+    fn round_trip() {
+        let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
+        let mut timetable = Timetable::from_gtfs(&gtfs, "2017-1-1", 10);
+        // A trip running later on one day
+        let trips = &timetable.trips;
+        let late = timetable
+            .connections
+            .iter_mut()
+            .find(|c| trips[c.trip].day == 6)
+            .unwrap();
+        late.dep_time += 60;
+        late.arr_time += 60;
+
+        let dir = std::env::temp_dir().join(format!("csa_gtfs_export_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        timetable.write_gtfs(&dir).unwrap();
+        let trips = std::fs::read_to_string(dir.join("trips.txt")).unwrap();
+        assert_eq!(3, trips.lines().count());
+        assert!(trips.contains("trip1:2"));
+
+        let exported = gtfs_structures::Gtfs::new(dir.to_str().unwrap()).unwrap();
+        let reimported = Timetable::from_gtfs(&exported, "2017-1-1", 10);
+        let times = |t: &Timetable| {
+            let mut times: Vec<_> = t
+                .connections
+                .iter()
+                .map(|c| {
+                    let (dep, arr) = (&t.stops[c.dep_stop].id, &t.stops[c.arr_stop].id);
+                    (dep.to_owned(), arr.to_owned(), c.dep_time, c.arr_time)
+                })
+                .collect();
+            times.sort();
+            times
+        };
+        assert_eq!(times(&timetable), times(&reimported));
+        assert_eq!(timetable.stops.len(), reimported.stops.len());
+        let child = reimported.stop_index_by_id("stop3").unwrap();
+        assert_eq!(
+            Some("stop1"),
+            reimported.stops[child].parent_station.as_deref()
+        );
+    }
+}
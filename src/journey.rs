@@ -0,0 +1,475 @@
+use crate::geo;
+use crate::query::QueryOptions;
+use crate::structures::{Connection, Timetable};
+use chrono::{Duration, NaiveDateTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegKind {
+    Vehicle { trip: usize },
+    Walk,
+}
+
+// A part of a journey, either seated in a single trip or walking between two stops
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leg {
+    pub kind: LegKind,
+    pub from: usize,
+    pub to: usize,
+    pub dep_time: u32,
+    pub arr_time: u32,
+    // In meters, as the crow flies between the stops of the leg
+    // None when a stop has no coordinates
+    pub distance: Option<u32>,
+    // The vehicle leg continues the previous one in the same vehicle (the trips share a block)
+    // Going from one to the other is not a transfer
+    pub stay_seated: bool,
+}
+
+fn distance(timetable: &Timetable, from: usize, to: usize) -> Option<f64> {
+    let coord = |stop: &crate::structures::Stop| Some((stop.latitude?, stop.longitude?));
+    Some(geo::distance(
+        coord(&timetable.stops[from])?,
+        coord(&timetable.stops[to])?,
+    ))
+}
+
+// Two trips run one after the other by the same vehicle
+fn same_block(timetable: &Timetable, a: usize, b: usize) -> bool {
+    let (a, b) = (&timetable.trips[a], &timetable.trips[b]);
+    a.day == b.day && a.block_id.is_some() && a.block_id == b.block_id
+}
+
+fn meters(d: Option<f64>) -> Option<u32> {
+    d.map(|d| d.round() as u32)
+}
+
+impl Leg {
+    pub fn duration(&self) -> u32 {
+        self.arr_time - self.dep_time
+    }
+
+    pub fn is_walk(&self) -> bool {
+        self.kind == LegKind::Walk
+    }
+
+    pub fn departure(&self, timetable: &Timetable) -> NaiveDateTime {
+        timetable.datetime(self.dep_time)
+    }
+
+    pub fn arrival(&self, timetable: &Timetable) -> NaiveDateTime {
+        timetable.datetime(self.arr_time)
+    }
+}
+
+fn seconds(seconds: u32) -> Duration {
+    Duration::seconds(i64::from(seconds))
+}
+
+// A journey with dates and durations instead of the offsets in seconds of the timetable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub departure: NaiveDateTime,
+    pub arrival: NaiveDateTime,
+    pub duration: Duration,
+    pub transit_time: Duration,
+    pub walking_time: Duration,
+    pub transfers: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Journey {
+    pub legs: Vec<Leg>,
+}
+
+impl Journey {
+    // Rebuilds the legs from the connections of a route (as given by `Profile::route`)
+    // The walking legs are deduced from the footpaths between consecutive connections
+    pub fn new(
+        timetable: &Timetable,
+        origin: usize,
+        connections: &[&Connection],
+        destinations: &[usize],
+    ) -> Self {
+        let options = QueryOptions::default();
+        Self::with_options(timetable, origin, connections, destinations, &options)
+    }
+
+    // The walking durations must be the ones used to compute the route
+    pub fn with_options(
+        timetable: &Timetable,
+        origin: usize,
+        connections: &[&Connection],
+        destinations: &[usize],
+        options: &QueryOptions,
+    ) -> Self {
+        let mut legs = Vec::new();
+        let mut connections = connections.iter().peekable();
+
+        if let Some(first) = connections.peek() {
+            if first.dep_stop != origin {
+                let duration = options
+                    .footpath_duration(timetable, origin, first.dep_stop)
+                    .unwrap_or(0);
+                legs.push(Leg {
+                    kind: LegKind::Walk,
+                    from: origin,
+                    to: first.dep_stop,
+                    dep_time: first.dep_time - duration,
+                    arr_time: first.dep_time,
+                    distance: meters(distance(timetable, origin, first.dep_stop)),
+                    stay_seated: false,
+                });
+            }
+        }
+
+        while let Some(first) = connections.next() {
+            let mut last = first;
+            // Following the intermediate stops
+            let mut leg_distance = distance(timetable, first.dep_stop, first.arr_stop);
+            while let Some(next) = connections.peek() {
+                if next.trip != first.trip || next.dep_stop != last.arr_stop {
+                    break;
+                }
+                last = connections.next().unwrap();
+                leg_distance = leg_distance
+                    .and_then(|d| Some(d + distance(timetable, last.dep_stop, last.arr_stop)?));
+            }
+            let stay_seated = match legs.last() {
+                Some(Leg {
+                    kind: LegKind::Vehicle { trip },
+                    to,
+                    ..
+                }) => *to == first.dep_stop && same_block(timetable, *trip, first.trip),
+                _ => false,
+            };
+            legs.push(Leg {
+                kind: LegKind::Vehicle { trip: first.trip },
+                from: first.dep_stop,
+                to: last.arr_stop,
+                dep_time: first.dep_time,
+                arr_time: last.arr_time,
+                distance: meters(leg_distance),
+                stay_seated,
+            });
+
+            if let Some(next) = connections.peek() {
+                if next.dep_stop != last.arr_stop {
+                    let duration = options
+                        .footpath_duration(timetable, last.arr_stop, next.dep_stop)
+                        .unwrap_or(0);
+                    legs.push(Leg {
+                        kind: LegKind::Walk,
+                        from: last.arr_stop,
+                        to: next.dep_stop,
+                        dep_time: last.arr_time,
+                        arr_time: last.arr_time + duration,
+                        distance: meters(distance(timetable, last.arr_stop, next.dep_stop)),
+                        stay_seated: false,
+                    });
+                }
+            } else if !destinations.contains(&last.arr_stop) {
+                // We walk to the closest destination
+                let final_walk = destinations
+                    .iter()
+                    .filter_map(|&d| {
+                        options
+                            .footpath_duration(timetable, last.arr_stop, d)
+                            .map(|duration| (duration, d))
+                    })
+                    .min();
+                if let Some((duration, destination)) = final_walk {
+                    legs.push(Leg {
+                        kind: LegKind::Walk,
+                        from: last.arr_stop,
+                        to: destination,
+                        dep_time: last.arr_time,
+                        arr_time: last.arr_time + duration,
+                        distance: meters(distance(timetable, last.arr_stop, destination)),
+                        stay_seated: false,
+                    });
+                }
+            }
+        }
+
+        Self { legs }
+    }
+
+    // None for a journey without legs, when the origin is a destination
+    pub fn summary(&self, timetable: &Timetable) -> Option<Summary> {
+        Some(Summary {
+            departure: timetable.datetime(self.dep_time()?),
+            arrival: timetable.datetime(self.arr_time()?),
+            duration: seconds(self.duration()),
+            transit_time: seconds(self.transit_time()),
+            walking_time: seconds(self.walking_time()),
+            transfers: self.transfers(),
+        })
+    }
+
+    pub fn dep_time(&self) -> Option<u32> {
+        self.legs.first().map(|leg| leg.dep_time)
+    }
+
+    pub fn arr_time(&self) -> Option<u32> {
+        self.legs.last().map(|leg| leg.arr_time)
+    }
+
+    pub fn vehicle_legs(&self) -> impl Iterator<Item = &Leg> {
+        self.legs.iter().filter(|leg| !leg.is_walk())
+    }
+
+    pub fn duration(&self) -> u32 {
+        match (self.dep_time(), self.arr_time()) {
+            (Some(dep), Some(arr)) => arr - dep,
+            _ => 0,
+        }
+    }
+
+    // The trips of the vehicle legs, in order
+    pub fn trips(&self) -> Vec<usize> {
+        self.vehicle_legs()
+            .filter_map(|leg| match leg.kind {
+                LegKind::Vehicle { trip } => Some(trip),
+                LegKind::Walk => None,
+            })
+            .collect()
+    }
+
+    // Staying seated in a vehicle that continues as another trip is not a transfer
+    pub fn transfers(&self) -> usize {
+        self.vehicle_legs()
+            .filter(|leg| !leg.stay_seated)
+            .count()
+            .saturating_sub(1)
+    }
+
+    pub fn walking_time(&self) -> u32 {
+        self.legs
+            .iter()
+            .filter(|leg| leg.is_walk())
+            .map(Leg::duration)
+            .sum()
+    }
+
+    // In meters, the walking legs without a distance are ignored
+    pub fn walking_distance(&self) -> u32 {
+        self.legs
+            .iter()
+            .filter(|leg| leg.is_walk())
+            .filter_map(|leg| leg.distance)
+            .sum()
+    }
+
+    pub fn transit_time(&self) -> u32 {
+        self.vehicle_legs().map(Leg::duration).sum()
+    }
+
+    // The stops the journey goes through, in order, including the ones where the vehicles do not stop
+    // The intermediate stops are the ones of the timetable connections, not of a realtime view
+    pub fn stops(&self, timetable: &Timetable) -> Vec<usize> {
+        let mut stops = Vec::new();
+        for leg in &self.legs {
+            stops.push(leg.from);
+            if let LegKind::Vehicle { trip } = leg.kind {
+                // Sorted by decreasing departure time
+                let connections = &timetable.connections;
+                let start = connections.partition_point(|c| c.dep_time >= leg.arr_time);
+                let end = connections.partition_point(|c| c.dep_time >= leg.dep_time);
+                stops.extend(
+                    connections[start..end]
+                        .iter()
+                        .rev()
+                        .filter(|c| c.trip == trip && c.arr_time < leg.arr_time)
+                        .map(|c| c.arr_stop),
+                );
+            }
+            stops.push(leg.to);
+        }
+        stops.dedup();
+        stops
+    }
+
+    // The fare zones traversed, in order, the stops without a zone are ignored
+    pub fn zones<'a>(&self, timetable: &'a Timetable) -> Vec<&'a str> {
+        let mut zones: Vec<&str> = Vec::new();
+        for stop in self.stops(timetable) {
+            if let Some(zone) = &timetable.stops[stop].zone_id {
+                if !zones.contains(&zone.as_str()) {
+                    zones.push(zone);
+                }
+            }
+        }
+        zones
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::compute;
+
+    #[test]
+    fn transfer_legs() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .s("c", "0:24")
+            .trip()
+            .s("c", "0:30")
+            .s("d", "0:40");
+        let t = b.build();
+        let profiles = compute(&t, &[3]);
+        let route = profiles[0][0].route(&profiles, &t);
+        let journey = Journey::new(&t, 0, &route, &[3]);
+
+        assert_eq!(2, journey.legs.len());
+        assert_eq!(1, journey.transfers());
+        assert_eq!(0, journey.legs[0].from);
+        assert_eq!(2, journey.legs[0].to);
+        assert_eq!(Some(10), journey.dep_time());
+        assert_eq!(Some(40), journey.arr_time());
+        assert_eq!(0, journey.walking_time());
+    }
+
+    #[test]
+    fn summary() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("b", "0:30")
+            .s("c", "0:40");
+        let t = b.build();
+        let midnight = t.start_date.and_hms(0, 0, 0);
+        let query = crate::query::Query::at(&t, vec![0], vec![2], midnight).unwrap();
+        assert_eq!(0, query.departure);
+        assert!(
+            crate::query::Query::at(&t, vec![0], vec![2], midnight - Duration::days(1)).is_none()
+        );
+
+        let summary = query.journeys(&t)[0].summary(&t).unwrap();
+        assert_eq!(midnight + Duration::seconds(10), summary.departure);
+        assert_eq!(midnight + Duration::seconds(40), summary.arrival);
+        assert_eq!(Duration::seconds(30), summary.duration);
+        assert_eq!(Duration::seconds(20), summary.transit_time);
+        assert_eq!(1, summary.transfers);
+        assert_eq!(None, Journey { legs: Vec::new() }.summary(&t));
+    }
+
+    #[test]
+    fn block_transfers() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("b", "0:30")
+            .s("c", "0:40");
+        let mut t = b.build();
+        let journey = |t: &Timetable| {
+            let profiles = compute(t, &[2]);
+            let route = profiles[0][0].route(&profiles, t);
+            Journey::new(t, 0, &route, &[2])
+        };
+        assert_eq!(1, journey(&t).transfers());
+
+        for trip in &mut t.trips {
+            trip.block_id = Some("block".to_owned());
+        }
+        let journey = journey(&t);
+        assert_eq!(2, journey.legs.len());
+        assert!(journey.legs[1].stay_seated);
+        assert_eq!(0, journey.transfers());
+    }
+
+    #[test]
+    fn zones() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .s("c", "0:24")
+            .trip()
+            .s("c", "0:30")
+            .s("d", "0:40");
+        let mut t = b.build();
+        for (stop, zone) in [(0, "1"), (1, "2"), (3, "1")] {
+            t.stops[stop].zone_id = Some(zone.to_owned());
+        }
+        let profiles = compute(&t, &[3]);
+        let route = profiles[0][0].route(&profiles, &t);
+        let journey = Journey::new(&t, 0, &route, &[3]);
+
+        assert_eq!(vec![0, 1, 2, 3], journey.stops(&t));
+        assert_eq!(vec!["1", "2"], journey.zones(&t));
+    }
+
+    #[test]
+    fn walking_legs() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("c", "0:30")
+            .s("d", "0:40");
+        let mut t = b.build();
+        t.add_footpath(1, 2, 3);
+        let profiles = compute(&t, &[3]);
+        let route = profiles[0][0].route(&profiles, &t);
+        let journey = Journey::new(&t, 0, &route, &[3]);
+
+        assert_eq!(3, journey.legs.len());
+        assert!(journey.legs[1].is_walk());
+        assert_eq!(1, journey.legs[1].from);
+        assert_eq!(2, journey.legs[1].to);
+        assert_eq!(23, journey.legs[1].arr_time);
+        assert_eq!(3, journey.walking_time());
+        assert_eq!(1, journey.transfers());
+    }
+
+    #[test]
+    fn distances() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .s("c", "0:30")
+            .trip()
+            .s("d", "0:40")
+            .s("e", "0:50");
+        let mut t = b.build();
+        t.add_footpath(2, 3, 3);
+        // Every stop is 0.01° of latitude (≈ 1112 m) to the north of the previous one, but e
+        for (i, stop) in t.stops.iter_mut().enumerate().take(4) {
+            stop.latitude = Some(48. + 0.01 * i as f64);
+            stop.longitude = Some(2.);
+        }
+        let profiles = compute(&t, &[4]);
+        let route = profiles[0][0].route(&profiles, &t);
+        let journey = Journey::new(&t, 0, &route, &[4]);
+
+        assert_eq!(3, journey.legs.len());
+        assert!((2220..2230).contains(&journey.legs[0].distance.unwrap()));
+        assert!((1110..1115).contains(&journey.walking_distance()));
+        assert_eq!(None, journey.legs[2].distance);
+    }
+
+    #[test]
+    fn final_walk() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("b", "0:20");
+        b.trip().s("c", "0:30").s("d", "0:40");
+        let mut t = b.build();
+        t.add_footpath(1, 2, 3);
+        let profiles = compute(&t, &[2]);
+        let route = profiles[0][0].route(&profiles, &t);
+        let journey = Journey::new(&t, 0, &route, &[2]);
+
+        assert_eq!(2, journey.legs.len());
+        assert!(journey.legs[1].is_walk());
+        assert_eq!(Some(23), journey.arr_time());
+    }
+}
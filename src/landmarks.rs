@@ -0,0 +1,190 @@
+// Lower bounds of the travel times between any two stops, from the shortest travel times to and
+// from a few landmark stops (with the triangle inequality)
+// A query uses them to skip the connections leaving a stop before its origins could be there
+// The footpaths count as instantaneous, so that the bounds hold for any walking speed, and the
+// bounds stay valid with the realtime delays, that do not shorten the trips
+use crate::query::Query;
+use crate::structures::Timetable;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+const UNREACHABLE: u32 = u32::MAX;
+
+// By stop, the next stops with the shortest duration, in vehicle or walking
+type Graph = Vec<Vec<(usize, u32)>>;
+
+fn graphs(timetable: &Timetable) -> (Graph, Graph) {
+    let mut durations: HashMap<(usize, usize), u32> = HashMap::new();
+    for c in &timetable.connections {
+        let duration = durations
+            .entry((c.dep_stop, c.arr_stop))
+            .or_insert(u32::MAX);
+        *duration = (*duration).min(c.arr_time - c.dep_time);
+    }
+    for (to, footpaths) in timetable.footpaths.iter().enumerate() {
+        for footpath in footpaths {
+            durations.insert((footpath.from, to), 0);
+        }
+    }
+    let mut forward = vec![Vec::new(); timetable.stops.len()];
+    let mut backward = vec![Vec::new(); timetable.stops.len()];
+    for (&(from, to), &duration) in &durations {
+        forward[from].push((to, duration));
+        backward[to].push((from, duration));
+    }
+    (forward, backward)
+}
+
+// The shortest durations from the source to every stop
+fn dijkstra(graph: &Graph, source: usize) -> Vec<u32> {
+    let mut durations = vec![UNREACHABLE; graph.len()];
+    let mut queue = BinaryHeap::new();
+    durations[source] = 0;
+    queue.push(Reverse((0, source)));
+    while let Some(Reverse((duration, stop))) = queue.pop() {
+        if duration > durations[stop] {
+            continue;
+        }
+        for &(next, edge) in &graph[stop] {
+            let next_duration = duration + edge;
+            if next_duration < durations[next] {
+                durations[next] = next_duration;
+                queue.push(Reverse((next_duration, next)));
+            }
+        }
+    }
+    durations
+}
+
+pub struct Landmarks {
+    pub stops: Vec<usize>,
+    // By landmark, then by stop
+    from: Vec<Vec<u32>>,
+    to: Vec<Vec<u32>>,
+}
+
+impl Landmarks {
+    // At most `count` landmarks: first the stop with the most departures, then every time the stop
+    // the farthest from the previous landmarks, so that they are spread over the network
+    pub fn new(timetable: &Timetable, count: usize) -> Self {
+        let (forward, backward) = graphs(timetable);
+        let mut departures = vec![0; timetable.stops.len()];
+        for c in &timetable.connections {
+            departures[c.dep_stop] += 1;
+        }
+        let mut landmarks = Self {
+            stops: Vec::new(),
+            from: Vec::new(),
+            to: Vec::new(),
+        };
+        let mut next = (0..departures.len())
+            .filter(|&stop| departures[stop] > 0)
+            .max_by_key(|&stop| departures[stop]);
+        while let Some(stop) = next.filter(|_| landmarks.stops.len() < count) {
+            landmarks.stops.push(stop);
+            landmarks.from.push(dijkstra(&forward, stop));
+            landmarks.to.push(dijkstra(&backward, stop));
+            // The closest landmark of every stop reached from a landmark
+            next = (0..departures.len())
+                .filter_map(|stop| {
+                    let closest = landmarks.from.iter().map(|from| from[stop]).min()?;
+                    Some((closest, stop)).filter(|_| closest != UNREACHABLE && closest > 0)
+                })
+                .max()
+                .map(|(_, stop)| stop);
+        }
+        landmarks
+    }
+
+    // None when `to` can not be reached from `from`
+    pub fn lower_bound(&self, from: usize, to: usize) -> Option<u32> {
+        let mut bound = 0;
+        for (from_landmark, to_landmark) in self.from.iter().zip(&self.to) {
+            // The landmark reaches `from` and then `to`, but not `to` directly
+            match (from_landmark[from], from_landmark[to]) {
+                (UNREACHABLE, _) => (),
+                (_, UNREACHABLE) => return None,
+                (f, t) => bound = bound.max(t.saturating_sub(f)),
+            }
+            match (to_landmark[from], to_landmark[to]) {
+                (_, UNREACHABLE) => (),
+                (UNREACHABLE, _) => return None,
+                (f, t) => bound = bound.max(f.saturating_sub(t)),
+            }
+        }
+        Some(bound)
+    }
+
+    // By stop, a time before which none of the origins leaving at `departure` can be there
+    // u32::MAX when none of them can reach it
+    pub fn earliest_reach(&self, origins: &[usize], departure: u32) -> Vec<u32> {
+        let stops = self.from.first().map_or(0, Vec::len);
+        (0..stops)
+            .map(|stop| {
+                origins
+                    .iter()
+                    .filter_map(|&origin| self.lower_bound(origin, stop))
+                    .min()
+                    .map_or(u32::MAX, |bound| departure.saturating_add(bound))
+            })
+            .collect()
+    }
+
+    // The profiles are only computed for the connections the origins of the query can catch
+    pub fn prune(&self, query: &mut Query) {
+        if !self.stops.is_empty() {
+            let reach = self.earliest_reach(&query.origin, query.departure);
+            query.options.earliest_reach = Some(Arc::new(reach));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timetable() -> Timetable {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "1:00").s("b", "2:00").s("c", "3:00");
+        b.trip().s("c", "4:00").s("d", "5:00");
+        b.trip().s("d", "1:00").s("e", "1:10");
+        b.trip().s("b", "2:30").s("e", "9:00");
+        b.trip().s("e", "9:10").s("a", "9:20");
+        b.build()
+    }
+
+    #[test]
+    fn lower_bounds() {
+        let t = timetable();
+        let landmarks = Landmarks::new(&t, 3);
+        assert_eq!(3, landmarks.stops.len());
+        for from in 0..t.stops.len() {
+            let exact = dijkstra(&graphs(&t).0, from);
+            for (to, &exact) in exact.iter().enumerate() {
+                match landmarks.lower_bound(from, to) {
+                    Some(bound) => assert!(bound <= exact, "{} to {}", from, to),
+                    None => assert_eq!(UNREACHABLE, exact),
+                }
+            }
+        }
+        // a to c takes at least 2 minutes
+        assert!(landmarks.lower_bound(0, 2).unwrap() > 0);
+    }
+
+    #[test]
+    fn pruned_scan() {
+        let t = timetable();
+        let landmarks = Landmarks::new(&t, 2);
+        let queries: Vec<_> = crate::sampling::QuerySampler::new(&t, 3).take(50).collect();
+        let mut pruned = 0;
+        for query in queries {
+            let mut with_landmarks = query.clone();
+            landmarks.prune(&mut with_landmarks);
+            let (journeys, stats) = with_landmarks.journeys_with_stats(&t);
+            assert_eq!(query.journeys(&t), journeys);
+            pruned += stats.scan.connections_pruned;
+        }
+        assert!(pruned > 0);
+    }
+}
@@ -1,2 +1,54 @@
+// Without the full feature, only the timetable and the algorithms: the timetables are built with
+// `Timetable::builder`, or read from the binary cache of the convert binary with the cache feature
+// (`Timetable::from_bytes`)
 pub mod algo;
+#[cfg(feature = "full")]
+pub mod analysis;
+#[cfg(feature = "full")]
+pub mod analytics;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "full")]
+pub mod clustering;
+#[cfg(feature = "full")]
+pub mod comparison;
+#[cfg(feature = "full")]
+pub mod connections;
+#[cfg(feature = "ffi")]
+pub mod csa_ffi;
+#[cfg(feature = "full")]
+pub mod footpaths;
+#[cfg(feature = "full")]
+pub mod formats;
+pub mod geo;
+#[cfg(feature = "gtfs")]
+pub mod gtfs;
+#[cfg(feature = "full")]
+pub mod gtfs_export;
+pub mod journey;
+#[cfg(feature = "full")]
+pub mod landmarks;
+#[cfg(feature = "netex")]
+pub mod netex;
+#[cfg(feature = "node")]
+pub mod node;
+#[cfg(feature = "osm")]
+pub mod osm;
+#[cfg(feature = "full")]
+pub mod patch;
+#[cfg(feature = "full")]
+pub mod poi;
+pub mod query;
+#[cfg(feature = "full")]
+pub mod query_log;
+#[cfg(feature = "full")]
+pub mod realtime;
+#[cfg(feature = "full")]
+pub mod sampling;
+#[cfg(feature = "full")]
+pub mod scenario;
 pub mod structures;
+#[cfg(feature = "full")]
+pub mod subnetwork;
+#[cfg(feature = "wasm")]
+pub mod wasm;
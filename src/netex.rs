@@ -0,0 +1,572 @@
+// Reads NeTEx timetables, as published by several European access points
+// Only the elements used by the European passenger information profile (EPIP) are handled:
+// stop places and quays, scheduled stop points and their assignments, lines,
+// service journey patterns, service journeys and day types
+use crate::structures::{Connection, LocationType, Route, RouteType, Stop, Timetable, Trip};
+use chrono::{Datelike, NaiveDate, Utc, Weekday};
+use roxmltree::Node;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Xml(roxmltree::Error),
+    Invalid(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "Could not read the NeTEx file: {}", e),
+            Error::Xml(e) => write!(f, "Invalid NeTEx XML: {}", e),
+            Error::Invalid(e) => write!(f, "Invalid NeTEx data: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<roxmltree::Error> for Error {
+    fn from(e: roxmltree::Error) -> Self {
+        Error::Xml(e)
+    }
+}
+
+struct PassingTime {
+    point: String,
+    arrival: Option<u32>,
+    departure: Option<u32>,
+}
+
+struct ServiceJourney {
+    id: String,
+    line: Option<String>,
+    day_types: Vec<String>,
+    passing_times: Vec<PassingTime>,
+}
+
+enum DayTypeAssignment {
+    Date(NaiveDate),
+    OperatingDay(String),
+    OperatingPeriod(String),
+}
+
+// Everything read from the NeTEx files, before building the timetable
+#[derive(Default)]
+struct Netex {
+    stops: Vec<Stop>,
+    scheduled_stop_points: Vec<Stop>,
+    // ScheduledStopPoint id → Quay id
+    stop_assignments: HashMap<String, String>,
+    // StopPointInJourneyPattern id → ScheduledStopPoint id
+    points_in_pattern: HashMap<String, String>,
+    lines: Vec<Route>,
+    // None means every day of the week
+    day_types: HashMap<String, Option<HashSet<Weekday>>>,
+    day_type_assignments: Vec<(String, DayTypeAssignment, bool)>,
+    operating_days: HashMap<String, NaiveDate>,
+    operating_periods: HashMap<String, (NaiveDate, NaiveDate)>,
+    journeys: Vec<ServiceJourney>,
+    timezone: Option<String>,
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.tag_name().name() == name)
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    child(node, name)
+        .and_then(|n| n.text())
+        .map(|t| t.trim().to_owned())
+}
+
+fn child_ref(node: Node, name: &str) -> Option<String> {
+    child(node, name)
+        .and_then(|n| n.attribute("ref"))
+        .map(str::to_owned)
+}
+
+fn id(node: Node) -> Result<String, Error> {
+    node.attribute("id")
+        .map(str::to_owned)
+        .ok_or_else(|| Error::Invalid(format!("Missing id on element {}", node.tag_name().name())))
+}
+
+fn location(node: Node) -> (Option<f64>, Option<f64>) {
+    let location = child(node, "Centroid")
+        .and_then(|c| child(c, "Location"))
+        .or_else(|| child(node, "Location"));
+    let coordinate = |name| {
+        location
+            .and_then(|l| child_text(l, name))
+            .and_then(|v| v.parse().ok())
+    };
+    (coordinate("Latitude"), coordinate("Longitude"))
+}
+
+fn parse_time(time: &str, day_offset: Option<String>) -> Result<u32, Error> {
+    let invalid = || Error::Invalid(format!("Invalid time {}", time));
+    let parts: Vec<u32> = time
+        .split(':')
+        .map(|p| p.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    let seconds = match parts.as_slice() {
+        [h, m] => h * 3600 + m * 60,
+        [h, m, s] => h * 3600 + m * 60 + s,
+        _ => return Err(invalid()),
+    };
+    let offset: u32 = day_offset.and_then(|o| o.parse().ok()).unwrap_or(0);
+    Ok(seconds + offset * 24 * 60 * 60)
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, Error> {
+    // Dates can be given as xsd:dateTime
+    NaiveDate::parse_from_str(&date[..date.len().min(10)], "%Y-%m-%d")
+        .map_err(|_| Error::Invalid(format!("Invalid date {}", date)))
+}
+
+fn days_of_week(days: &str) -> HashSet<Weekday> {
+    use Weekday::*;
+    days.split_whitespace()
+        .flat_map(|day| match day {
+            "Monday" => vec![Mon],
+            "Tuesday" => vec![Tue],
+            "Wednesday" => vec![Wed],
+            "Thursday" => vec![Thu],
+            "Friday" => vec![Fri],
+            "Saturday" => vec![Sat],
+            "Sunday" => vec![Sun],
+            "Weekdays" => vec![Mon, Tue, Wed, Thu, Fri],
+            "Weekend" => vec![Sat, Sun],
+            "Everyday" => vec![Mon, Tue, Wed, Thu, Fri, Sat, Sun],
+            _ => vec![],
+        })
+        .collect()
+}
+
+fn route_type(mode: &str) -> RouteType {
+    match mode {
+        "tram" => RouteType::Tramway,
+        "metro" => RouteType::Subway,
+        "rail" => RouteType::Rail,
+        "water" | "ferry" => RouteType::Ferry,
+        "cableway" => RouteType::CableCar,
+        "funicular" => RouteType::Funicular,
+        "coach" => RouteType::Coach,
+        "air" => RouteType::Air,
+        "taxi" => RouteType::Taxi,
+        _ => RouteType::Bus,
+    }
+}
+
+impl Netex {
+    fn read(&mut self, xml: &str) -> Result<(), Error> {
+        let document = roxmltree::Document::parse(xml)?;
+        for node in document.descendants().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "StopPlace" => self.read_stop_place(node)?,
+                "ScheduledStopPoint" => {
+                    let (latitude, longitude) = location(node);
+                    self.scheduled_stop_points.push(Stop {
+                        id: id(node)?,
+                        name: child_text(node, "Name").unwrap_or_default(),
+                        latitude,
+                        longitude,
+                        ..Default::default()
+                    });
+                }
+                "PassengerStopAssignment" => {
+                    if let (Some(point), Some(quay)) = (
+                        child_ref(node, "ScheduledStopPointRef"),
+                        child_ref(node, "QuayRef"),
+                    ) {
+                        self.stop_assignments.insert(point, quay);
+                    }
+                }
+                "StopPointInJourneyPattern" => {
+                    if let Some(point) = child_ref(node, "ScheduledStopPointRef") {
+                        self.points_in_pattern.insert(id(node)?, point);
+                    }
+                }
+                "Line" => self.lines.push(Route {
+                    id: id(node)?,
+                    short_name: child_text(node, "PublicCode").unwrap_or_default(),
+                    long_name: child_text(node, "Name").unwrap_or_default(),
+                    route_type: route_type(&child_text(node, "TransportMode").unwrap_or_default()),
+                    agency_id: child_ref(node, "OperatorRef"),
+                    agency_name: None,
+                }),
+                "DayType" => {
+                    let days = node
+                        .descendants()
+                        .find(|n| n.tag_name().name() == "DaysOfWeek")
+                        .and_then(|n| n.text())
+                        .map(days_of_week);
+                    self.day_types.insert(id(node)?, days);
+                }
+                "DayTypeAssignment" => self.read_day_type_assignment(node)?,
+                "OperatingDay" => {
+                    if let Some(date) = child_text(node, "CalendarDate") {
+                        self.operating_days.insert(id(node)?, parse_date(&date)?);
+                    }
+                }
+                "OperatingPeriod" => {
+                    if let (Some(from), Some(to)) =
+                        (child_text(node, "FromDate"), child_text(node, "ToDate"))
+                    {
+                        self.operating_periods
+                            .insert(id(node)?, (parse_date(&from)?, parse_date(&to)?));
+                    }
+                }
+                "ServiceJourney" => self.read_service_journey(node)?,
+                "TimeZone" if self.timezone.is_none() => {
+                    self.timezone = node.text().map(|t| t.trim().to_owned());
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_stop_place(&mut self, node: Node) -> Result<(), Error> {
+        let stop_place_id = id(node)?;
+        let (latitude, longitude) = location(node);
+        self.stops.push(Stop {
+            id: stop_place_id.to_owned(),
+            name: child_text(node, "Name").unwrap_or_default(),
+            location_type: LocationType::StopArea,
+            latitude,
+            longitude,
+            ..Default::default()
+        });
+
+        for quay in child(node, "quays")
+            .iter()
+            .flat_map(|q| q.children())
+            .filter(|q| q.tag_name().name() == "Quay")
+        {
+            let (latitude, longitude) = location(quay);
+            self.stops.push(Stop {
+                id: id(quay)?,
+                name: child_text(quay, "Name")
+                    .or_else(|| child_text(node, "Name"))
+                    .unwrap_or_default(),
+                parent_station: Some(stop_place_id.to_owned()),
+                location_type: LocationType::StopPoint,
+                latitude,
+                longitude,
+                zone_id: None,
+            });
+        }
+        Ok(())
+    }
+
+    fn read_day_type_assignment(&mut self, node: Node) -> Result<(), Error> {
+        let day_type = match child_ref(node, "DayTypeRef") {
+            Some(day_type) => day_type,
+            None => return Ok(()),
+        };
+        let available = child_text(node, "isAvailable").is_none_or(|a| a != "false");
+        let assignment = if let Some(date) = child_text(node, "Date") {
+            DayTypeAssignment::Date(parse_date(&date)?)
+        } else if let Some(day) = child_ref(node, "OperatingDayRef") {
+            DayTypeAssignment::OperatingDay(day)
+        } else if let Some(period) = child_ref(node, "OperatingPeriodRef") {
+            DayTypeAssignment::OperatingPeriod(period)
+        } else {
+            return Ok(());
+        };
+        self.day_type_assignments
+            .push((day_type, assignment, available));
+        Ok(())
+    }
+
+    fn read_service_journey(&mut self, node: Node) -> Result<(), Error> {
+        let day_types = child(node, "dayTypes")
+            .iter()
+            .flat_map(|d| d.children())
+            .filter_map(|d| d.attribute("ref"))
+            .map(str::to_owned)
+            .collect();
+
+        let mut passing_times = Vec::new();
+        for passing_time in child(node, "passingTimes")
+            .iter()
+            .flat_map(|p| p.children())
+            .filter(|p| p.tag_name().name() == "TimetabledPassingTime")
+        {
+            let point =
+                child_ref(passing_time, "StopPointInJourneyPatternRef").ok_or_else(|| {
+                    Error::Invalid(
+                        "TimetabledPassingTime without StopPointInJourneyPatternRef".to_owned(),
+                    )
+                })?;
+            let arrival = child_text(passing_time, "ArrivalTime")
+                .map(|t| parse_time(&t, child_text(passing_time, "ArrivalDayOffset")))
+                .transpose()?;
+            let departure = child_text(passing_time, "DepartureTime")
+                .map(|t| parse_time(&t, child_text(passing_time, "DepartureDayOffset")))
+                .transpose()?;
+            passing_times.push(PassingTime {
+                point,
+                arrival,
+                departure,
+            });
+        }
+
+        self.journeys.push(ServiceJourney {
+            id: id(node)?,
+            line: child_ref(node, "LineRef"),
+            day_types,
+            passing_times,
+        });
+        Ok(())
+    }
+
+    // The offsets from the start date of the days when the day type is active
+    fn active_days(&self, day_type: &str, start_date: NaiveDate, horizon: u16) -> Vec<u16> {
+        let mut added = HashSet::new();
+        let mut removed = HashSet::new();
+        let weekdays = self.day_types.get(day_type).cloned().flatten();
+
+        for (_, assignment, available) in self
+            .day_type_assignments
+            .iter()
+            .filter(|(d, _, _)| d == day_type)
+        {
+            let dates = match assignment {
+                DayTypeAssignment::Date(date) => vec![*date],
+                DayTypeAssignment::OperatingDay(day) => {
+                    self.operating_days.get(day).cloned().into_iter().collect()
+                }
+                DayTypeAssignment::OperatingPeriod(period) => {
+                    match self.operating_periods.get(period) {
+                        Some((from, to)) => (0..horizon)
+                            .map(|d| start_date + chrono::Duration::days(i64::from(d)))
+                            .filter(|date| from <= date && date <= to)
+                            .filter(|date| {
+                                weekdays
+                                    .as_ref()
+                                    .is_none_or(|w| w.contains(&date.weekday()))
+                            })
+                            .collect(),
+                        None => Vec::new(),
+                    }
+                }
+            };
+            for date in dates {
+                let offset = date.signed_duration_since(start_date).num_days();
+                if offset >= 0 && offset < i64::from(horizon) {
+                    if *available {
+                        added.insert(offset as u16);
+                    } else {
+                        removed.insert(offset as u16);
+                    }
+                }
+            }
+        }
+
+        let mut days: Vec<_> = added.difference(&removed).cloned().collect();
+        days.sort_unstable();
+        days
+    }
+
+    fn into_timetable(self, start_date: NaiveDate, horizon: u16) -> Result<Timetable, Error> {
+        let now = Utc::now();
+        let mut stops = self.stops.clone();
+        // Scheduled stop points that are not assigned to a quay are used as stops
+        for point in &self.scheduled_stop_points {
+            if !self.stop_assignments.contains_key(&point.id) {
+                stops.push(point.clone());
+            }
+        }
+        let stop_indices: HashMap<_, _> = stops
+            .iter()
+            .enumerate()
+            .map(|(index, stop)| (stop.id.to_owned(), index))
+            .collect();
+        let route_indices: HashMap<_, _> = self
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(index, route)| (route.id.to_owned(), index))
+            .collect();
+
+        let stop_index = |point_in_pattern: &str| -> Result<usize, Error> {
+            let point = self
+                .points_in_pattern
+                .get(point_in_pattern)
+                .map(String::as_str)
+                .unwrap_or(point_in_pattern);
+            let stop = self
+                .stop_assignments
+                .get(point)
+                .map(String::as_str)
+                .unwrap_or(point);
+            stop_indices
+                .get(stop)
+                .copied()
+                .ok_or_else(|| Error::Invalid(format!("Unknown stop point {}", point)))
+        };
+
+        let mut trips = Vec::new();
+        let mut connections = Vec::new();
+        for journey in &self.journeys {
+            let mut days: Vec<_> = journey
+                .day_types
+                .iter()
+                .flat_map(|d| self.active_days(d, start_date, horizon))
+                .collect();
+            days.sort_unstable();
+            days.dedup();
+
+            let route = journey
+                .line
+                .as_ref()
+                .and_then(|l| route_indices.get(l))
+                .copied();
+            for day in days {
+                let trip = trips.len();
+                trips.push(Trip {
+                    id: journey.id.to_owned(),
+                    day,
+                    route,
+                    block_id: None,
+                });
+                let day_offset = u32::from(day) * 24 * 60 * 60;
+                for (departure, arrival) in journey
+                    .passing_times
+                    .iter()
+                    .zip(journey.passing_times.iter().skip(1))
+                {
+                    let dep_time = departure.departure.or(departure.arrival).ok_or_else(|| {
+                        Error::Invalid(format!("Passing time without time on {}", journey.id))
+                    })?;
+                    let arr_time = arrival.arrival.or(arrival.departure).unwrap_or(dep_time);
+                    connections.push(Connection {
+                        trip,
+                        dep_time: dep_time + day_offset,
+                        arr_time: arr_time + day_offset,
+                        dep_stop: stop_index(&departure.point)?,
+                        arr_stop: stop_index(&arrival.point)?,
+                    });
+                }
+            }
+        }
+        // We want the connections by decreasing departure time
+        connections.sort_by_key(|c| std::cmp::Reverse((c.dep_time, c.arr_time)));
+
+        let timezone = match self.timezone {
+            Some(timezone) => timezone
+                .parse()
+                .map_err(|_| Error::Invalid(format!("Invalid timezone {}", timezone)))?,
+            None => chrono_tz::UTC,
+        };
+
+        Ok(Timetable {
+            start_date,
+            timezone,
+            footpaths: Timetable::footpaths(&stops, &stop_indices),
+            stops,
+            connections,
+            transform_duration: Utc::now().signed_duration_since(now).num_milliseconds(),
+            translations: Default::default(),
+            fare_rules: Vec::new(),
+            trips,
+            routes: self.lines,
+        })
+    }
+}
+
+impl Timetable {
+    // Reads a NeTEx file, or all the .xml files of a directory
+    pub fn from_netex<P: AsRef<Path>>(
+        path: P,
+        start_date_str: &str,
+        horizon: u16,
+    ) -> Result<Timetable, Error> {
+        let start_date = start_date_str
+            .parse::<NaiveDate>()
+            .map_err(|_| Error::Invalid(format!("Invalid start date {}", start_date_str)))?;
+        Timetable::from_netex_starting(path, start_date, horizon)
+    }
+
+    // Like `from_netex`, without parsing the start date
+    pub fn from_netex_starting<P: AsRef<Path>>(
+        path: P,
+        start_date: NaiveDate,
+        horizon: u16,
+    ) -> Result<Timetable, Error> {
+        let path = path.as_ref();
+        let mut files = Vec::new();
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let file = entry?.path();
+                if file.extension() == Some("xml".as_ref()) {
+                    files.push(file);
+                }
+            }
+            files.sort();
+        } else {
+            files.push(path.to_owned());
+        }
+
+        let mut netex = Netex::default();
+        for file in files {
+            netex.read(&std::fs::read_to_string(file)?)?;
+        }
+        netex.into_timetable(start_date, horizon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_netex() {
+        let t = Timetable::from_netex("fixtures/netex/", "2017-1-1", 10).unwrap();
+        // Two stop places with a quay each
+        assert_eq!(4, t.stops.len());
+        assert_eq!(1, t.routes.len());
+        assert_eq!(chrono_tz::Europe::Paris, t.timezone);
+        // Runs on weekdays in the period, except on the 3rd of January
+        let days: Vec<_> = t
+            .trips
+            .iter()
+            .filter(|t| t.id == "sj:1")
+            .map(|t| t.day)
+            .collect();
+        assert_eq!(vec![1, 3, 4, 5, 8, 9], days);
+        assert_eq!(2 * 6, t.connections.len());
+        // The arrival is after midnight
+        let night = t.connections.first().unwrap();
+        assert_eq!(10 * 24 * 3600 + 10 * 60, night.arr_time);
+
+        let c = t.connections.last().unwrap();
+        assert_eq!(1, t.trips[c.trip].day);
+        assert_eq!(24 * 3600 + 8 * 3600, c.dep_time);
+        assert_eq!("quay:A", t.stops[c.dep_stop].id);
+        assert_eq!(
+            Some("stop_place:1".to_owned()),
+            t.stops[c.dep_stop].parent_station
+        );
+        assert_eq!(Some(48.8), t.stops[c.dep_stop].latitude);
+    }
+
+    #[test]
+    fn day_offset() {
+        assert_eq!(
+            Ok(2 * 3600 + 24 * 3600),
+            parse_time("02:00:00", Some("1".to_owned())).map_err(|_| ())
+        );
+        assert!(parse_time("2h", None).is_err());
+    }
+}
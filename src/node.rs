@@ -0,0 +1,128 @@
+// Node.js bindings, the queries run on the libuv thread pool and return promises
+// The timetable is a binary one, as written by the convert binary
+use crate::algo::earliest_arrival;
+use crate::formats::navitia::{self, JourneysRequest, DATETIME_FORMAT};
+use crate::structures::Timetable;
+use napi::bindgen_prelude::AsyncTask;
+use napi::{Env, Error, Result, Task};
+use napi_derive::napi;
+use std::sync::Arc;
+
+#[napi]
+pub struct Router {
+    timetable: Arc<Timetable>,
+}
+
+#[napi(object)]
+pub struct IsochroneStop {
+    pub stop_id: String,
+    // In seconds since the requested departure
+    pub duration: u32,
+}
+
+pub struct Plan {
+    timetable: Arc<Timetable>,
+    request: JourneysRequest,
+}
+
+impl Task for Plan {
+    type Output = String;
+    type JsValue = String;
+
+    fn compute(&mut self) -> Result<String> {
+        serde_json::to_string(&navitia::journeys(self.timetable.as_ref(), &self.request))
+            .map_err(|e| Error::from_reason(e.to_string()))
+    }
+
+    fn resolve(&mut self, _env: Env, output: String) -> Result<String> {
+        Ok(output)
+    }
+}
+
+pub struct Isochrone {
+    timetable: Arc<Timetable>,
+    origin: String,
+    datetime: Option<String>,
+}
+
+impl Task for Isochrone {
+    type Output = Vec<IsochroneStop>;
+    type JsValue = Vec<IsochroneStop>;
+
+    fn compute(&mut self) -> Result<Vec<IsochroneStop>> {
+        let timetable = &self.timetable;
+        let departure = match &self.datetime {
+            Some(datetime) => chrono::NaiveDateTime::parse_from_str(datetime, DATETIME_FORMAT)
+                .ok()
+                .and_then(|datetime| timetable.time(datetime))
+                .ok_or_else(|| Error::from_reason(format!("Invalid datetime {}", datetime)))?,
+            None => 0,
+        };
+        let origins = timetable.stop_indices(&self.origin);
+        if origins.is_empty() {
+            return Err(Error::from_reason(format!(
+                "Unknown object {}",
+                self.origin
+            )));
+        }
+
+        Ok(earliest_arrival(timetable, &origins, departure)
+            .iter()
+            .enumerate()
+            .filter_map(|(stop, arrival)| {
+                arrival.map(|arrival| IsochroneStop {
+                    stop_id: timetable.stops[stop].id.to_owned(),
+                    duration: arrival - departure,
+                })
+            })
+            .collect())
+    }
+
+    fn resolve(&mut self, _env: Env, output: Vec<IsochroneStop>) -> Result<Vec<IsochroneStop>> {
+        Ok(output)
+    }
+}
+
+#[napi]
+impl Router {
+    #[napi(factory)]
+    pub fn load(path: String) -> Result<Router> {
+        let bytes = std::fs::read(&path).map_err(|e| Error::from_reason(e.to_string()))?;
+        let timetable = Timetable::from_bytes(&bytes)
+            .map_err(|e| Error::from_reason(format!("Invalid timetable: {}", e)))?;
+        Ok(Router {
+            timetable: Arc::new(timetable),
+        })
+    }
+
+    // Resolves to a navitia /journeys response, serialized as JSON
+    #[napi(ts_return_type = "Promise<string>")]
+    pub fn plan(
+        &self,
+        from: String,
+        to: String,
+        datetime: Option<String>,
+        count: Option<u32>,
+    ) -> AsyncTask<Plan> {
+        AsyncTask::new(Plan {
+            timetable: self.timetable.clone(),
+            request: JourneysRequest {
+                from,
+                to,
+                datetime,
+                count: count.map(|c| c as usize),
+                ..Default::default()
+            },
+        })
+    }
+
+    // Resolves to how long it takes to reach every reachable stop
+    #[napi(ts_return_type = "Promise<IsochroneStop[]>")]
+    pub fn isochrone(&self, from: String, datetime: Option<String>) -> AsyncTask<Isochrone> {
+        AsyncTask::new(Isochrone {
+            timetable: self.timetable.clone(),
+            origin: from,
+            datetime,
+        })
+    }
+}
@@ -0,0 +1,384 @@
+// Walking durations between nearby stops over the OpenStreetMap street network
+// They replace the default footpaths and are kept in the binary timetable (see the convert binary)
+use crate::footpaths::FootpathProvider;
+use crate::geo::distance;
+use crate::structures::{insert_footpath, Footpath, Stop, Timetable};
+use osmpbf::{Element, ElementReader};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::path::Path;
+
+// Roughly 200 m, the stops are snapped to the nodes in the neighbouring cells
+const CELL_SIZE: f64 = 0.002;
+
+pub struct WalkingOptions {
+    // In meters per second
+    pub speed: f64,
+    // The longest footpath, in seconds
+    pub max_duration: u32,
+}
+
+impl Default for WalkingOptions {
+    fn default() -> Self {
+        Self {
+            speed: 1.2,
+            max_duration: 600,
+        }
+    }
+}
+
+pub struct WalkingGraph {
+    // (latitude, longitude)
+    nodes: Vec<(f64, f64)>,
+    // The neighbours of every node with the distance in meters, and if there are stairs
+    edges: Vec<Vec<(usize, f64, bool)>>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+fn cell(coord: (f64, f64)) -> (i32, i32) {
+    (
+        (coord.0 / CELL_SIZE).floor() as i32,
+        (coord.1 / CELL_SIZE).floor() as i32,
+    )
+}
+
+// None if the way can not be walked, otherwise if it has stairs
+fn walkable_way<'a>(mut tags: impl Iterator<Item = (&'a str, &'a str)>) -> Option<bool> {
+    let mut highway = None;
+    let mut foot = None;
+    for (key, value) in &mut tags {
+        match key {
+            "highway" => highway = Some(value),
+            "foot" => foot = Some(value),
+            _ => (),
+        }
+    }
+    let walkable = match (highway, foot) {
+        (_, Some("no")) | (None, _) => false,
+        (_, Some("yes")) | (_, Some("designated")) => true,
+        (Some(highway), _) => !matches!(
+            highway,
+            "motorway" | "motorway_link" | "trunk" | "trunk_link" | "construction" | "proposed"
+        ),
+    };
+    if walkable {
+        Some(highway == Some("steps"))
+    } else {
+        None
+    }
+}
+
+#[derive(PartialEq)]
+struct State {
+    distance: f64,
+    node: usize,
+}
+
+impl Eq for State {}
+
+impl Ord for State {
+    // Reversed to get a min-heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .distance
+            .partial_cmp(&self.distance)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for State {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl WalkingGraph {
+    // The ways are the sequences of nodes (indices in `nodes`), and if they are stairs
+    pub fn new(nodes: Vec<(f64, f64)>, ways: &[(Vec<usize>, bool)]) -> Self {
+        let mut edges = vec![Vec::new(); nodes.len()];
+        for (way, stairs) in ways {
+            for pair in way.windows(2) {
+                let d = distance(nodes[pair[0]], nodes[pair[1]]);
+                edges[pair[0]].push((pair[1], d, *stairs));
+                edges[pair[1]].push((pair[0], d, *stairs));
+            }
+        }
+        let mut cells = HashMap::new();
+        for (index, node) in nodes.iter().enumerate() {
+            if !edges[index].is_empty() {
+                cells
+                    .entry(cell(*node))
+                    .or_insert_with(Vec::new)
+                    .push(index);
+            }
+        }
+        Self {
+            nodes,
+            edges,
+            cells,
+        }
+    }
+
+    // Reads the walkable ways of an .osm.pbf extract
+    pub fn from_pbf<P: AsRef<Path>>(path: P) -> Result<Self, osmpbf::Error> {
+        let mut ways = Vec::new();
+        ElementReader::from_path(&path)?.for_each(|element| {
+            if let Element::Way(way) = element {
+                if let Some(stairs) = walkable_way(way.tags()) {
+                    ways.push((way.refs().collect::<Vec<_>>(), stairs));
+                }
+            }
+        })?;
+
+        let needed: HashSet<i64> = ways.iter().flat_map(|(way, _)| way).copied().collect();
+        let mut node_indices = HashMap::new();
+        let mut nodes = Vec::new();
+        ElementReader::from_path(&path)?.for_each(|element| {
+            let (id, coord) = match element {
+                Element::Node(node) => (node.id(), (node.lat(), node.lon())),
+                Element::DenseNode(node) => (node.id(), (node.lat(), node.lon())),
+                _ => return,
+            };
+            if needed.contains(&id) {
+                node_indices.insert(id, nodes.len());
+                nodes.push(coord);
+            }
+        })?;
+
+        let ways: Vec<(Vec<usize>, bool)> = ways
+            .iter()
+            .map(|(way, stairs)| {
+                let way = way
+                    .iter()
+                    .filter_map(|id| node_indices.get(id).copied())
+                    .collect();
+                (way, *stairs)
+            })
+            .collect();
+        Ok(Self::new(nodes, &ways))
+    }
+
+    // The closest node of the street network, with the distance to it
+    fn nearest(&self, coord: (f64, f64)) -> Option<(usize, f64)> {
+        let (x, y) = cell(coord);
+        (x - 1..=x + 1)
+            .flat_map(|x| (y - 1..=y + 1).map(move |y| (x, y)))
+            .filter_map(|c| self.cells.get(&c))
+            .flatten()
+            .map(|&node| (node, distance(coord, self.nodes[node])))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+    }
+
+    fn has_stairs(&self) -> bool {
+        self.edges.iter().flatten().any(|(_, _, stairs)| *stairs)
+    }
+
+    // The walking distance to every node closer than `max_distance`
+    fn distances(&self, from: usize, max_distance: f64, step_free: bool) -> HashMap<usize, f64> {
+        let mut result = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        heap.push(State {
+            distance: 0.,
+            node: from,
+        });
+        while let Some(State { distance, node }) = heap.pop() {
+            if result.contains_key(&node) {
+                continue;
+            }
+            result.insert(node, distance);
+            for &(next, d, stairs) in &self.edges[node] {
+                if step_free && stairs {
+                    continue;
+                }
+                let next_distance = distance + d;
+                if next_distance <= max_distance && !result.contains_key(&next) {
+                    heap.push(State {
+                        distance: next_distance,
+                        node: next,
+                    });
+                }
+            }
+        }
+        result
+    }
+}
+
+// Walking over the street network, between stops closer than `max_duration`
+// When the shortest path has stairs, the shortest step-free path is also kept
+pub struct OsmProvider<'a> {
+    pub graph: &'a WalkingGraph,
+    pub options: &'a WalkingOptions,
+}
+
+impl FootpathProvider for OsmProvider<'_> {
+    fn footpaths(&self, stops: &[Stop]) -> Vec<Vec<Footpath>> {
+        let (graph, options) = (self.graph, self.options);
+        let mut result = vec![Vec::new(); stops.len()];
+        let max_distance = options.speed * f64::from(options.max_duration);
+        // The network nodes of every stop, with the distance to reach them
+        let snapped: Vec<_> = stops
+            .iter()
+            .map(|stop| match (stop.latitude, stop.longitude) {
+                (Some(lat), Some(lon)) => graph
+                    .nearest((lat, lon))
+                    .filter(|(_, d)| *d <= max_distance),
+                _ => None,
+            })
+            .collect();
+        let mut stops_by_node: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (stop, snap) in snapped.iter().enumerate() {
+            if let Some((node, _)) = snap {
+                stops_by_node.entry(*node).or_default().push(stop);
+            }
+        }
+
+        let has_stairs = graph.has_stairs();
+        for (from, snap) in snapped.iter().enumerate() {
+            let (node, access) = match snap {
+                Some(snap) => *snap,
+                None => continue,
+            };
+            let step_free = if has_stairs {
+                graph.distances(node, max_distance - access, true)
+            } else {
+                HashMap::new()
+            };
+            for (reached, d) in graph.distances(node, max_distance - access, false) {
+                // Without stairs, the distance is the same in the step-free graph
+                let step_free_d = if has_stairs {
+                    step_free.get(&reached).copied()
+                } else {
+                    Some(d)
+                };
+                for &to in stops_by_node.get(&reached).into_iter().flatten() {
+                    let (_, egress) = snapped[to].unwrap_or((reached, 0.));
+                    let total = access + d + egress;
+                    if to == from || total > max_distance {
+                        continue;
+                    }
+                    let duration = (total / options.speed).round() as u32;
+                    let step_free_duration = step_free_d
+                        .map(|d| access + d + egress)
+                        .filter(|total| *total <= max_distance)
+                        .map(|total| (total / options.speed).round() as u32);
+                    if step_free_duration != Some(duration) {
+                        insert_footpath(
+                            &mut result[to],
+                            Footpath {
+                                from,
+                                duration,
+                                stairs: true,
+                            },
+                        );
+                    }
+                    if let Some(duration) = step_free_duration {
+                        insert_footpath(
+                            &mut result[to],
+                            Footpath {
+                                from,
+                                duration,
+                                stairs: false,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Timetable {
+    // Replaces the footpaths by walking over the street network, see `OsmProvider`
+    // The stops without coordinates, or too far from the network, keep their footpaths
+    pub fn set_osm_footpaths(&mut self, graph: &WalkingGraph, options: &WalkingOptions) {
+        self.add_footpaths(&OsmProvider { graph, options });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walking_footpaths() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("b", "0:20").s("c", "0:30");
+        let mut t = b.build();
+        for (stop, coord) in t
+            .stops
+            .iter_mut()
+            .zip(&[(48.85, 2.35), (48.851, 2.35), (48.9, 2.35)])
+        {
+            stop.latitude = Some(coord.0);
+            stop.longitude = Some(coord.1);
+        }
+        // An L shaped street between a and b, c is far away
+        let nodes = vec![
+            (48.85, 2.35),
+            (48.85, 2.351),
+            (48.851, 2.351),
+            (48.851, 2.35),
+            (48.9, 2.35),
+        ];
+        let graph = WalkingGraph::new(nodes, &[(vec![0, 1, 2, 3], false)]);
+        t.set_osm_footpaths(&graph, &WalkingOptions::default());
+
+        // 111 + 73 + 73 ≈ 258 m, longer than the 111 m as the crow flies
+        let duration = t.footpath_duration(0, 1).unwrap();
+        assert!((210..=220).contains(&duration));
+        assert_eq!(Some(duration), t.footpath_duration(1, 0));
+        assert_eq!(None, t.footpath_duration(0, 2));
+    }
+
+    #[test]
+    fn walkable() {
+        assert_eq!(
+            Some(false),
+            walkable_way(vec![("highway", "footway")].into_iter())
+        );
+        assert_eq!(
+            None,
+            walkable_way(vec![("highway", "motorway")].into_iter())
+        );
+        assert_eq!(
+            None,
+            walkable_way(vec![("highway", "primary"), ("foot", "no")].into_iter())
+        );
+        assert_eq!(None, walkable_way(vec![("building", "yes")].into_iter()));
+        assert_eq!(
+            Some(true),
+            walkable_way(vec![("highway", "steps")].into_iter())
+        );
+    }
+
+    #[test]
+    fn stairs() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("b", "0:20");
+        let mut t = b.build();
+        for (stop, coord) in t.stops.iter_mut().zip(&[(48.85, 2.35), (48.851, 2.35)]) {
+            stop.latitude = Some(coord.0);
+            stop.longitude = Some(coord.1);
+        }
+        // Straight stairs between a and b, or a detour by an L shaped street
+        let nodes = vec![
+            (48.85, 2.35),
+            (48.85, 2.351),
+            (48.851, 2.351),
+            (48.851, 2.35),
+        ];
+        let ways = [(vec![0, 3], true), (vec![0, 1, 2, 3], false)];
+        t.set_osm_footpaths(&WalkingGraph::new(nodes, &ways), &WalkingOptions::default());
+
+        assert_eq!(2, t.footpaths[1].len());
+        let stairs = t.footpaths[1].iter().find(|f| f.stairs).unwrap();
+        assert!((90..=95).contains(&stairs.duration));
+        let step_free = crate::query::QueryOptions {
+            step_free: true,
+            ..Default::default()
+        };
+        let duration = step_free.footpath_duration(&t, 0, 1).unwrap();
+        assert!((210..=220).contains(&duration));
+    }
+}
@@ -0,0 +1,349 @@
+// Modifications of a loaded timetable, the foundation of the realtime and simulation features
+// The connections stay sorted by decreasing departure then arrival time, and a patch is applied
+// entirely or not at all
+use crate::structures::{Connection, Footpath, Timetable};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    AddConnection(Connection),
+    // The connections are found by value, as their indices move with the other changes
+    RemoveConnection(Connection),
+    Retime {
+        connection: Connection,
+        dep_time: u32,
+        arr_time: u32,
+    },
+    AddFootpath {
+        to: usize,
+        footpath: Footpath,
+    },
+    // Both the step-free footpath and the one with stairs, if any
+    RemoveFootpath {
+        from: usize,
+        to: usize,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch {
+    pub changes: Vec<Change>,
+}
+
+impl Patch {
+    pub fn add_connection(&mut self, connection: Connection) -> &mut Self {
+        self.changes.push(Change::AddConnection(connection));
+        self
+    }
+
+    pub fn remove_connection(&mut self, connection: Connection) -> &mut Self {
+        self.changes.push(Change::RemoveConnection(connection));
+        self
+    }
+
+    pub fn retime(&mut self, connection: Connection, dep_time: u32, arr_time: u32) -> &mut Self {
+        self.changes.push(Change::Retime {
+            connection,
+            dep_time,
+            arr_time,
+        });
+        self
+    }
+
+    pub fn add_footpath(&mut self, to: usize, footpath: Footpath) -> &mut Self {
+        self.changes.push(Change::AddFootpath { to, footpath });
+        self
+    }
+
+    pub fn remove_footpath(&mut self, from: usize, to: usize) -> &mut Self {
+        self.changes.push(Change::RemoveFootpath { from, to });
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchError {
+    UnknownStop(usize),
+    UnknownTrip(usize),
+    // Arriving before leaving
+    InvalidTimes { dep_time: u32, arr_time: u32 },
+    UnknownConnection(Connection),
+    UnknownFootpath { from: usize, to: usize },
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PatchError::UnknownStop(stop) => write!(f, "Unknown stop index {}", stop),
+            PatchError::UnknownTrip(trip) => write!(f, "Unknown trip index {}", trip),
+            PatchError::InvalidTimes { dep_time, arr_time } => write!(
+                f,
+                "The arrival {} is before the departure {}",
+                arr_time, dep_time
+            ),
+            PatchError::UnknownConnection(c) => write!(
+                f,
+                "No connection of trip {} from stop {} at {}",
+                c.trip, c.dep_stop, c.dep_time
+            ),
+            PatchError::UnknownFootpath { from, to } => {
+                write!(f, "No footpath from stop {} to stop {}", from, to)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl Timetable {
+    // The changes are applied in order, on copies of the connections and footpaths: when one of
+    // them fails, like a connection to remove that does not exist, the timetable is left unchanged
+    pub fn apply_patch(&mut self, patch: Patch) -> Result<(), PatchError> {
+        for change in &patch.changes {
+            self.check(change)?;
+        }
+        let mut connections = self.connections.clone();
+        let mut footpaths = self.footpaths.clone();
+        for change in patch.changes {
+            match change {
+                Change::AddConnection(connection) => {
+                    insert_connection(&mut connections, connection)
+                }
+                Change::RemoveConnection(connection) => {
+                    remove_connection(&mut connections, &connection)?;
+                }
+                Change::Retime {
+                    connection,
+                    dep_time,
+                    arr_time,
+                } => {
+                    let connection = remove_connection(&mut connections, &connection)?;
+                    insert_connection(
+                        &mut connections,
+                        Connection {
+                            dep_time,
+                            arr_time,
+                            ..connection
+                        },
+                    );
+                }
+                Change::AddFootpath { to, footpath } => {
+                    crate::structures::insert_footpath(&mut footpaths[to], footpath)
+                }
+                Change::RemoveFootpath { from, to } => {
+                    let footpaths = &mut footpaths[to];
+                    let count = footpaths.len();
+                    footpaths.retain(|f| f.from != from);
+                    if footpaths.len() == count {
+                        return Err(PatchError::UnknownFootpath { from, to });
+                    }
+                }
+            }
+        }
+        self.connections = connections;
+        self.footpaths = footpaths;
+        Ok(())
+    }
+
+    fn check(&self, change: &Change) -> Result<(), PatchError> {
+        let stop = |stop: usize| {
+            if stop < self.stops.len() {
+                Ok(())
+            } else {
+                Err(PatchError::UnknownStop(stop))
+            }
+        };
+        let times = |dep_time: u32, arr_time: u32| {
+            if arr_time >= dep_time {
+                Ok(())
+            } else {
+                Err(PatchError::InvalidTimes { dep_time, arr_time })
+            }
+        };
+        match change {
+            Change::AddConnection(c) => {
+                stop(c.dep_stop)?;
+                stop(c.arr_stop)?;
+                times(c.dep_time, c.arr_time)?;
+                if c.trip >= self.trips.len() {
+                    return Err(PatchError::UnknownTrip(c.trip));
+                }
+                Ok(())
+            }
+            Change::RemoveConnection(_) => Ok(()),
+            Change::Retime {
+                dep_time, arr_time, ..
+            } => times(*dep_time, *arr_time),
+            Change::AddFootpath { to, footpath } => {
+                stop(*to)?;
+                stop(footpath.from)
+            }
+            Change::RemoveFootpath { from, to } => {
+                stop(*from)?;
+                stop(*to)
+            }
+        }
+    }
+}
+
+// Sorted like the readers do, by decreasing departure then arrival time, after the equal ones
+fn insert_connection(connections: &mut Vec<Connection>, connection: Connection) {
+    let key = (connection.dep_time, connection.arr_time);
+    let position = connections.partition_point(|c| (c.dep_time, c.arr_time) >= key);
+    connections.insert(position, connection);
+}
+
+// Among the connections leaving at the same time, whatever their arrival order
+fn remove_connection(
+    connections: &mut Vec<Connection>,
+    connection: &Connection,
+) -> Result<Connection, PatchError> {
+    let start = connections.partition_point(|c| c.dep_time > connection.dep_time);
+    let position = connections[start..]
+        .iter()
+        .take_while(|c| c.dep_time == connection.dep_time)
+        .position(|c| c == connection)
+        .ok_or_else(|| PatchError::UnknownConnection(connection.clone()))?;
+    Ok(connections.remove(start + position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+
+    fn is_sorted(t: &Timetable) -> bool {
+        t.connections
+            .windows(2)
+            .all(|w| (w[0].dep_time, w[0].arr_time) >= (w[1].dep_time, w[1].arr_time))
+    }
+
+    #[test]
+    fn connections() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .s("c", "0:30")
+            .trip()
+            .s("a", "0:40")
+            .s("c", "0:50");
+        let mut t = b.build();
+        let late = t.connections[0].clone();
+        let second = t.connections[1].clone();
+
+        let mut patch = Patch::default();
+        patch
+            .retime(late.clone(), 5, 25)
+            .remove_connection(second)
+            .add_connection(Connection {
+                trip: 0,
+                dep_time: 15,
+                arr_time: 30,
+                dep_stop: 0,
+                arr_stop: 2,
+            });
+        t.apply_patch(patch).unwrap();
+        assert!(is_sorted(&t));
+        assert_eq!(
+            vec![15, 10, 5],
+            t.connections.iter().map(|c| c.dep_time).collect::<Vec<_>>()
+        );
+
+        let journeys = Query::new(vec![0], vec![2], 0).journeys(&t);
+        assert_eq!(Some(25), journeys[0].arr_time());
+
+        // Nothing is applied when a connection to remove is missing
+        let before = t.connections.clone();
+        let mut patch = Patch::default();
+        patch
+            .add_connection(Connection {
+                trip: 1,
+                dep_time: 1,
+                arr_time: 2,
+                dep_stop: 0,
+                arr_stop: 1,
+            })
+            .remove_connection(late);
+        assert!(matches!(
+            t.apply_patch(patch),
+            Err(PatchError::UnknownConnection(_))
+        ));
+        assert_eq!(before, t.connections);
+    }
+
+    #[test]
+    fn same_departure() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("b", "0:30");
+        let mut t = b.build();
+        let connection = |arr_time| Connection {
+            trip: 0,
+            dep_time: 10,
+            arr_time,
+            dep_stop: 0,
+            arr_stop: 1,
+        };
+        let mut patch = Patch::default();
+        patch
+            .add_connection(connection(20))
+            .add_connection(connection(40))
+            .add_connection(connection(30))
+            .retime(connection(40), 10, 25);
+        t.apply_patch(patch).unwrap();
+        assert!(is_sorted(&t));
+        assert_eq!(
+            vec![30, 30, 25, 20],
+            t.connections.iter().map(|c| c.arr_time).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn footpaths() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("c", "0:30")
+            .s("d", "0:40");
+        let mut t = b.build();
+        let footpath = Footpath {
+            from: 1,
+            duration: 3,
+            stairs: false,
+        };
+
+        let mut patch = Patch::default();
+        patch.add_footpath(2, footpath.clone());
+        t.apply_patch(patch).unwrap();
+        assert_eq!(1, Query::new(vec![0], vec![3], 0).journeys(&t).len());
+
+        let mut patch = Patch::default();
+        patch.remove_footpath(1, 2);
+        t.apply_patch(patch).unwrap();
+        assert!(Query::new(vec![0], vec![3], 0).journeys(&t).is_empty());
+
+        // Nothing is applied when a change is invalid or its footpath is missing
+        let mut patch = Patch::default();
+        patch
+            .add_footpath(2, footpath.clone())
+            .remove_footpath(0, 3);
+        assert_eq!(
+            Err(PatchError::UnknownFootpath { from: 0, to: 3 }),
+            t.apply_patch(patch)
+        );
+        assert!(t.footpaths[2].is_empty());
+
+        let mut patch = Patch::default();
+        patch.add_footpath(2, footpath).add_footpath(
+            9,
+            Footpath {
+                from: 0,
+                duration: 1,
+                stairs: false,
+            },
+        );
+        assert_eq!(Err(PatchError::UnknownStop(9)), t.apply_patch(patch));
+        assert!(t.footpaths[2].is_empty());
+    }
+}
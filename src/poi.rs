@@ -0,0 +1,141 @@
+// Named sets of destinations, like all the hospitals, to reach the closest member in time
+// A member is a stop or a stop area id, the journeys tell which one was reached
+use crate::journey::Journey;
+use crate::query::Query;
+use crate::structures::{Network, Timetable};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Clone)]
+pub struct DestinationSets {
+    sets: BTreeMap<String, Vec<String>>,
+}
+
+impl DestinationSets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces the set with the same name
+    // The set is not registered when one of the members is unknown, they are returned
+    pub fn insert(
+        &mut self,
+        timetable: &Timetable,
+        name: &str,
+        members: Vec<String>,
+    ) -> Result<(), Vec<String>> {
+        let unknown: Vec<_> = members
+            .iter()
+            .filter(|member| timetable.stop_indices(member).is_empty())
+            .cloned()
+            .collect();
+        if !unknown.is_empty() {
+            return Err(unknown);
+        }
+        self.sets.insert(name.to_owned(), members);
+        Ok(())
+    }
+
+    // False when there was no such set
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.sets.remove(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[String]> {
+        self.sets.get(name).map(Vec::as_slice)
+    }
+
+    // By name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.sets
+            .iter()
+            .map(|(name, members)| (name.as_str(), members.as_slice()))
+    }
+
+    // The stops of all the members, to be used as the destination of a query
+    pub fn stops(&self, timetable: &Timetable, name: &str) -> Option<Vec<usize>> {
+        let mut stops: Vec<_> = self
+            .get(name)?
+            .iter()
+            .flat_map(|member| timetable.stop_indices(member))
+            .collect();
+        stops.sort_unstable();
+        stops.dedup();
+        Some(stops)
+    }
+
+    // The member where the journey ends, the first one listed when the members overlap
+    pub fn reached(&self, timetable: &Timetable, name: &str, journey: &Journey) -> Option<&str> {
+        let last = journey.legs.last()?.to;
+        self.get(name)?
+            .iter()
+            .find(|member| timetable.stop_indices(member).contains(&last))
+            .map(String::as_str)
+    }
+
+    // The journeys of the query towards any member of the set instead of its destination
+    // None when there is no such set
+    pub fn nearest<N: Network>(
+        &self,
+        network: &N,
+        name: &str,
+        query: &Query,
+    ) -> Option<Vec<(Journey, &str)>> {
+        let timetable = network.timetable();
+        let mut query = query.clone();
+        query.destination = self.stops(timetable, name)?;
+        let journeys = query
+            .journeys(network)
+            .into_iter()
+            .filter_map(|journey| {
+                let member = self.reached(timetable, name, &journey)?;
+                Some((journey, member))
+            })
+            .collect();
+        Some(journeys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Algorithm;
+
+    #[test]
+    fn nearest_member() {
+        let mut b = Timetable::builder();
+        b.trip().s("home", "1:00").s("north", "1:30");
+        b.trip().s("home", "1:10").s("south", "1:20");
+        b.trip().s("home", "1:20").s("work", "1:25");
+        let t = b.build();
+        let mut sets = DestinationSets::new();
+        assert_eq!(
+            Err(vec!["nowhere".to_owned()]),
+            sets.insert(
+                &t,
+                "hospitals",
+                vec!["north".to_owned(), "nowhere".to_owned()]
+            )
+        );
+        assert!(sets.get("hospitals").is_none());
+        let hospitals = vec!["north".to_owned(), "south".to_owned()];
+        assert_eq!(Ok(()), sets.insert(&t, "hospitals", hospitals));
+        assert_eq!(Some(vec![1, 2]), sets.stops(&t, "hospitals"));
+
+        let mut query = Query::new(vec![0], Vec::new(), 0);
+        query.algorithm = Algorithm::EarliestArrival;
+        let journeys = sets.nearest(&t, "hospitals", &query).unwrap();
+        assert_eq!(1, journeys.len());
+        assert_eq!("south", journeys[0].1);
+        assert_eq!(80, journeys[0].0.legs[0].arr_time);
+
+        // Leaving after the departure to south
+        query.departure = 71;
+        let journeys = sets.nearest(&t, "hospitals", &query).unwrap();
+        assert!(journeys.is_empty());
+
+        assert!(sets.nearest(&t, "schools", &query).is_none());
+        assert!(sets.remove("hospitals"));
+        assert!(!sets.remove("hospitals"));
+        assert_eq!(0, sets.iter().count());
+    }
+}
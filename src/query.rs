@@ -0,0 +1,799 @@
+use crate::algo::{self, Profile, ScanStats};
+use crate::journey::Journey;
+use crate::structures::{Footpath, Network, Timetable};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// How the traveller walks, for people with reduced mobility
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryOptions {
+    // Relative to the speed used to compute the footpaths, 0.5 takes twice as long to walk
+    pub walking_speed: f64,
+    // The footpaths with stairs are not used
+    pub step_free: bool,
+    // In seconds, the longer routes are never kept
+    pub max_duration: Option<u32>,
+    // How many seconds every walked second adds to the perceived arrival, 0 keeps the fastest journeys
+    // With 2, walking 3 minutes less is worth arriving up to 6 minutes later
+    pub walking_reluctance: f64,
+    // Only the stops in these fare zones are used, like for a pass for zones 1 to 3
+    // The stops without a zone are always allowed
+    pub allowed_zones: Option<HashSet<String>>,
+    // In seconds, added to the perceived arrival for every leg in a vehicle of the agency, by agency_id
+    // The journeys with these agencies are still found, but the others are preferred
+    pub agency_penalties: HashMap<String, u32>,
+    // For the legs of the agencies without a penalty, a preferred agency has a penalty of 0
+    pub default_agency_penalty: u32,
+    // In seconds, by route_id, like 600 for an unreliable line: it is only used when much faster
+    // It adds up with the penalty of the agency
+    pub route_penalties: HashMap<String, u32>,
+    // When the scan takes longer, it stops with the profiles found so far (see ScanStats::partial)
+    // It is measured with the system clock, that is not available in WebAssembly
+    pub budget: Option<Duration>,
+    // By stop, a time before which the origins can not be there, from `Landmarks::prune`
+    // The connections leaving earlier are not scanned
+    pub earliest_reach: Option<Arc<Vec<u32>>>,
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self {
+            walking_speed: 1.,
+            step_free: false,
+            max_duration: None,
+            walking_reluctance: 0.,
+            allowed_zones: None,
+            agency_penalties: HashMap::new(),
+            default_agency_penalty: 0,
+            route_penalties: HashMap::new(),
+            budget: None,
+            earliest_reach: None,
+        }
+    }
+}
+
+impl QueryOptions {
+    // None if the footpath can not be used
+    pub fn duration(&self, footpath: &Footpath) -> Option<u32> {
+        if self.step_free && footpath.stairs {
+            None
+        } else if (self.walking_speed - 1.).abs() < f64::EPSILON {
+            Some(footpath.duration)
+        } else {
+            Some((f64::from(footpath.duration) / self.walking_speed).round() as u32)
+        }
+    }
+
+    // The perceived cost of walking `duration` seconds, on top of the duration itself
+    pub fn walking_penalty(&self, duration: u32) -> u32 {
+        if self.walking_reluctance > 0. {
+            (f64::from(duration) * self.walking_reluctance).round() as u32
+        } else {
+            0
+        }
+    }
+
+    // By stop index, None when every stop is allowed
+    pub fn allowed_stops(&self, timetable: &Timetable) -> Option<Vec<bool>> {
+        let zones = self.allowed_zones.as_ref()?;
+        let allowed = timetable.stops.iter().map(|stop| {
+            stop.zone_id
+                .as_ref()
+                .is_none_or(|zone| zones.contains(zone))
+        });
+        Some(allowed.collect())
+    }
+
+    // The penalty of a leg by route index, None when no agency or route has a penalty
+    // The trips without a route count as the agencies without a penalty
+    pub fn leg_penalties(&self, timetable: &Timetable) -> Option<Vec<u32>> {
+        if self.agency_penalties.is_empty()
+            && self.default_agency_penalty == 0
+            && self.route_penalties.is_empty()
+        {
+            return None;
+        }
+        let penalties = timetable.routes.iter().map(|route| {
+            let agency = route
+                .agency_id
+                .as_ref()
+                .and_then(|agency| self.agency_penalties.get(agency))
+                .copied()
+                .unwrap_or(self.default_agency_penalty);
+            let route = self.route_penalties.get(&route.id).copied().unwrap_or(0);
+            agency.saturating_add(route)
+        });
+        Some(penalties.collect())
+    }
+
+    pub fn is_too_long(&self, dep_time: u32, arr_time: u32) -> bool {
+        self.max_duration
+            .is_some_and(|max| arr_time.saturating_sub(dep_time) > max)
+    }
+
+    pub fn footpath_duration(&self, timetable: &Timetable, from: usize, to: usize) -> Option<u32> {
+        timetable.footpaths[to]
+            .iter()
+            .filter(|footpath| footpath.from == from)
+            .filter_map(|footpath| self.duration(footpath))
+            .min()
+    }
+}
+
+// Statistics of a query, to understand the slow ones
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+    #[serde(flatten)]
+    pub scan: ScanStats,
+    pub scan_ms: f64,
+    // Rebuilding the journeys from the profiles
+    pub reconstruction_ms: f64,
+}
+
+pub fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
+
+// Why a query can not be computed, found before scanning any connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryError {
+    NoOrigin,
+    NoDestination,
+    UnknownStop(usize),
+    // After the days loaded in the timetable
+    OutsideHorizon(u32),
+    InvertedTimeWindow {
+        departure: u32,
+        latest_departure: u32,
+    },
+}
+
+impl std::fmt::Display for QueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            QueryError::NoOrigin => write!(f, "The query has no origin"),
+            QueryError::NoDestination => write!(f, "The query has no destination"),
+            QueryError::UnknownStop(stop) => write!(f, "Unknown stop index {}", stop),
+            QueryError::OutsideHorizon(time) => write!(
+                f,
+                "The departure {} is after the last day of the timetable",
+                time
+            ),
+            QueryError::InvertedTimeWindow {
+                departure,
+                latest_departure,
+            } => write!(
+                f,
+                "The latest departure {} is before the departure {}",
+                latest_departure, departure
+            ),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+// How the journeys of a query are computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithm {
+    // The profiles of every departure, for the next optimal journeys
+    #[default]
+    Profile,
+    // Only the journey arriving the earliest, much cheaper for interactive requests
+    EarliestArrival,
+}
+
+impl Algorithm {
+    // A new backend is added here, so that `comparison` checks it against the others
+    pub const ALL: [Algorithm; 2] = [Algorithm::Profile, Algorithm::EarliestArrival];
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "profile" => Ok(Algorithm::Profile),
+            "earliest-arrival" => Ok(Algorithm::EarliestArrival),
+            _ => Err(format!(
+                "Unknown algorithm {}, expected profile or earliest-arrival",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Algorithm::Profile => write!(f, "profile"),
+            Algorithm::EarliestArrival => write!(f, "earliest-arrival"),
+        }
+    }
+}
+
+// A journey request from a set of stops to an other, leaving after `departure`
+#[derive(Debug, Clone)]
+pub struct Query {
+    pub origin: Vec<usize>,
+    pub destination: Vec<usize>,
+    // Seconds since midnight of the first day of the timetable
+    pub departure: u32,
+    // The journeys leaving later are not returned, even if there are fewer than `count`
+    pub latest_departure: Option<u32>,
+    // How many journeys are returned at most
+    pub count: usize,
+    pub options: QueryOptions,
+    pub algorithm: Algorithm,
+}
+
+impl Query {
+    pub fn new(origin: Vec<usize>, destination: Vec<usize>, departure: u32) -> Self {
+        Self {
+            origin,
+            destination,
+            departure,
+            latest_departure: None,
+            count: 3,
+            options: QueryOptions::default(),
+            algorithm: Algorithm::Profile,
+        }
+    }
+
+    // Leaving now, None if the timetable has no more departures
+    pub fn now(timetable: &Timetable, origin: Vec<usize>, destination: Vec<usize>) -> Option<Self> {
+        let departure = timetable.local_time(chrono::Utc::now())?;
+        Some(Self::new(origin, destination, departure))
+    }
+
+    // Leaving at a date and time of the timetable, None if it is before its first day
+    pub fn at(
+        timetable: &Timetable,
+        origin: Vec<usize>,
+        destination: Vec<usize>,
+        departure: chrono::NaiveDateTime,
+    ) -> Option<Self> {
+        let departure = timetable.time(departure)?;
+        Some(Self::new(origin, destination, departure))
+    }
+
+    // None if it is before the first day of the timetable
+    pub fn leaving_before(
+        mut self,
+        timetable: &Timetable,
+        latest_departure: chrono::NaiveDateTime,
+    ) -> Option<Self> {
+        self.latest_departure = Some(timetable.time(latest_departure)?);
+        Some(self)
+    }
+
+    pub fn departure_datetime(&self, timetable: &Timetable) -> chrono::NaiveDateTime {
+        timetable.datetime(self.departure)
+    }
+
+    // The stops must exist and the timetable must have departures left
+    pub fn validate(&self, timetable: &Timetable) -> Result<(), QueryError> {
+        if self.origin.is_empty() {
+            return Err(QueryError::NoOrigin);
+        }
+        if self.destination.is_empty() {
+            return Err(QueryError::NoDestination);
+        }
+        let stops = self.origin.iter().chain(&self.destination);
+        if let Some(&stop) = stops.into_iter().find(|&&s| s >= timetable.stops.len()) {
+            return Err(QueryError::UnknownStop(stop));
+        }
+        if self.departure >= timetable.end_time() {
+            return Err(QueryError::OutsideHorizon(self.departure));
+        }
+        match self.latest_departure {
+            Some(latest_departure) if latest_departure < self.departure => {
+                Err(QueryError::InvertedTimeWindow {
+                    departure: self.departure,
+                    latest_departure,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Like `journeys`, but an invalid query is an error instead of a panic or no journey
+    pub fn checked_journeys<N: Network>(&self, network: &N) -> Result<Vec<Journey>, QueryError> {
+        self.validate(network.timetable())?;
+        Ok(self.journeys(network))
+    }
+
+    pub fn journeys<N: Network>(&self, network: &N) -> Vec<Journey> {
+        if self.algorithm == Algorithm::EarliestArrival {
+            return self.journeys_with_stats(network).0;
+        }
+        let profiles =
+            algo::compute_after(network, &self.destination, self.departure, &self.options);
+        self.journeys_from_profiles(network, &profiles)
+    }
+
+    // Like `journeys`, measuring every phase
+    pub fn journeys_with_stats<N: Network>(&self, network: &N) -> (Vec<Journey>, Stats) {
+        if self.algorithm == Algorithm::EarliestArrival {
+            return self.earliest_arrival_journey(network);
+        }
+        let start = Instant::now();
+        let (profiles, scan) =
+            algo::compute_with_stats(network, &self.destination, self.departure, &self.options);
+        let scan_ms = milliseconds(start.elapsed());
+        let start = Instant::now();
+        let journeys = self.journeys_from_profiles(network, &profiles);
+        let stats = Stats {
+            scan,
+            scan_ms,
+            reconstruction_ms: milliseconds(start.elapsed()),
+        };
+        (journeys, stats)
+    }
+
+    // At most one journey, leaving before `latest_departure`
+    fn earliest_arrival_journey<N: Network>(&self, network: &N) -> (Vec<Journey>, Stats) {
+        let start = Instant::now();
+        let (route, scan) = algo::earliest_arrival_route(
+            network,
+            &self.origin,
+            &self.destination,
+            self.departure,
+            &self.options,
+        );
+        let scan_ms = milliseconds(start.elapsed());
+        let start = Instant::now();
+        let journeys = route
+            .map(|(origin, route)| {
+                let route: Vec<_> = route.iter().map(|&c| network.connection(c)).collect();
+                let timetable = network.timetable();
+                Journey::with_options(timetable, origin, &route, &self.destination, &self.options)
+            })
+            .filter(|j| {
+                self.latest_departure
+                    .is_none_or(|l| j.dep_time().is_some_and(|d| d <= l))
+            })
+            .into_iter()
+            .take(self.count)
+            .collect();
+        let stats = Stats {
+            scan,
+            scan_ms,
+            reconstruction_ms: milliseconds(start.elapsed()),
+        };
+        (journeys, stats)
+    }
+
+    // The next optimal journeys, by increasing departure time
+    pub fn journeys_from_profiles<N: Network>(
+        &self,
+        network: &N,
+        profiles: &[Vec<Profile>],
+    ) -> Vec<Journey> {
+        let timetable = network.timetable();
+        let mut candidates: Vec<_> = self
+            .origin
+            .iter()
+            .flat_map(|&origin| profiles[origin].iter().map(move |p| (origin, p)))
+            .filter(|(_, p)| p.out_connection.is_some() && p.dep_time >= self.departure)
+            .filter(|(_, p)| self.latest_departure.is_none_or(|l| p.dep_time <= l))
+            .collect();
+
+        // With multiple origins, a profile can be dominated by a profile from another origin
+        candidates.sort_by_key(|(_, p)| (std::cmp::Reverse(p.dep_time), p.cost()));
+        let mut best_cost = u32::MAX;
+        let mut selected = Vec::new();
+        for (origin, profile) in candidates {
+            if profile.cost() < best_cost {
+                best_cost = profile.cost();
+                selected.push((origin, profile));
+            }
+        }
+
+        // Journeys with the same trips only differ by the stop where they start or end
+        // (like sibling platforms), only the shortest one is kept
+        let mut journeys: Vec<Journey> = Vec::new();
+        for (origin, profile) in selected.iter().rev() {
+            let route = profile.route(profiles, network);
+            let journey =
+                Journey::with_options(timetable, *origin, &route, &self.destination, &self.options);
+            let trips = journey.trips();
+            match journeys.iter().position(|j| j.trips() == trips) {
+                Some(i) if journey.duration() < journeys[i].duration() => journeys[i] = journey,
+                Some(_) => (),
+                None if journeys.len() < self.count => journeys.push(journey),
+                None => break,
+            }
+        }
+        journeys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_journeys() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("a", "1:10")
+            .s("b", "1:20")
+            .trip()
+            .s("a", "2:10")
+            .s("b", "2:20");
+        let t = b.build();
+
+        let journeys = Query::new(vec![0], vec![1], 15).journeys(&t);
+        assert_eq!(2, journeys.len());
+        assert_eq!(Some(70), journeys[0].dep_time());
+        assert_eq!(Some(130), journeys[1].dep_time());
+
+        let mut query = Query::new(vec![0], vec![1], 0);
+        query.count = 1;
+        let journeys = query.journeys(&t);
+        assert_eq!(1, journeys.len());
+        assert_eq!(Some(10), journeys[0].dep_time());
+    }
+
+    #[test]
+    fn multiple_origins() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("c", "0:50")
+            .trip()
+            .s("b", "0:20")
+            .s("c", "0:30");
+        let t = b.build();
+
+        // Leaving from b is both later and faster
+        let journeys = Query::new(vec![0, 2], vec![1], 0).journeys(&t);
+        assert_eq!(1, journeys.len());
+        assert_eq!(2, journeys[0].legs[0].from);
+    }
+
+    #[test]
+    fn step_free() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("c", "0:30")
+            .s("d", "0:40");
+        let mut t = b.build();
+        t.insert_footpath(
+            2,
+            Footpath {
+                from: 1,
+                duration: 3,
+                stairs: true,
+            },
+        );
+
+        let mut query = Query::new(vec![0], vec![3], 0);
+        assert_eq!(1, query.journeys(&t).len());
+        query.options.step_free = true;
+        assert!(query.journeys(&t).is_empty());
+
+        // A longer step-free alternative
+        t.insert_footpath(
+            2,
+            Footpath {
+                from: 1,
+                duration: 4,
+                stairs: false,
+            },
+        );
+        let journeys = query.journeys(&t);
+        assert_eq!(4, journeys[0].walking_time());
+    }
+
+    #[test]
+    fn walking_speed() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("c", "0:30")
+            .s("d", "0:40");
+        let mut t = b.build();
+        t.add_footpath(1, 2, 2);
+
+        let mut query = Query::new(vec![0], vec![3], 0);
+        query.options.walking_speed = 0.5;
+        assert_eq!(4, query.journeys(&t)[0].walking_time());
+        // Walking 3 times slower misses the connection
+        query.options.walking_speed = 0.3;
+        assert!(query.journeys(&t).is_empty());
+    }
+
+    #[test]
+    fn walking_reluctance() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("c", "1:30")
+            .s("d", "1:40")
+            .trip()
+            .s("a", "0:05")
+            .s("d", "2:00");
+        let mut t = b.build();
+        t.add_footpath(1, 2, 60);
+
+        let mut query = Query::new(vec![0], vec![3], 0);
+        let journeys = query.journeys(&t);
+        assert_eq!(1, journeys.len());
+        assert_eq!(60, journeys[0].walking_time());
+
+        // Walking a minute less is worth arriving up to a minute later
+        query.options.walking_reluctance = 1.;
+        let journeys = query.journeys(&t);
+        assert_eq!(2, journeys.len());
+        assert_eq!(Some(120), journeys[0].arr_time());
+        assert_eq!(0, journeys[0].walking_time());
+    }
+
+    #[test]
+    fn allowed_zones() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .s("c", "0:30")
+            .trip()
+            .s("a", "0:15")
+            .s("c", "0:50");
+        let mut t = b.build();
+        t.stops[0].zone_id = Some("1".to_owned());
+        t.stops[1].zone_id = Some("4".to_owned());
+
+        let mut query = Query::new(vec![0], vec![2], 0);
+        assert_eq!(Some(30), query.journeys(&t)[0].arr_time());
+        // The first trip goes through the zone 4, even without alighting there
+        query.options.allowed_zones = Some(["1", "2", "3"].iter().map(|z| z.to_string()).collect());
+        let journeys = query.journeys(&t);
+        assert_eq!(1, journeys.len());
+        assert_eq!(Some(50), journeys[0].arr_time());
+    }
+
+    #[test]
+    fn agency_penalties() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:30")
+            .trip()
+            .s("a", "0:05")
+            .s("b", "0:40");
+        let mut t = b.build();
+        for (i, agency) in ["A", "B"].iter().enumerate() {
+            t.routes.push(crate::structures::Route {
+                id: i.to_string(),
+                short_name: String::new(),
+                long_name: String::new(),
+                route_type: crate::structures::RouteType::Bus,
+                agency_id: Some(agency.to_string()),
+                agency_name: None,
+            });
+            t.trips[i].route = Some(i);
+        }
+
+        let mut query = Query::new(vec![0], vec![1], 0);
+        assert_eq!(1, query.journeys(&t).len());
+        query.options.agency_penalties.insert("A".to_owned(), 20);
+        let journeys = query.journeys(&t);
+        assert_eq!(2, journeys.len());
+        assert_eq!(vec![1], journeys[0].trips());
+
+        // Preferring B is the same as avoiding all the others
+        query.options.agency_penalties.clear();
+        query.options.agency_penalties.insert("B".to_owned(), 0);
+        query.options.default_agency_penalty = 20;
+        assert_eq!(2, query.journeys(&t).len());
+
+        // The penalties are counted once per leg, the journey is rebuilt with both legs
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:30")
+            .trip()
+            .s("b", "0:45")
+            .s("c", "0:55");
+        let mut t2 = b.build();
+        t2.routes = t.routes.clone();
+        t2.trips[0].route = Some(0);
+        query.destination = vec![2];
+        let journeys = query.journeys(&t2);
+        assert_eq!(1, journeys.len());
+        assert_eq!(vec![0, 1], journeys[0].trips());
+    }
+
+    #[test]
+    fn route_penalties() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "1:00")
+            .trip()
+            .s("a", "0:05")
+            .s("b", "10:00");
+        let mut t = b.build();
+        t.routes.push(crate::structures::Route {
+            id: "RER B".to_owned(),
+            short_name: "B".to_owned(),
+            long_name: String::new(),
+            route_type: crate::structures::RouteType::Rail,
+            agency_id: None,
+            agency_name: None,
+        });
+        t.trips[0].route = Some(0);
+
+        // Still the fallback when the other trip is much longer
+        let mut query = Query::new(vec![0], vec![1], 0);
+        query.options.route_penalties.insert("RER B".to_owned(), 60);
+        let journeys = query.journeys(&t);
+        assert_eq!(1, journeys.len());
+        assert_eq!(vec![0], journeys[0].trips());
+
+        query
+            .options
+            .route_penalties
+            .insert("RER B".to_owned(), 600);
+        let journeys = query.journeys(&t);
+        assert_eq!(2, journeys.len());
+        assert_eq!(vec![1], journeys[0].trips());
+    }
+
+    #[test]
+    fn now() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "1440:00").s("b", "1450:00");
+        let mut t = b.build();
+        // The timetable of 2019 is over
+        assert!(Query::now(&t, vec![0], vec![1]).is_none());
+
+        t.start_date = chrono::Utc::now().naive_utc().date();
+        let query = Query::now(&t, vec![0], vec![1]).unwrap();
+        assert!(query.departure <= 86_400);
+        assert_eq!(1, query.journeys(&t).len());
+    }
+
+    #[test]
+    fn same_trips() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a1", "0:10")
+            .s("b1", "0:20")
+            .s("a2", "0:22")
+            .s("b2", "0:25");
+        let t = b.build();
+
+        // From a1 to b1 and from a2 to b2 are both optimal, with the same trip
+        let journeys = Query::new(vec![0, 2], vec![1, 3], 0).journeys(&t);
+        assert_eq!(1, journeys.len());
+        assert_eq!(2, journeys[0].legs[0].from);
+        assert_eq!(3, journeys[0].legs[0].to);
+    }
+
+    #[test]
+    fn max_duration() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:35")
+            .trip()
+            .s("a", "0:30")
+            .s("b", "0:40");
+        let t = b.build();
+
+        let mut query = Query::new(vec![0], vec![1], 0);
+        assert_eq!(2, query.journeys(&t).len());
+        query.options.max_duration = Some(20);
+        let journeys = query.journeys(&t);
+        assert_eq!(1, journeys.len());
+        assert_eq!(Some(30), journeys[0].dep_time());
+
+        query.options.max_duration = Some(5);
+        assert!(query.journeys(&t).is_empty());
+    }
+
+    #[test]
+    fn earliest_arrival() {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("c", "0:30")
+            .s("d", "0:40")
+            .trip()
+            .s("a", "0:05")
+            .s("d", "1:00")
+            .trip()
+            .s("e", "0:01")
+            .s("a", "0:02")
+            .s("b", "0:03")
+            .trip()
+            .s("a", "1:10")
+            .s("b", "1:20");
+        let mut t = b.build();
+        t.add_footpath(1, 2, 5);
+
+        let mut query = Query::new(vec![0], vec![3], 0);
+        let profile = query.journeys(&t);
+        query.algorithm = Algorithm::EarliestArrival;
+        let (journeys, stats) = query.journeys_with_stats(&t);
+        assert_eq!(1, journeys.len());
+        assert_eq!(profile[0].legs, journeys[0].legs);
+        assert_eq!(3, journeys[0].legs.len());
+        assert_eq!(Some(40), journeys[0].arr_time());
+        // The scan stops at the arrival
+        assert_eq!(t.connections.len() - 1, stats.scan.connections_scanned);
+
+        // Only later departures
+        query.departure = 11;
+        assert!(query.journeys(&t).is_empty());
+        query.departure = 0;
+        query.latest_departure = Some(1);
+        assert!(query.journeys(&t).is_empty());
+
+        // A stop that is not allowed can not be passed through
+        let mut query = Query::new(vec![4], vec![1], 0);
+        query.algorithm = Algorithm::EarliestArrival;
+        assert_eq!(Some(3), query.journeys(&t)[0].arr_time());
+        t.stops[0].zone_id = Some("2".to_owned());
+        t.stops[4].zone_id = Some("1".to_owned());
+        query.options.allowed_zones = Some(std::iter::once("1".to_owned()).collect());
+        assert!(query.journeys(&t).is_empty());
+
+        assert_eq!(Ok(Algorithm::EarliestArrival), "earliest-arrival".parse());
+        assert!("dijkstra".parse::<Algorithm>().is_err());
+        assert_eq!("earliest-arrival", Algorithm::EarliestArrival.to_string());
+    }
+
+    #[test]
+    fn validation() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("b", "0:20");
+        b.trip().s("a", "1:10").s("b", "1:20");
+        let t = b.build();
+
+        assert_eq!(Ok(()), Query::new(vec![0], vec![1], 0).validate(&t));
+        assert_eq!(
+            Err(QueryError::NoOrigin),
+            Query::new(vec![], vec![1], 0).validate(&t)
+        );
+        assert_eq!(
+            Err(QueryError::UnknownStop(7)),
+            Query::new(vec![0], vec![1, 7], 0).checked_journeys(&t)
+        );
+        // After the last departure, but still on the first day
+        assert_eq!(Ok(()), Query::new(vec![0], vec![1], 71).validate(&t));
+        assert_eq!(
+            Err(QueryError::OutsideHorizon(86_400)),
+            Query::new(vec![0], vec![1], 86_400).validate(&t)
+        );
+
+        let mut query = Query::new(vec![0], vec![1], 30);
+        query.latest_departure = Some(20);
+        assert!(matches!(
+            query.validate(&t),
+            Err(QueryError::InvertedTimeWindow { .. })
+        ));
+        query.departure = 0;
+        let journeys = query.checked_journeys(&t).unwrap();
+        assert_eq!(1, journeys.len());
+        assert_eq!(Some(10), journeys[0].dep_time());
+    }
+}
@@ -0,0 +1,222 @@
+// Logs the queries of the server as JSON lines, to replay real traffic in the benchmark
+// Only the query itself is kept, rounded to a few minutes, never who asked it
+use crate::query::{Algorithm, Query};
+use crate::structures::Timetable;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// In seconds, the departures are rounded down to it
+const DEPARTURE_ROUNDING: u32 = 300;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggedQuery {
+    // The stop ids, so that the log can be replayed with another version of the timetable
+    pub origin: Vec<String>,
+    pub destination: Vec<String>,
+    // Seconds since midnight of the first day of the timetable
+    pub departure: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latest_departure: Option<u32>,
+    pub count: usize,
+    #[serde(default)]
+    pub step_free: bool,
+    #[serde(default = "default_walking_speed")]
+    pub walking_speed: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_duration: Option<u32>,
+    #[serde(default)]
+    pub walking_reluctance: f64,
+    #[serde(default)]
+    pub earliest_arrival: bool,
+    // Sorted, so that the same query is always logged the same way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_zones: Option<BTreeSet<String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub agency_penalties: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub default_agency_penalty: u32,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub route_penalties: BTreeMap<String, u32>,
+    // In milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_ms: Option<u64>,
+}
+
+fn default_walking_speed() -> f64 {
+    1.
+}
+
+impl LoggedQuery {
+    pub fn new(timetable: &Timetable, query: &Query) -> Self {
+        let ids = |stops: &[usize]| {
+            stops
+                .iter()
+                .map(|&s| timetable.stops[s].id.to_owned())
+                .collect()
+        };
+        let round = |time: u32| time - time % DEPARTURE_ROUNDING;
+        Self {
+            origin: ids(&query.origin),
+            destination: ids(&query.destination),
+            departure: round(query.departure),
+            latest_departure: query.latest_departure.map(round),
+            count: query.count,
+            step_free: query.options.step_free,
+            walking_speed: query.options.walking_speed,
+            max_duration: query.options.max_duration,
+            walking_reluctance: query.options.walking_reluctance,
+            earliest_arrival: query.algorithm == Algorithm::EarliestArrival,
+            allowed_zones: query
+                .options
+                .allowed_zones
+                .as_ref()
+                .map(|zones| zones.iter().cloned().collect()),
+            agency_penalties: query.options.agency_penalties.clone().into_iter().collect(),
+            default_agency_penalty: query.options.default_agency_penalty,
+            route_penalties: query.options.route_penalties.clone().into_iter().collect(),
+            budget_ms: query.options.budget.map(|budget| budget.as_millis() as u64),
+        }
+    }
+
+    // None when none of the origins or destinations are in the timetable
+    pub fn query(&self, timetable: &Timetable) -> Option<Query> {
+        let indices = |ids: &[String]| -> Vec<usize> {
+            ids.iter()
+                .filter_map(|id| timetable.stop_index_by_id(id))
+                .collect()
+        };
+        let (origin, destination) = (indices(&self.origin), indices(&self.destination));
+        if origin.is_empty() || destination.is_empty() {
+            return None;
+        }
+        let mut query = Query::new(origin, destination, self.departure);
+        query.latest_departure = self.latest_departure;
+        query.count = self.count;
+        query.options.step_free = self.step_free;
+        query.options.walking_speed = self.walking_speed;
+        query.options.max_duration = self.max_duration;
+        query.options.walking_reluctance = self.walking_reluctance;
+        query.options.allowed_zones = self
+            .allowed_zones
+            .as_ref()
+            .map(|zones| zones.iter().cloned().collect());
+        query.options.agency_penalties = self.agency_penalties.clone().into_iter().collect();
+        query.options.default_agency_penalty = self.default_agency_penalty;
+        query.options.route_penalties = self.route_penalties.clone().into_iter().collect();
+        query.options.budget = self.budget_ms.map(std::time::Duration::from_millis);
+        if self.earliest_arrival {
+            query.algorithm = Algorithm::EarliestArrival;
+        }
+        Some(query)
+    }
+}
+
+// Appends the queries to a file, shared by the workers of the server
+pub struct QueryLog {
+    file: Mutex<std::fs::File>,
+}
+
+impl QueryLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn log(&self, timetable: &Timetable, query: &Query) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(&LoggedQuery::new(timetable, query))?;
+        line.push(b'\n');
+        // A single write per line, so that the lines of concurrent queries are not mixed
+        self.file
+            .lock()
+            .expect("Poisoned query log")
+            .write_all(&line)
+    }
+}
+
+// The empty lines are skipped
+pub fn read_log<R: BufRead>(reader: R) -> std::io::Result<Vec<LoggedQuery>> {
+    let mut result = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            result.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "10:10").s("b", "20:20");
+        let t = b.build();
+        let mut query = Query::new(vec![0], vec![1], 601);
+        query.options.step_free = true;
+        query.algorithm = Algorithm::EarliestArrival;
+        query.options.allowed_zones =
+            Some(vec!["1".to_owned(), "2".to_owned()].into_iter().collect());
+        query
+            .options
+            .agency_penalties
+            .insert("ratp".to_owned(), 300);
+        query.options.default_agency_penalty = 60;
+        query
+            .options
+            .route_penalties
+            .insert("rer_b".to_owned(), 600);
+        query.options.budget = Some(std::time::Duration::from_millis(250));
+
+        let path = std::env::temp_dir().join(format!("csa_query_log_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = QueryLog::open(&path).unwrap();
+        log.log(&t, &query).unwrap();
+        log.log(&t, &Query::new(vec![1], vec![0], 0)).unwrap();
+
+        let file = std::io::BufReader::new(std::fs::File::open(&path).unwrap());
+        let logged = read_log(file).unwrap();
+        assert_eq!(2, logged.len());
+        assert_eq!(vec!["a".to_owned()], logged[0].origin);
+        // Rounded to 5 minutes
+        assert_eq!(600, logged[0].departure);
+
+        let replayed = logged[0].query(&t).unwrap();
+        assert_eq!(vec![0], replayed.origin);
+        assert_eq!(600, replayed.departure);
+        assert!(replayed.options.step_free);
+        assert_eq!(Algorithm::EarliestArrival, replayed.algorithm);
+        assert_eq!(query.options.allowed_zones, replayed.options.allowed_zones);
+        assert_eq!(
+            query.options.agency_penalties,
+            replayed.options.agency_penalties
+        );
+        assert_eq!(60, replayed.options.default_agency_penalty);
+        assert_eq!(
+            query.options.route_penalties,
+            replayed.options.route_penalties
+        );
+        assert_eq!(query.options.budget, replayed.options.budget);
+        assert_eq!(1, replayed.journeys(&t).len());
+
+        let other = Timetable::builder().build();
+        assert!(logged[0].query(&other).is_none());
+
+        let minimal = r#"{"origin":["b"],"destination":["a"],"departure":0,"count":3}"#;
+        let logged = read_log(format!("\n{}\n", minimal).as_bytes()).unwrap();
+        assert_eq!(1., logged[0].walking_speed);
+        assert!(logged[0].agency_penalties.is_empty());
+        assert_eq!(None, logged[0].budget_ms);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
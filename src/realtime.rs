@@ -0,0 +1,339 @@
+// Realtime modifications (delays and cancellations of trips) over a shared timetable
+// The connections of the timetable are never copied: only the ones of the delayed trips are
+use crate::structures::{Connection, Network, Timetable};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io::Write;
+use std::iter::{Enumerate, Peekable};
+use std::path::Path;
+use std::slice::Iter;
+use std::sync::Arc;
+
+// By trip index
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Realtime {
+    // In seconds, negative when the trip runs early
+    pub delays: HashMap<usize, i32>,
+    pub cancellations: HashSet<usize>,
+}
+
+impl Realtime {
+    pub fn is_empty(&self) -> bool {
+        self.delays.is_empty() && self.cancellations.is_empty()
+    }
+
+    // It replaces the previous delay of the trip
+    pub fn delay(&mut self, trip: usize, delay: i32) {
+        self.delays.insert(trip, delay);
+    }
+
+    pub fn cancel(&mut self, trip: usize) {
+        self.cancellations.insert(trip);
+    }
+
+    // By trip id and date, as the trip indices can change when the timetable is loaded again
+    pub fn snapshot(&self, timetable: &Timetable) -> Snapshot {
+        let trips: HashSet<_> = self.delays.keys().chain(&self.cancellations).collect();
+        let mut trips: Vec<_> = trips
+            .into_iter()
+            .filter_map(|&trip| {
+                let t = timetable.trips.get(trip)?;
+                Some(TripState {
+                    trip_id: t.id.to_owned(),
+                    date: timetable.start_date + chrono::Duration::days(i64::from(t.day)),
+                    delay: self.delays.get(&trip).copied(),
+                    cancelled: self.cancellations.contains(&trip),
+                })
+            })
+            .collect();
+        trips.sort_by(|a, b| (&a.date, &a.trip_id).cmp(&(&b.date, &b.trip_id)));
+        Snapshot { trips }
+    }
+
+    // The trips that do not run in the timetable are ignored
+    pub fn restore(snapshot: &Snapshot, timetable: &Timetable) -> Self {
+        let indices: HashMap<_, _> = timetable
+            .trips
+            .iter()
+            .enumerate()
+            .map(|(index, trip)| ((trip.id.as_str(), trip.day), index))
+            .collect();
+        let mut realtime = Self::default();
+        for state in &snapshot.trips {
+            let day = (state.date - timetable.start_date).num_days();
+            let trip = u16::try_from(day)
+                .ok()
+                .and_then(|day| indices.get(&(state.trip_id.as_str(), day)));
+            if let Some(&trip) = trip {
+                if let Some(delay) = state.delay {
+                    realtime.delay(trip, delay);
+                }
+                if state.cancelled {
+                    realtime.cancel(trip);
+                }
+            }
+        }
+        realtime
+    }
+
+    // Written to a temporary file first, so that a crash never leaves a truncated snapshot
+    pub fn save<P: AsRef<Path>>(&self, timetable: &Timetable, path: P) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        let mut file = std::fs::File::create(&temporary)?;
+        serde_json::to_writer(&mut file, &self.snapshot(timetable))?;
+        file.flush()?;
+        file.sync_all()?;
+        std::fs::rename(temporary, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(timetable: &Timetable, path: P) -> std::io::Result<Self> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let snapshot: Snapshot = serde_json::from_reader(file)?;
+        Ok(Self::restore(&snapshot, timetable))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TripState {
+    pub trip_id: String,
+    // The day the trip runs
+    pub date: chrono::NaiveDate,
+    pub delay: Option<i32>,
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub trips: Vec<TripState>,
+}
+
+// The timetable as known in realtime, the base timetable can be shared between many views
+pub struct TimetableView {
+    base: Arc<Timetable>,
+    realtime: Realtime,
+    // The trips whose base connections are not scanned
+    modified: Vec<bool>,
+    // The connections of the delayed trips, by decreasing departure time
+    // Their indices follow the ones of the base connections
+    delayed: Vec<Connection>,
+}
+
+fn shift(time: u32, delay: i32) -> u32 {
+    if delay < 0 {
+        time.saturating_sub(delay.unsigned_abs())
+    } else {
+        time.saturating_add(delay as u32)
+    }
+}
+
+impl TimetableView {
+    // The unknown trips are ignored
+    pub fn new(base: Arc<Timetable>, realtime: Realtime) -> Self {
+        let mut modified = vec![false; base.trips.len()];
+        for &trip in realtime.cancellations.iter().chain(realtime.delays.keys()) {
+            if let Some(modified) = modified.get_mut(trip) {
+                *modified = true;
+            }
+        }
+
+        let mut delayed: Vec<_> = base
+            .connections
+            .iter()
+            .filter(|c| modified[c.trip] && !realtime.cancellations.contains(&c.trip))
+            .map(|c| {
+                let delay = realtime.delays.get(&c.trip).copied().unwrap_or(0);
+                Connection {
+                    dep_time: shift(c.dep_time, delay),
+                    arr_time: shift(c.arr_time, delay),
+                    ..c.clone()
+                }
+            })
+            .collect();
+        delayed.sort_by_key(|c| std::cmp::Reverse((c.dep_time, c.arr_time)));
+
+        Self {
+            base,
+            realtime,
+            modified,
+            delayed,
+        }
+    }
+
+    pub fn base(&self) -> &Arc<Timetable> {
+        &self.base
+    }
+
+    pub fn realtime(&self) -> &Realtime {
+        &self.realtime
+    }
+}
+
+// Merges the unmodified base connections with the delayed ones
+pub struct ViewConnections<'a> {
+    base: Enumerate<Iter<'a, Connection>>,
+    next_base: Option<(usize, &'a Connection)>,
+    modified: &'a [bool],
+    delayed: Peekable<Enumerate<Iter<'a, Connection>>>,
+    offset: usize,
+}
+
+impl<'a> ViewConnections<'a> {
+    fn advance_base(&mut self) {
+        let modified = self.modified;
+        self.next_base = self.base.find(|(_, c)| !modified[c.trip]);
+    }
+}
+
+impl<'a> Iterator for ViewConnections<'a> {
+    type Item = (usize, &'a Connection);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let base_first = match (self.next_base, self.delayed.peek()) {
+            // Sorted like the base, the base first when they are equal
+            (Some((_, base)), Some((_, delayed))) => {
+                (base.dep_time, base.arr_time) >= (delayed.dep_time, delayed.arr_time)
+            }
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if base_first {
+            let next = self.next_base;
+            self.advance_base();
+            next
+        } else {
+            let offset = self.offset;
+            self.delayed.next().map(|(i, c)| (offset + i, c))
+        }
+    }
+}
+
+impl Network for TimetableView {
+    type Connections<'a> = ViewConnections<'a>;
+
+    fn timetable(&self) -> &Timetable {
+        &self.base
+    }
+
+    fn connection(&self, index: usize) -> &Connection {
+        let base = &self.base.connections;
+        if index < base.len() {
+            &base[index]
+        } else {
+            &self.delayed[index - base.len()]
+        }
+    }
+
+    fn connections(&self) -> ViewConnections<'_> {
+        let mut connections = ViewConnections {
+            base: self.base.connections.iter().enumerate(),
+            next_base: None,
+            modified: &self.modified,
+            delayed: self.delayed.iter().enumerate().peekable(),
+            offset: self.base.connections.len(),
+        };
+        connections.advance_base();
+        connections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+
+    fn timetable() -> Arc<Timetable> {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .trip()
+            .s("a", "0:15")
+            .s("b", "0:40")
+            .trip()
+            .s("a", "0:30")
+            .s("b", "0:45");
+        Arc::new(b.build())
+    }
+
+    #[test]
+    fn merged_connections() {
+        let base = timetable();
+        let mut realtime = Realtime::default();
+        realtime.delay(0, 25);
+        realtime.cancel(2);
+        let view = TimetableView::new(base.clone(), realtime);
+
+        let connections: Vec<_> = view.connections().map(|(i, c)| (i, c.dep_time)).collect();
+        assert_eq!(vec![(3, 35), (1, 15)], connections);
+        assert_eq!(45, view.connection(3).arr_time);
+        // The base is shared and left untouched
+        assert_eq!(3, base.connections.len());
+        assert_eq!(2, Arc::strong_count(&base));
+    }
+
+    #[test]
+    fn tied_departures() {
+        let mut realtime = Realtime::default();
+        // Leaves at 0:10 like the first trip, but arrives later
+        realtime.delay(1, -5);
+        let view = TimetableView::new(timetable(), realtime);
+        let connections: Vec<_> = view
+            .connections()
+            .map(|(i, c)| (i, c.dep_time, c.arr_time))
+            .collect();
+        assert_eq!(vec![(0, 30, 45), (3, 10, 35), (2, 10, 20)], connections);
+    }
+
+    #[test]
+    fn realtime_journeys() {
+        let base = timetable();
+        let query = Query::new(vec![0], vec![1], 0);
+        assert_eq!(Some(20), query.journeys(base.as_ref())[0].arr_time());
+
+        let mut realtime = Realtime::default();
+        realtime.cancel(0);
+        realtime.delay(1, -5);
+        let view = TimetableView::new(base, realtime);
+        let journeys = query.journeys(&view);
+        assert_eq!(Some(10), journeys[0].dep_time());
+        assert_eq!(Some(35), journeys[0].arr_time());
+        assert_eq!(2, journeys.len());
+    }
+
+    #[test]
+    fn snapshot() {
+        let base = timetable();
+        let mut realtime = Realtime::default();
+        realtime.delay(0, 25);
+        realtime.cancel(2);
+        realtime.cancel(7);
+        let snapshot = realtime.snapshot(&base);
+        assert_eq!(2, snapshot.trips.len());
+        assert_eq!("trip0", snapshot.trips[0].trip_id);
+        assert_eq!(Some(25), snapshot.trips[0].delay);
+
+        // The same trips in another order
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:30").s("b", "0:45");
+        let mut reloaded = b.build();
+        reloaded.trips[0].id = "trip2".to_owned();
+        reloaded.trips.push(crate::structures::Trip {
+            id: "trip0".to_owned(),
+            day: 0,
+            route: None,
+            block_id: None,
+        });
+        let restored = Realtime::restore(&snapshot, &reloaded);
+        assert_eq!(Some(&25), restored.delays.get(&1));
+        assert!(restored.cancellations.contains(&0));
+
+        let path = std::env::temp_dir().join(format!("csa-snapshot-{}.json", std::process::id()));
+        realtime.save(&base, &path).unwrap();
+        let loaded = Realtime::load(&base, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        realtime.cancellations.remove(&7);
+        assert_eq!(realtime, loaded);
+    }
+}
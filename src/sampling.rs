@@ -0,0 +1,121 @@
+// Reproducible random queries, to compare the performance across feeds and machines
+// The generator is implemented here so that a seed gives the same queries with every version
+use crate::query::Query;
+use crate::structures::Timetable;
+
+// How many times an origin and a destination are drawn before giving up, like in a single stop area
+const MAX_ATTEMPTS: usize = 100;
+// The departure is at most this many seconds before the connection leaving the origin
+const MAX_WAIT: u32 = 3600;
+
+// SplitMix64, small and good enough to sample queries
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+// The origins and destinations are drawn from random connections, so that the busy stops are more
+// often used, like in real requests
+// The queries are between stop areas and leave up to an hour before a departure from the origin
+pub struct QuerySampler<'a> {
+    timetable: &'a Timetable,
+    random: Random,
+}
+
+impl<'a> QuerySampler<'a> {
+    pub fn new(timetable: &'a Timetable, seed: u64) -> Self {
+        Self {
+            timetable,
+            random: Random { state: seed },
+        }
+    }
+
+    // The stops of the stop area of the stop
+    fn stop_area(&self, stop: usize) -> Vec<usize> {
+        let stop = &self.timetable.stops[stop];
+        self.timetable
+            .stop_indices(stop.parent_station.as_deref().unwrap_or(&stop.id))
+    }
+}
+
+impl Iterator for QuerySampler<'_> {
+    type Item = Query;
+
+    // None when no two stop areas are connected
+    fn next(&mut self) -> Option<Query> {
+        let connections = &self.timetable.connections;
+        if connections.is_empty() {
+            return None;
+        }
+        for _ in 0..MAX_ATTEMPTS {
+            let departure = &connections[self.random.below(connections.len())];
+            let arrival = &connections[self.random.below(connections.len())];
+            let origin = self.stop_area(departure.dep_stop);
+            let destination = self.stop_area(arrival.arr_stop);
+            if origin.iter().any(|stop| destination.contains(stop)) {
+                continue;
+            }
+            let wait = self.random.below(MAX_WAIT as usize + 1) as u32;
+            let time = departure.dep_time.saturating_sub(wait);
+            return Some(Query::new(origin, destination, time));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timetable() -> Timetable {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "1:00").s("b", "1:10");
+        b.trip().s("a", "2:00").s("c", "2:10");
+        b.trip().s("a", "3:00").s("b", "3:10").s("c", "3:20");
+        b.build()
+    }
+
+    #[test]
+    fn reproducible() {
+        let t = timetable();
+        let queries = |seed| {
+            QuerySampler::new(&t, seed)
+                .take(20)
+                .map(|q| (q.origin, q.destination, q.departure))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(queries(42), queries(42));
+        assert_ne!(queries(42), queries(43));
+        for (origin, destination, departure) in queries(42) {
+            assert_ne!(origin, destination);
+            assert!(departure <= 180);
+        }
+    }
+
+    #[test]
+    fn weighted_by_connections() {
+        let t = timetable();
+        let queries: Vec<_> = QuerySampler::new(&t, 1).take(1000).collect();
+        // a leaves 3 of the 4 connections, b only 1
+        let from_a = queries.iter().filter(|q| q.origin == vec![0]).count();
+        let from_b = queries.iter().filter(|q| q.origin == vec![1]).count();
+        assert!(from_a > 2 * from_b);
+
+        assert!(QuerySampler::new(&Timetable::builder().build(), 1)
+            .next()
+            .is_none());
+    }
+}
@@ -0,0 +1,374 @@
+// What-if studies, like "what happens to travel times if line A is closed"
+// The modifications are applied over a base timetable, that is never modified
+use crate::journey::Journey;
+use crate::query::Query;
+use crate::structures::{Connection, Timetable, Trip};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+
+pub struct Scenario<'a> {
+    base: &'a Timetable,
+    removed_trips: HashSet<usize>,
+    closed_stops: HashSet<usize>,
+    // In seconds, by route index
+    route_delays: HashMap<usize, u32>,
+    // The base trips run once more, shifted by the seconds
+    duplicated_trips: Vec<(usize, u32)>,
+}
+
+// How the travel times change with the modifications, for a set of queries
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Impact {
+    pub queries: usize,
+    // Reachable both in the base timetable and with the modifications
+    pub compared: usize,
+    pub faster: usize,
+    pub slower: usize,
+    pub newly_reachable: usize,
+    pub unreachable: usize,
+    // In seconds, of the earliest arrival over the compared queries, negative when faster
+    pub total_change: i64,
+    pub mean_change: f64,
+}
+
+impl Impact {
+    fn add(&mut self, base: Option<u32>, modified: Option<u32>) {
+        self.queries += 1;
+        match (base, modified) {
+            (Some(base), Some(modified)) => {
+                let change = i64::from(modified) - i64::from(base);
+                self.compared += 1;
+                self.total_change += change;
+                match change {
+                    c if c < 0 => self.faster += 1,
+                    c if c > 0 => self.slower += 1,
+                    _ => (),
+                }
+                self.mean_change = self.total_change as f64 / self.compared as f64;
+            }
+            (None, Some(_)) => self.newly_reachable += 1,
+            (Some(_), None) => self.unreachable += 1,
+            (None, None) => (),
+        }
+    }
+}
+
+fn earliest_arrival(journeys: &[Journey]) -> Option<u32> {
+    journeys.iter().filter_map(Journey::arr_time).min()
+}
+
+impl<'a> Scenario<'a> {
+    pub fn new(base: &'a Timetable) -> Self {
+        Self {
+            base,
+            removed_trips: HashSet::new(),
+            closed_stops: HashSet::new(),
+            route_delays: HashMap::new(),
+            duplicated_trips: Vec::new(),
+        }
+    }
+
+    pub fn base(&self) -> &'a Timetable {
+        self.base
+    }
+
+    // The trip is removed on every day of the horizon
+    // The unknown ids are ignored, like for the other modifications
+    pub fn remove_trip(&mut self, trip_id: &str) -> &mut Self {
+        let trips = self.base.trips.iter().enumerate();
+        self.removed_trips
+            .extend(trips.filter(|(_, t)| t.id == trip_id).map(|(i, _)| i));
+        self
+    }
+
+    // All the trips of the route are removed
+    pub fn close_route(&mut self, route_id: &str) -> &mut Self {
+        if let Some(route) = self.route(route_id) {
+            let trips = self.base.trips.iter().enumerate();
+            self.removed_trips.extend(
+                trips
+                    .filter(|(_, t)| t.route == Some(route))
+                    .map(|(i, _)| i),
+            );
+        }
+        self
+    }
+
+    // The vehicles still go through the stop, but nobody can board or alight there
+    pub fn close_stop(&mut self, stop_id: &str) -> &mut Self {
+        if let Some(stop) = self.base.stops.iter().position(|s| s.id == stop_id) {
+            self.closed_stops.insert(stop);
+        }
+        self
+    }
+
+    // Every trip of the route leaves and arrives `delay` seconds later, the delays add up
+    pub fn delay_route(&mut self, route_id: &str, delay: u32) -> &mut Self {
+        if let Some(route) = self.route(route_id) {
+            *self.route_delays.entry(route).or_default() += delay;
+        }
+        self
+    }
+
+    // Every trip of the route leaving its first stop within the seconds of the day (like 7:00 to
+    // 9:00) runs a second time, `shift` seconds later, like to double the frequency between them
+    // with half the headway
+    // The new trips come after the ones of the base timetable, their ids end with `:duplicate`
+    pub fn duplicate_trips(&mut self, route_id: &str, window: Range<u32>, shift: u32) -> &mut Self {
+        if let Some(route) = self.route(route_id) {
+            let mut first_departures: HashMap<usize, u32> = HashMap::new();
+            for c in &self.base.connections {
+                if self.base.trips[c.trip].route == Some(route) {
+                    let departure = first_departures.entry(c.trip).or_insert(c.dep_time);
+                    *departure = (*departure).min(c.dep_time);
+                }
+            }
+            let mut trips: Vec<_> = first_departures
+                .into_iter()
+                .filter(|&(trip, departure)| {
+                    let day = u32::from(self.base.trips[trip].day);
+                    window.contains(&(departure - day * SECONDS_PER_DAY))
+                })
+                .map(|(trip, _)| (trip, shift))
+                .collect();
+            trips.sort_unstable();
+            self.duplicated_trips.extend(trips);
+        }
+        self
+    }
+
+    fn route(&self, route_id: &str) -> Option<usize> {
+        self.base.routes.iter().position(|r| r.id == route_id)
+    }
+
+    fn delay(&self, trip: usize) -> u32 {
+        self.trip(trip)
+            .route
+            .and_then(|route| self.route_delays.get(&route))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // The base trips, then the duplicated ones
+    fn trip(&self, trip: usize) -> &Trip {
+        match trip.checked_sub(self.base.trips.len()) {
+            Some(duplicate) => &self.base.trips[self.duplicated_trips[duplicate].0],
+            None => &self.base.trips[trip],
+        }
+    }
+
+    // The connections of the base timetable and of the duplicated trips
+    fn all_connections(&self) -> Vec<Connection> {
+        let mut duplicates: HashMap<usize, Vec<(usize, u32)>> = HashMap::new();
+        for (index, &(trip, shift)) in self.duplicated_trips.iter().enumerate() {
+            let duplicate = self.base.trips.len() + index;
+            duplicates.entry(trip).or_default().push((duplicate, shift));
+        }
+        let mut result = self.base.connections.clone();
+        for c in &self.base.connections {
+            for &(trip, shift) in duplicates.get(&c.trip).into_iter().flatten() {
+                result.push(Connection {
+                    trip,
+                    dep_time: c.dep_time + shift,
+                    arr_time: c.arr_time + shift,
+                    ..c.clone()
+                });
+            }
+        }
+        result
+    }
+
+    // The connections of the scenario, sorted by decreasing departure time
+    // Riding through a closed stop merges the connections before and after it
+    pub fn connections(&self) -> Vec<Connection> {
+        let connections = self.all_connections();
+        let mut departures: HashMap<(usize, usize), Vec<&Connection>> = HashMap::new();
+        if !self.closed_stops.is_empty() {
+            for c in &connections {
+                departures.entry((c.trip, c.dep_stop)).or_default().push(c);
+            }
+        }
+
+        let mut result: Vec<_> = connections
+            .iter()
+            .filter(|c| !self.removed_trips.contains(&c.trip))
+            .filter(|c| !self.closed_stops.contains(&c.dep_stop))
+            .filter_map(|c| {
+                let mut c = c.clone();
+                while self.closed_stops.contains(&c.arr_stop) {
+                    // The next connection of the trip from the closed stop
+                    let next = departures
+                        .get(&(c.trip, c.arr_stop))?
+                        .iter()
+                        .filter(|next| next.dep_time >= c.arr_time)
+                        .min_by_key(|next| next.dep_time)?;
+                    c.arr_stop = next.arr_stop;
+                    c.arr_time = next.arr_time;
+                }
+                let delay = self.delay(c.trip);
+                c.dep_time += delay;
+                c.arr_time += delay;
+                Some(c)
+            })
+            .collect();
+        result.sort_by_key(|c| std::cmp::Reverse((c.dep_time, c.arr_time)));
+        result
+    }
+
+    // A timetable with the modifications, that can be queried like any other
+    // The stops and trips keep the indices of the base timetable
+    pub fn timetable(&self) -> Timetable {
+        let base = self.base;
+        let mut trips = base.trips.clone();
+        trips.extend(self.duplicated_trips.iter().map(|&(trip, _)| Trip {
+            id: format!("{}:duplicate", base.trips[trip].id),
+            ..base.trips[trip].clone()
+        }));
+        Timetable {
+            start_date: base.start_date,
+            timezone: base.timezone,
+            transform_duration: base.transform_duration,
+            stops: base.stops.clone(),
+            connections: self.connections(),
+            footpaths: base.footpaths.clone(),
+            trips,
+            routes: base.routes.clone(),
+            translations: base.translations.clone(),
+            fare_rules: base.fare_rules.clone(),
+        }
+    }
+
+    // The journeys of the query, both in the base timetable and with the modifications
+    // Build `timetable` once to run many queries
+    pub fn compare(&self, query: &Query) -> (Vec<Journey>, Vec<Journey>) {
+        (query.journeys(self.base), query.journeys(&self.timetable()))
+    }
+
+    // Compares the earliest arrivals of the queries, like the ones of a `QuerySampler`
+    pub fn impact<'q, I: IntoIterator<Item = &'q Query>>(&self, queries: I) -> Impact {
+        let modified = self.timetable();
+        let mut impact = Impact::default();
+        for query in queries {
+            impact.add(
+                earliest_arrival(&query.journeys(self.base)),
+                earliest_arrival(&query.journeys(&modified)),
+            );
+        }
+        impact
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::{Route, RouteType};
+
+    fn timetable() -> Timetable {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .s("c", "0:30")
+            .trip()
+            .s("a", "0:15")
+            .s("c", "0:50");
+        let mut t = b.build();
+        for (i, id) in ["A", "B"].iter().enumerate() {
+            t.routes.push(Route {
+                id: id.to_string(),
+                short_name: id.to_string(),
+                long_name: String::new(),
+                route_type: RouteType::Bus,
+                agency_id: None,
+                agency_name: None,
+            });
+            t.trips[i].route = Some(i);
+        }
+        t
+    }
+
+    #[test]
+    fn closed_route() {
+        let t = timetable();
+        let query = Query::new(vec![0], vec![2], 0);
+        let mut scenario = Scenario::new(&t);
+        scenario.close_route("A");
+        let (base, closed) = scenario.compare(&query);
+        assert_eq!(Some(30), base[0].arr_time());
+        assert_eq!(Some(50), closed[0].arr_time());
+        // The base timetable is left untouched
+        assert_eq!(3, t.connections.len());
+        assert_eq!(1, scenario.connections().len());
+    }
+
+    #[test]
+    fn closed_stop() {
+        let t = timetable();
+        let mut scenario = Scenario::new(&t);
+        scenario.close_stop("b");
+        let connections = scenario.connections();
+        assert_eq!(2, connections.len());
+        assert!(connections
+            .iter()
+            .all(|c| c.dep_stop != 1 && c.arr_stop != 1));
+
+        let s = scenario.timetable();
+        let journeys = Query::new(vec![0], vec![2], 0).journeys(&s);
+        assert_eq!(Some(30), journeys[0].arr_time());
+        assert!(Query::new(vec![0], vec![1], 0).journeys(&s).is_empty());
+    }
+
+    #[test]
+    fn delayed_route() {
+        let t = timetable();
+        let mut scenario = Scenario::new(&t);
+        scenario
+            .delay_route("A", 10)
+            .delay_route("A", 5)
+            .remove_trip("trip1");
+        let connections = scenario.connections();
+        assert_eq!(
+            vec![35, 25],
+            connections.iter().map(|c| c.dep_time).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(45), connections.first().map(|c| c.arr_time));
+    }
+
+    #[test]
+    fn doubled_frequency() {
+        let t = timetable();
+        let mut scenario = Scenario::new(&t);
+        scenario
+            .duplicate_trips("A", 0..20, 4)
+            .duplicate_trips("B", 20..60, 4);
+        let s = scenario.timetable();
+        assert_eq!(3, s.trips.len());
+        assert_eq!("trip0:duplicate", s.trips[2].id);
+        assert_eq!(Some(0), s.trips[2].route);
+        assert_eq!(5, s.connections.len());
+
+        let queries = [
+            // The duplicate of A arrives before B
+            Query::new(vec![0], vec![2], 11),
+            Query::new(vec![0], vec![2], 0),
+            Query::new(vec![0], vec![2], 16),
+            Query::new(vec![0], vec![1], 11),
+        ];
+        let impact = scenario.impact(&queries);
+        assert_eq!(4, impact.queries);
+        assert_eq!(2, impact.compared);
+        assert_eq!(1, impact.faster);
+        assert_eq!(0, impact.slower);
+        assert_eq!(1, impact.newly_reachable);
+        assert_eq!(-16, impact.total_change);
+        assert_eq!(-8., impact.mean_change);
+
+        let sampled: Vec<_> = crate::sampling::QuerySampler::new(&t, 1).take(10).collect();
+        let impact = scenario.impact(&sampled);
+        assert_eq!(10, impact.queries);
+        assert_eq!(0, impact.slower + impact.unreachable);
+    }
+}
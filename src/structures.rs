@@ -1,28 +1,62 @@
-use chrono::prelude::{NaiveDate, Utc};
-use itertools::Itertools;
-use serde::Serialize;
+use chrono::prelude::NaiveDate;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, Default)]
+// The same values as in GTFS, but the core does not depend on the GTFS reader
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LocationType {
+    #[default]
+    StopPoint,
+    StopArea,
+    StationEntrance,
+    GenericNode,
+    BoardingArea,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RouteType {
+    Tramway,
+    Subway,
+    Rail,
+    Bus,
+    Ferry,
+    CableCar,
+    Gondola,
+    Funicular,
+    Coach,
+    Air,
+    Taxi,
+    Other(u16),
+}
+
+// In seconds, between two stop points of the same stop area
+const TRANSFER_DURATION: u32 = 5;
+// In meters per second, to walk from an entrance to a stop point
+const WALKING_SPEED: f64 = 1.12;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Stop {
     pub id: String,
     pub name: String,
     pub parent_station: Option<String>,
-    pub location_type: gtfs_structures::LocationType,
+    pub location_type: LocationType,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    // The fare zone, like "1" to "5" around Paris
+    pub zone_id: Option<String>,
 }
 
-impl<'a> From<&'a std::sync::Arc<gtfs_structures::Stop>> for Stop {
-    fn from(stop: &std::sync::Arc<gtfs_structures::Stop>) -> Self {
-        Self {
-            id: stop.id.to_owned(),
-            name: stop.name.to_owned(),
-            parent_station: stop.parent_station.to_owned(),
-            location_type: stop.location_type,
-        }
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub id: String,
+    pub short_name: String,
+    pub long_name: String,
+    pub route_type: RouteType,
+    pub agency_id: Option<String>,
+    pub agency_name: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Connection {
     pub trip: usize,
     pub dep_time: u32,
@@ -31,23 +65,160 @@ pub struct Connection {
     pub arr_stop: usize,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Footpath {
     pub from: usize,
     pub duration: u32,
+    // Step-free queries can not use it
+    pub stairs: bool,
+}
+
+// As the crow flies, never shorter than a transfer in the stop area
+pub(crate) fn walking_duration(from: &Stop, to: &Stop) -> u32 {
+    let coord = |stop: &Stop| Some((stop.latitude?, stop.longitude?));
+    match (coord(from), coord(to)) {
+        (Some(from), Some(to)) => {
+            let duration = (crate::geo::distance(from, to) / WALKING_SPEED).round() as u32;
+            duration.max(TRANSFER_DURATION)
+        }
+        _ => TRANSFER_DURATION,
+    }
+}
+
+// The footpaths arriving at a stop, replacing the duration of the one from the same stop
+pub(crate) fn insert_footpath(footpaths: &mut Vec<Footpath>, footpath: Footpath) {
+    match footpaths
+        .iter_mut()
+        .find(|f| f.from == footpath.from && f.stairs == footpath.stairs)
+    {
+        Some(f) => f.duration = footpath.duration,
+        None => footpaths.push(footpath),
+    }
 }
 
+// Which fare applies to a trip, like in fare_rules.txt
+// The unset fields match any route or zone
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FareRule {
+    pub fare_id: String,
+    pub route: Option<usize>,
+    pub origin_zone: Option<String>,
+    pub destination_zone: Option<String>,
+    // Every zone traversed by the trip must have a rule with it
+    pub contains_zone: Option<String>,
+}
+
+// The names in other languages, by language tag (like "fr" or "pt-BR")
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Translations {
+    // By language, then by stop index
+    pub stop_names: HashMap<String, HashMap<usize, String>>,
+    // By language, then by route index
+    pub route_short_names: HashMap<String, HashMap<usize, String>>,
+    pub route_long_names: HashMap<String, HashMap<usize, String>>,
+}
+
+impl Translations {
+    // The best translated language for an Accept-Language header (like "fr-CH, fr;q=0.9, en;q=0.8")
+    // A language also matches its region variants: "fr" for "fr-CH"
+    pub fn language(&self, accepted: &str) -> Option<&str> {
+        let mut accepted: Vec<(&str, f32)> = accepted
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.);
+                Some((tag, quality))
+            })
+            .filter(|(tag, quality)| !tag.is_empty() && *quality > 0.)
+            .collect();
+        // Stable, the first ones are preferred with the same quality
+        accepted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        let languages = self.languages();
+        accepted.iter().find_map(|(tag, _)| {
+            let primary = tag.split('-').next().unwrap_or(tag);
+            languages
+                .iter()
+                .find(|l| l.eq_ignore_ascii_case(tag))
+                .or_else(|| languages.iter().find(|l| l.eq_ignore_ascii_case(primary)))
+                .copied()
+        })
+    }
+
+    pub fn languages(&self) -> Vec<&str> {
+        let mut languages: Vec<_> = self
+            .stop_names
+            .keys()
+            .chain(self.route_short_names.keys())
+            .chain(self.route_long_names.keys())
+            .map(String::as_str)
+            .collect();
+        languages.sort_unstable();
+        languages.dedup();
+        languages
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Timetable {
     pub start_date: chrono::NaiveDate,
+    // The timezone of the agencies, all the times are local times in that timezone
+    // Without the timezone feature, they are UTC times
+    #[cfg(feature = "timezone")]
+    pub timezone: chrono_tz::Tz,
     pub transform_duration: i64,
     pub stops: Vec<Stop>,
     pub connections: Vec<Connection>,
+    // The footpaths arriving at every stop
+    // They are directed: walking back can take another duration, or not be possible at all
     pub footpaths: Vec<Vec<Footpath>>,
     pub trips: Vec<Trip>,
+    pub routes: Vec<Route>,
+    pub translations: Translations,
+    pub fare_rules: Vec<FareRule>,
+}
+
+// What the algorithm scans: the connections of a timetable, or a view with realtime modifications
+// The stops, trips and footpaths are always the ones of the timetable
+pub trait Network {
+    type Connections<'a>: Iterator<Item = (usize, &'a Connection)>
+    where
+        Self: 'a;
+
+    fn timetable(&self) -> &Timetable;
+    fn connection(&self, index: usize) -> &Connection;
+    // With their indices, by decreasing departure time
+    fn connections(&self) -> Self::Connections<'_>;
+}
+
+impl Network for Timetable {
+    type Connections<'a> = std::iter::Enumerate<std::slice::Iter<'a, Connection>>;
+
+    fn timetable(&self) -> &Timetable {
+        self
+    }
+
+    fn connection(&self, index: usize) -> &Connection {
+        &self.connections[index]
+    }
+
+    fn connections(&self) -> Self::Connections<'_> {
+        self.connections.iter().enumerate()
+    }
 }
 
-#[derive(Clone)]
-pub struct Trip {}
+// A trip is a GTFS trip on a given day of the horizon
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trip {
+    pub id: String,
+    pub day: u16,
+    pub route: Option<usize>,
+    // The trips of a block are run by the same vehicle, the travellers can stay seated between them
+    pub block_id: Option<String>,
+}
 
 pub struct TimetableBuilder {
     stop_map: HashMap<String, usize>,
@@ -56,10 +227,26 @@ pub struct TimetableBuilder {
     connections: Vec<Connection>,
 }
 
+// The builder times are formatted as M:SS
+fn parse_time(time: &str) -> Option<u32> {
+    let mut parts = time.split(':');
+    let minutes: u32 = parts.next()?.parse().ok()?;
+    let seconds: u32 = parts.next()?.parse().ok()?;
+    match parts.next() {
+        Some(_) => None,
+        None => Some(minutes * 60 + seconds),
+    }
+}
+
 impl TimetableBuilder {
     pub fn trip(&mut self) -> &mut Self {
         self.last_stop = None;
-        self.trips.push(Trip {});
+        self.trips.push(Trip {
+            id: format!("trip{}", self.trips.len()),
+            day: 0,
+            route: None,
+            block_id: None,
+        });
         self
     }
 
@@ -74,8 +261,8 @@ impl TimetableBuilder {
             panic!("Timetable builder: trying to add a stop without a trip");
         }
         let stop_index = self.stop(stop);
-        let parsed_time = gtfs_structures::parse_time(&format!("0:{}", time))
-            .unwrap_or_else(|_| panic!("Invalid time format {}", time));
+        let parsed_time =
+            parse_time(time).unwrap_or_else(|| panic!("Invalid time format {}", time));
 
         if let Some(prev) = self.last_stop {
             self.connections.push(Connection {
@@ -92,60 +279,37 @@ impl TimetableBuilder {
         self
     }
     pub fn build(mut self) -> Timetable {
-        self.connections.sort_by(|a, b| b.dep_time.cmp(&a.dep_time));
+        self.connections
+            .sort_by_key(|c| std::cmp::Reverse(c.dep_time));
         let mut stops = vec![Stop::default(); self.stop_map.len()];
         for (name, idx) in self.stop_map.iter() {
             stops[*idx] = Stop {
                 id: name.to_owned(),
                 name: name.to_owned(),
-                location_type: gtfs_structures::LocationType::StopPoint,
+                location_type: LocationType::StopPoint,
                 parent_station: None,
+                latitude: None,
+                longitude: None,
+                zone_id: None,
             }
         }
         Timetable {
             start_date: NaiveDate::from_yo(2019, 42),
+            #[cfg(feature = "timezone")]
+            timezone: chrono_tz::UTC,
             trips: self.trips,
+            routes: Vec::new(),
             connections: self.connections,
             stops,
             footpaths: self.stop_map.iter().map(|_| Vec::new()).collect(),
             transform_duration: 0,
+            translations: Translations::default(),
+            fare_rules: Vec::new(),
         }
     }
 }
 
 impl Timetable {
-    pub fn from_gtfs(
-        gtfs: &gtfs_structures::Gtfs,
-        start_date_str: &str,
-        horizon: u16,
-    ) -> Timetable {
-        let start_date = start_date_str
-            .parse::<NaiveDate>()
-            .expect("Could not parse start date");
-
-        let stops: Vec<_> = gtfs.stops.values().map(Stop::from).collect();
-
-        let stop_indices = stops
-            .iter()
-            .enumerate()
-            .map(|(index, stop)| (stop.id.to_owned(), index))
-            .collect();
-
-        let now = Utc::now();
-        let trips = vec![Trip {}; gtfs.trips.len() * horizon as usize];
-        let connections = Timetable::connections(&gtfs, start_date, horizon, &stop_indices);
-        let transform_duration = Utc::now().signed_duration_since(now).num_milliseconds();
-
-        Timetable {
-            start_date,
-            footpaths: Timetable::footpaths(&stops, &stop_indices),
-            stops,
-            connections,
-            transform_duration,
-            trips,
-        }
-    }
-
     pub fn print_stats(&self) {
         println!("Final data structures: ");
         println!("  Stops: {}", self.stops.len());
@@ -157,98 +321,72 @@ impl Timetable {
         println!("  Connections built in {} ms", self.transform_duration);
     }
 
-    fn connections(
-        gtfs: &gtfs_structures::Gtfs,
-        start_date: NaiveDate,
-        horizon: u16,
-        stop_indices: &HashMap<String, usize>,
-    ) -> Vec<Connection> {
-        let mut result = Vec::new();
-
-        let mut trip_indices = HashMap::new();
-        let mut index = 0;
-        for trip_id in gtfs.trips.keys() {
-            for day in 0..horizon {
-                trip_indices.insert(format!("{}-{}", trip_id, day), index);
-                index += 1;
-            }
-        }
-
-        for (trip_id, gtfs_trip) in &gtfs.trips {
-            let days = gtfs.trip_days(&gtfs_trip.service_id, start_date);
-            let mut last_arrival = None;
-
-            for (departure, arrival) in gtfs_trip.stop_times.iter().tuple_windows() {
-                let dep_time = departure.departure_time.unwrap_or_else(|| {
-                    last_arrival.unwrap_or_else(|| {
-                        panic!("First departure without time on trip {}", trip_id)
-                    })
-                });
+    // Footpaths between all the stop points of a same stop area
+    // The station itself, its entrances and generic nodes are linked both ways to every stop point,
+    // so that walking from the street goes through them rather than from the centroid of the platforms
+    pub fn footpaths(stops: &[Stop], stop_indices: &HashMap<String, usize>) -> Vec<Vec<Footpath>> {
+        let mut result: Vec<Vec<_>> = stops.iter().map(|_| Vec::new()).collect();
+        // By stop area, the stop points and the accesses
+        let mut stop_areas: HashMap<&str, (Vec<usize>, Vec<usize>)> = HashMap::new();
 
-                let arr_time = arrival.arrival_time.unwrap_or_else(|| dep_time);
-                last_arrival = Some(arr_time);
-                let dep_stop = *stop_indices
-                    .get(&departure.stop.id)
-                    .unwrap_or_else(|| panic!("Unknown stop id {}", departure.stop.id));
-
-                let arr_stop = *stop_indices
-                    .get(&arrival.stop.id)
-                    .unwrap_or_else(|| panic!("Unknown stop id {}", arrival.stop.id));
-
-                for day in &days {
-                    if *day < horizon {
-                        result.push(Connection {
-                            trip: *trip_indices.get(&format!("{}-{}", trip_id, day)).unwrap(),
-                            dep_time: dep_time + (u32::from(*day) * 24 * 60 * 60),
-                            arr_time: arr_time + (u32::from(*day) * 24 * 60 * 60),
-                            dep_stop,
-                            arr_stop,
-                        });
+        for (index, stop) in stops.iter().enumerate() {
+            if let Some(ref parent) = stop.parent_station {
+                let (children, accesses) = stop_areas.entry(parent).or_default();
+                match stop.location_type {
+                    LocationType::StopPoint => children.push(index),
+                    LocationType::StationEntrance | LocationType::GenericNode => {
+                        accesses.push(index)
                     }
+                    LocationType::StopArea | LocationType::BoardingArea => (),
                 }
             }
         }
 
-        // We want the connections by decreasing departure time
-        result.sort_by(|a, b| (b.dep_time, b.arr_time).cmp(&(a.dep_time, a.arr_time)));
-        result
-    }
-
-    fn footpaths(stops: &[Stop], stop_indices: &HashMap<String, usize>) -> Vec<Vec<Footpath>> {
-        let mut result: Vec<Vec<_>> = stops.iter().map(|_| Vec::new()).collect();
-        let mut stop_areas = HashMap::new();
-
-        for stop in stops {
-            if let Some(ref parent) = stop.parent_station {
-                if stop.location_type == gtfs_structures::LocationType::StopPoint {
-                    let children = stop_areas.entry(parent).or_insert_with(Vec::new);
-                    children.push(stop.id.to_owned())
+        for (parent, (children, mut accesses)) in stop_areas {
+            if let Some(&station) = stop_indices.get(parent) {
+                if stops[station].location_type == LocationType::StopArea {
+                    accesses.push(station);
                 }
             }
-        }
-
-        for (_, children) in stop_areas {
-            for (child_a, child_b) in children
+            for (&a, &b) in children
                 .iter()
-                .cartesian_product(&children)
+                .flat_map(|a| children.iter().map(move |b| (a, b)))
                 .filter(|&(a, b)| a != b)
             {
-                let index_a = *stop_indices
-                    .get(child_a)
-                    .unwrap_or_else(|| panic!("Missing child station {}", child_b));
-                let index_b = *stop_indices
-                    .get(child_b)
-                    .unwrap_or_else(|| panic!("Missing child station {}", child_b));
-
-                result[index_a as usize].push(Footpath {
-                    duration: 5,
-                    from: index_b,
+                result[a].push(Footpath {
+                    duration: TRANSFER_DURATION,
+                    from: b,
+                    stairs: false,
                 });
             }
+            for (&access, &child) in accesses
+                .iter()
+                .flat_map(|access| children.iter().map(move |child| (access, child)))
+            {
+                let duration = walking_duration(&stops[access], &stops[child]);
+                for (from, to) in [(access, child), (child, access)] {
+                    result[to].push(Footpath {
+                        duration,
+                        from,
+                        stairs: false,
+                    });
+                }
+            }
         }
         result
     }
 
+    // A compact binary encoding of the timetable, that can be loaded without the GTFS reader
+    #[cfg(feature = "cache")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Could not serialize the timetable")
+    }
+
+    #[cfg(feature = "cache")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Timetable, bincode::Error> {
+        bincode::deserialize(bytes)
+    }
+
     pub fn builder() -> TimetableBuilder {
         TimetableBuilder {
             connections: Vec::new(),
@@ -258,6 +396,160 @@ impl Timetable {
         }
     }
 
+    // Adds a footpath walking from `from` to `to`, but not the other way around
+    // It replaces the duration of an existing footpath between the same stops
+    pub fn add_footpath(&mut self, from: usize, to: usize, duration: u32) {
+        self.insert_footpath(
+            to,
+            Footpath {
+                from,
+                duration,
+                stairs: false,
+            },
+        );
+    }
+
+    // Like `add_footpath`, but there can be both a footpath with stairs and a step-free one between two stops
+    pub fn insert_footpath(&mut self, to: usize, footpath: Footpath) {
+        insert_footpath(&mut self.footpaths[to], footpath);
+    }
+
+    // The footpaths leaving every stop, as (to, duration)
+    pub fn outgoing_footpaths(&self) -> Vec<Vec<(usize, u32)>> {
+        let mut result = vec![Vec::new(); self.stops.len()];
+        for (to, footpaths) in self.footpaths.iter().enumerate() {
+            for footpath in footpaths {
+                result[footpath.from].push((to, footpath.duration));
+            }
+        }
+        result
+    }
+
+    // The duration to walk from a stop to another, if they are connected
+    pub fn footpath_duration(&self, from: usize, to: usize) -> Option<u32> {
+        self.footpaths[to]
+            .iter()
+            .filter(|footpath| footpath.from == from)
+            .map(|footpath| footpath.duration)
+            .min()
+    }
+
+    // Times are seconds since midnight of the start date
+    pub fn datetime(&self, time: u32) -> chrono::NaiveDateTime {
+        self.start_date.and_hms(0, 0, 0) + chrono::Duration::seconds(i64::from(time))
+    }
+
+    // The inverse of `datetime`, None if the datetime is before the start date
+    pub fn time(&self, datetime: chrono::NaiveDateTime) -> Option<u32> {
+        let seconds = datetime
+            .signed_duration_since(self.start_date.and_hms(0, 0, 0))
+            .num_seconds();
+        if seconds < 0 || seconds > i64::from(u32::MAX) {
+            None
+        } else {
+            Some(seconds as u32)
+        }
+    }
+
+    // The end of the last day loaded, or of the last departure if it is later
+    pub fn end_time(&self) -> u32 {
+        let days = self.trips.iter().map(|t| u32::from(t.day) + 1).max();
+        let last_departure = self.connections.first().map(|c| c.dep_time + 1);
+        days.map(|d| d * 86_400).max(last_departure).unwrap_or(0)
+    }
+
+    // The time of an instant in the timezone of the timetable
+    // None if there are no departures left at that time
+    pub fn local_time(&self, instant: chrono::DateTime<chrono::Utc>) -> Option<u32> {
+        #[cfg(feature = "timezone")]
+        let time = self.time(instant.with_timezone(&self.timezone).naive_local())?;
+        #[cfg(not(feature = "timezone"))]
+        let time = self.time(instant.naive_utc())?;
+        // The first connection is the last departure
+        match self.connections.first() {
+            Some(last) if time <= last.dep_time => Some(time),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "timezone"))]
+    pub fn timestamp_millis(&self, time: u32) -> i64 {
+        use chrono::TimeZone;
+        chrono::Utc
+            .from_utc_datetime(&self.datetime(time))
+            .timestamp_millis()
+    }
+
+    #[cfg(feature = "timezone")]
+    pub fn timestamp_millis(&self, time: u32) -> i64 {
+        use chrono::TimeZone;
+        let datetime = self.datetime(time);
+        // Local times skipped by a DST change do not exist, we consider them as UTC
+        self.timezone
+            .from_local_datetime(&datetime)
+            .earliest()
+            .unwrap_or_else(|| self.timezone.from_utc_datetime(&datetime))
+            .timestamp_millis()
+    }
+
+    // The name of the stop in the language, or the default one
+    pub fn stop_name(&self, stop: usize, language: Option<&str>) -> &str {
+        language
+            .and_then(|language| self.translations.stop_names.get(language))
+            .and_then(|names| names.get(&stop))
+            .unwrap_or(&self.stops[stop].name)
+    }
+
+    pub fn route_short_name(&self, route: usize, language: Option<&str>) -> &str {
+        language
+            .and_then(|language| self.translations.route_short_names.get(language))
+            .and_then(|names| names.get(&route))
+            .unwrap_or(&self.routes[route].short_name)
+    }
+
+    pub fn route_long_name(&self, route: usize, language: Option<&str>) -> &str {
+        language
+            .and_then(|language| self.translations.route_long_names.get(language))
+            .and_then(|names| names.get(&route))
+            .unwrap_or(&self.routes[route].long_name)
+    }
+
+    // The zones of the stops and of the fare rules, sorted
+    pub fn zones(&self) -> Vec<&str> {
+        let rules = self.fare_rules.iter().flat_map(|rule| {
+            [
+                &rule.origin_zone,
+                &rule.destination_zone,
+                &rule.contains_zone,
+            ]
+        });
+        let mut zones: Vec<_> = self
+            .stops
+            .iter()
+            .map(|stop| &stop.zone_id)
+            .chain(rules)
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        zones.sort_unstable();
+        zones.dedup();
+        zones
+    }
+
+    pub fn stop_index_by_id(&self, stop_id: &str) -> Option<usize> {
+        self.stops.iter().position(|stop| stop.id == stop_id)
+    }
+
+    // The stops of a stop area, or else the stop with that id (like a platform)
+    pub fn stop_indices(&self, id: &str) -> Vec<usize> {
+        let stops = self.stop_index_by_stop_area_id(id);
+        if stops.is_empty() {
+            self.stop_index_by_id(id).into_iter().collect()
+        } else {
+            stops
+        }
+    }
+
     pub fn stop_index_by_stop_area_id(&self, stop_area_id: &str) -> Vec<usize> {
         self.stops
             .iter()
@@ -272,32 +564,72 @@ impl Timetable {
 mod tests {
     use super::*;
 
-    #[test]
-    fn from_gtfs() {
-        let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
-        let timetable = Timetable::from_gtfs(&gtfs, "2017-1-1", 10);
-        assert_eq!(5, timetable.stops.len());
-        assert_eq!(2, timetable.connections.len());
-        assert_eq!(5, timetable.footpaths.len());
-        for i in 0..timetable.stops.len() {
-            if timetable.stops[i].id == "stop3" || timetable.stops[i].id == "stop5" {
-                assert_eq!(timetable.footpaths[i].len(), 1);
-            } else {
-                assert!(timetable.footpaths[i].is_empty());
-            }
-        }
-    }
-
     #[test]
     fn builder() {
         let mut b = Timetable::builder();
         b.trip();
         assert_eq!(1, b.trips.len());
+        assert_eq!("trip0", b.trips[0].id);
         assert_eq!(0, b.stop("a"));
         assert_eq!(0, b.stop("a"));
         assert_eq!(1, b.stop("b"));
     }
 
+    #[test]
+    fn station_footpaths() {
+        let stop = |id: &str, location_type, latitude| Stop {
+            id: id.to_owned(),
+            parent_station: Some("station".to_owned()).filter(|_| id != "station"),
+            location_type,
+            latitude,
+            longitude: latitude.map(|_| 2.35),
+            ..Default::default()
+        };
+        let stops = vec![
+            stop("station", LocationType::StopArea, Some(48.85)),
+            stop("platform1", LocationType::StopPoint, Some(48.85)),
+            stop("platform2", LocationType::StopPoint, None),
+            // About 111 meters north
+            stop("entrance", LocationType::StationEntrance, Some(48.851)),
+        ];
+        let indices = stops
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.id.to_owned(), i))
+            .collect();
+        let footpaths = Timetable::footpaths(&stops, &indices);
+
+        let duration = |from: usize, to: usize| {
+            footpaths[to]
+                .iter()
+                .find(|f| f.from == from)
+                .map(|f| f.duration)
+        };
+        assert_eq!(Some(5), duration(2, 1));
+        assert_eq!(Some(99), duration(3, 1));
+        assert_eq!(Some(99), duration(1, 3));
+        // Without coordinates, like a transfer
+        assert_eq!(Some(5), duration(3, 2));
+        assert_eq!(Some(5), duration(0, 1));
+        // The accesses are only linked to the stop points
+        assert_eq!(None, duration(0, 3));
+        assert_eq!(2, footpaths[3].len());
+    }
+
+    #[test]
+    fn stop_indices() {
+        let mut b = Timetable::builder();
+        b.trip().s("a1", "0:10").s("a2", "0:20").s("b", "0:30");
+        let mut t = b.build();
+        t.stops[0].parent_station = Some("a".to_owned());
+        t.stops[1].parent_station = Some("a".to_owned());
+
+        assert_eq!(vec![0, 1], t.stop_indices("a"));
+        assert_eq!(vec![1], t.stop_indices("a2"));
+        assert_eq!(vec![2], t.stop_indices("b"));
+        assert!(t.stop_indices("c").is_empty());
+    }
+
     #[test]
     fn builder_transform() {
         let mut b = Timetable::builder();
@@ -330,4 +662,71 @@ mod tests {
             assert_eq!(t.stops[2].name, "c");
         }
     }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn binary_roundtrip() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "1:05").s("b", "1:20");
+        let t = Timetable::from_bytes(&b.build().to_bytes()).unwrap();
+        assert_eq!(2, t.stops.len());
+        assert_eq!(65, t.connections[0].dep_time);
+        assert_eq!(chrono_tz::UTC, t.timezone);
+        assert!(Timetable::from_bytes(&[1, 2, 3]).is_err());
+    }
+
+    #[cfg(feature = "timezone")]
+    #[test]
+    fn local_time() {
+        use chrono::TimeZone;
+        let mut b = Timetable::builder();
+        b.trip().s("a", "70:00").s("b", "80:00");
+        let mut t = b.build();
+        t.timezone = chrono_tz::Europe::Paris;
+
+        // 01:00:05 in Paris
+        let instant = chrono::Utc.ymd(2019, 2, 11).and_hms(0, 0, 5);
+        assert_eq!(Some(3605), t.local_time(instant));
+        // The day before the start date, or after the last departure
+        assert_eq!(
+            None,
+            t.local_time(chrono::Utc.ymd(2019, 2, 10).and_hms(22, 0, 0))
+        );
+        assert_eq!(
+            None,
+            t.local_time(chrono::Utc.ymd(2019, 2, 11).and_hms(12, 0, 0))
+        );
+    }
+
+    #[test]
+    fn accept_language() {
+        let mut translations = Translations::default();
+        for language in &["de", "fr", "pt-BR"] {
+            translations
+                .stop_names
+                .insert(language.to_string(), HashMap::new());
+        }
+        assert_eq!(
+            Some("fr"),
+            translations.language("fr-CH, fr;q=0.9, en;q=0.8")
+        );
+        assert_eq!(Some("de"), translations.language("en, fr;q=0.5, de;q=0.7"));
+        assert_eq!(Some("pt-BR"), translations.language("pt-br"));
+        assert_eq!(None, translations.language("en, fr;q=0"));
+        assert_eq!(None, translations.language(""));
+    }
+
+    #[test]
+    fn directed_footpaths() {
+        let mut b = Timetable::builder();
+        b.trip().s("a", "0:10").s("b", "0:20");
+        let mut t = b.build();
+        t.add_footpath(0, 1, 3);
+        t.add_footpath(1, 0, 10);
+        t.add_footpath(0, 1, 4);
+        assert_eq!(Some(4), t.footpath_duration(0, 1));
+        assert_eq!(Some(10), t.footpath_duration(1, 0));
+        assert_eq!(1, t.footpaths[1].len());
+        assert_eq!(vec![vec![(1, 4)], vec![(0, 10)]], t.outgoing_footpaths());
+    }
 }
@@ -0,0 +1,356 @@
+// A smaller timetable restricted to a part of the network, like a city or a few lines
+// Handy to build test fixtures from a production feed or to run a server on a subset
+use crate::structures::{Footpath, LocationType, Timetable, Translations};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selection {
+    // The connections between two stops within the box, in degrees
+    BoundingBox {
+        min_latitude: f64,
+        min_longitude: f64,
+        max_latitude: f64,
+        max_longitude: f64,
+    },
+    // The trips of the routes, by id
+    Routes(HashSet<String>),
+    // The trips of the routes of the agencies, by id
+    Agencies(HashSet<String>),
+}
+
+// bbox:<min latitude>,<min longitude>,<max latitude>,<max longitude>, routes:<ids> or
+// agencies:<ids>, the ids being separated by commas
+impl std::str::FromStr for Selection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, values) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid selection {}, expected <kind>:<values>", s))?;
+        let ids = || {
+            values
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(str::to_owned)
+                .collect()
+        };
+        match kind {
+            "bbox" => {
+                let coords = values
+                    .split(',')
+                    .map(|v| v.trim().parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("Invalid bounding box {}: {}", values, e))?;
+                match coords[..] {
+                    [min_latitude, min_longitude, max_latitude, max_longitude] => {
+                        Ok(Selection::BoundingBox {
+                            min_latitude,
+                            min_longitude,
+                            max_latitude,
+                            max_longitude,
+                        })
+                    }
+                    _ => Err(format!(
+                        "Invalid bounding box {}, expected 4 coordinates",
+                        values
+                    )),
+                }
+            }
+            "routes" => Ok(Selection::Routes(ids())),
+            "agencies" => Ok(Selection::Agencies(ids())),
+            _ => Err(format!(
+                "Unknown selection {}, expected bbox, routes or agencies",
+                kind
+            )),
+        }
+    }
+}
+
+// The new index of every kept element
+fn indices(kept: &[bool]) -> Vec<Option<usize>> {
+    let mut count = 0;
+    kept.iter()
+        .map(|&kept| {
+            kept.then(|| {
+                count += 1;
+                count - 1
+            })
+        })
+        .collect()
+}
+
+fn filter_indexed<T: Clone>(values: &[T], indices: &[Option<usize>]) -> Vec<T> {
+    values
+        .iter()
+        .zip(indices)
+        .filter(|(_, index)| index.is_some())
+        .map(|(value, _)| value.clone())
+        .collect()
+}
+
+fn translations(
+    names: &HashMap<String, HashMap<usize, String>>,
+    indices: &[Option<usize>],
+) -> HashMap<String, HashMap<usize, String>> {
+    names
+        .iter()
+        .map(|(language, names)| {
+            let names = names
+                .iter()
+                .filter_map(|(&index, name)| Some((indices[index]?, name.to_owned())))
+                .collect();
+            (language.to_owned(), names)
+        })
+        .collect()
+}
+
+impl Timetable {
+    // The connections of the selection, the stops they use with their stations and entrances, and
+    // the trips and routes running them
+    // The stops, trips and routes are renumbered, in the same order
+    pub fn filter(&self, selection: &Selection) -> Timetable {
+        let mut kept_stops: Vec<bool> = match selection {
+            Selection::BoundingBox {
+                min_latitude,
+                min_longitude,
+                max_latitude,
+                max_longitude,
+            } => self
+                .stops
+                .iter()
+                .map(|stop| match (stop.latitude, stop.longitude) {
+                    (Some(lat), Some(lon)) => {
+                        (*min_latitude..=*max_latitude).contains(&lat)
+                            && (*min_longitude..=*max_longitude).contains(&lon)
+                    }
+                    _ => false,
+                })
+                .collect(),
+            _ => vec![false; self.stops.len()],
+        };
+        let kept_routes: Option<Vec<bool>> = match selection {
+            Selection::BoundingBox { .. } => None,
+            Selection::Routes(ids) => {
+                Some(self.routes.iter().map(|r| ids.contains(&r.id)).collect())
+            }
+            Selection::Agencies(ids) => Some(
+                self.routes
+                    .iter()
+                    .map(|r| r.agency_id.as_ref().is_some_and(|id| ids.contains(id)))
+                    .collect(),
+            ),
+        };
+        let connections: Vec<_> = match &kept_routes {
+            None => self
+                .connections
+                .iter()
+                .filter(|c| kept_stops[c.dep_stop] && kept_stops[c.arr_stop])
+                .collect(),
+            Some(kept_routes) => self
+                .connections
+                .iter()
+                .filter(|c| self.trips[c.trip].route.is_some_and(|r| kept_routes[r]))
+                .collect(),
+        };
+        for c in &connections {
+            kept_stops[c.dep_stop] = true;
+            kept_stops[c.arr_stop] = true;
+        }
+
+        // The parents of the kept stops, up to their station
+        let by_id: HashMap<&str, usize> = self
+            .stops
+            .iter()
+            .enumerate()
+            .map(|(index, stop)| (stop.id.as_str(), index))
+            .collect();
+        let parent = |stop: usize| {
+            let parent = self.stops[stop].parent_station.as_deref()?;
+            by_id.get(parent).copied()
+        };
+        for stop in 0..self.stops.len() {
+            let mut current = stop;
+            while kept_stops[current] {
+                match parent(current) {
+                    Some(p) if !kept_stops[p] => {
+                        kept_stops[p] = true;
+                        current = p;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        // The entrances, generic nodes and boarding areas of the kept stations and stop points
+        for stop in 0..self.stops.len() {
+            if self.stops[stop].location_type != LocationType::StopPoint
+                && parent(stop).is_some_and(|p| kept_stops[p])
+            {
+                kept_stops[stop] = true;
+            }
+        }
+        let stop_indices = indices(&kept_stops);
+
+        let mut kept_trips = vec![false; self.trips.len()];
+        for c in &connections {
+            kept_trips[c.trip] = true;
+        }
+        let trip_indices = indices(&kept_trips);
+        let mut kept_routes = vec![false; self.routes.len()];
+        for route in self
+            .trips
+            .iter()
+            .zip(&kept_trips)
+            .filter(|(_, &kept)| kept)
+            .filter_map(|(trip, _)| trip.route)
+        {
+            kept_routes[route] = true;
+        }
+        let route_indices = indices(&kept_routes);
+
+        let connections = connections
+            .into_iter()
+            .map(|c| {
+                let mut c = c.clone();
+                c.trip = trip_indices[c.trip].unwrap_or_default();
+                c.dep_stop = stop_indices[c.dep_stop].unwrap_or_default();
+                c.arr_stop = stop_indices[c.arr_stop].unwrap_or_default();
+                c
+            })
+            .collect();
+        let footpaths = filter_indexed(&self.footpaths, &stop_indices)
+            .into_iter()
+            .map(|footpaths: Vec<Footpath>| {
+                footpaths
+                    .into_iter()
+                    .filter_map(|footpath| {
+                        Some(Footpath {
+                            from: stop_indices[footpath.from]?,
+                            ..footpath
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut trips = filter_indexed(&self.trips, &trip_indices);
+        for trip in &mut trips {
+            trip.route = trip.route.and_then(|route| route_indices[route]);
+        }
+        let mut fare_rules = self.fare_rules.clone();
+        fare_rules.retain(|rule| {
+            rule.route
+                .is_none_or(|route| route_indices[route].is_some())
+        });
+        for rule in &mut fare_rules {
+            rule.route = rule.route.and_then(|route| route_indices[route]);
+        }
+
+        Timetable {
+            start_date: self.start_date,
+            timezone: self.timezone,
+            transform_duration: self.transform_duration,
+            stops: filter_indexed(&self.stops, &stop_indices),
+            connections,
+            footpaths,
+            trips,
+            routes: filter_indexed(&self.routes, &route_indices),
+            translations: Translations {
+                stop_names: translations(&self.translations.stop_names, &stop_indices),
+                route_short_names: translations(
+                    &self.translations.route_short_names,
+                    &route_indices,
+                ),
+                route_long_names: translations(&self.translations.route_long_names, &route_indices),
+            },
+            fare_rules,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::Query;
+    use crate::structures::{Route, RouteType};
+
+    fn timetable() -> Timetable {
+        let mut b = Timetable::builder();
+        b.trip()
+            .s("a", "0:10")
+            .s("b", "0:20")
+            .s("c", "0:30")
+            .trip()
+            .s("c", "0:40")
+            .s("d", "0:50");
+        let mut t = b.build();
+        for (i, (id, agency)) in [("A", "ratp"), ("B", "sncf")].iter().enumerate() {
+            t.routes.push(Route {
+                id: id.to_string(),
+                short_name: id.to_string(),
+                long_name: String::new(),
+                route_type: RouteType::Bus,
+                agency_id: Some(agency.to_string()),
+                agency_name: None,
+            });
+            t.trips[i].route = Some(i);
+        }
+        for (i, stop) in t.stops.iter_mut().enumerate() {
+            stop.latitude = Some(48. + i as f64 / 10.);
+            stop.longitude = Some(2.);
+        }
+        t.stops[1].parent_station = Some("station".to_owned());
+        t.stops.push(crate::structures::Stop {
+            id: "station".to_owned(),
+            location_type: LocationType::StopArea,
+            ..Default::default()
+        });
+        t.footpaths.push(Vec::new());
+        t.add_footpaths(&crate::footpaths::ParentStationProvider);
+        t.translations
+            .route_short_names
+            .insert("fr".to_owned(), [(1, "Bé".to_owned())].into());
+        t
+    }
+
+    #[test]
+    fn routes() {
+        let t = timetable();
+        let f = t.filter(&"agencies:sncf".parse().unwrap());
+        assert_eq!(1, f.connections.len());
+        assert_eq!(
+            vec!["c", "d"],
+            f.stops.iter().map(|s| &s.id).collect::<Vec<_>>()
+        );
+        assert_eq!(1, f.trips.len());
+        assert_eq!(Some(0), f.trips[0].route);
+        assert_eq!("B", f.routes[0].id);
+        assert_eq!("Bé", f.translations.route_short_names["fr"][&0]);
+        assert_eq!(1, Query::new(vec![0], vec![1], 0).journeys(&f).len());
+
+        let f = t.filter(&"routes:A,unknown".parse().unwrap());
+        // With the station of b
+        assert_eq!(4, f.stops.len());
+        assert_eq!(Some(5), f.footpath_duration(3, 1));
+        assert!(f.translations.route_short_names["fr"].is_empty());
+    }
+
+    #[test]
+    fn bounding_box() {
+        let t = timetable();
+        let f = t.filter(&"bbox:48.05,1.9,48.25,2.1".parse().unwrap());
+        // Only the connection from b to c stays
+        assert_eq!(1, f.connections.len());
+        assert_eq!(
+            vec!["b", "c", "station"],
+            f.stops.iter().map(|s| &s.id).collect::<Vec<_>>()
+        );
+        assert_eq!(1, f.trips.len());
+        assert_eq!(
+            Some(30),
+            Query::new(vec![0], vec![1], 0).journeys(&f)[0].arr_time()
+        );
+
+        assert!("bbox:1,2,3".parse::<Selection>().is_err());
+        assert!("lines:A".parse::<Selection>().is_err());
+    }
+}
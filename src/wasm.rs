@@ -0,0 +1,48 @@
+// JavaScript bindings running the queries in the browser
+// The timetable is a binary one, as written by the convert binary
+use crate::algo::compute;
+use crate::formats::navitia::{self, JourneysRequest};
+use crate::formats::protobuf;
+use crate::structures::Timetable;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct Router {
+    timetable: Timetable,
+}
+
+#[wasm_bindgen]
+impl Router {
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8]) -> Result<Router, JsValue> {
+        Timetable::from_bytes(bytes)
+            .map(|timetable| Router { timetable })
+            .map_err(|e| JsValue::from_str(&format!("Invalid timetable: {}", e)))
+    }
+
+    // A navitia /journeys response, serialized as JSON
+    pub fn journeys(
+        &self,
+        from: String,
+        to: String,
+        datetime: Option<String>,
+        count: Option<u32>,
+    ) -> String {
+        let request = JourneysRequest {
+            from,
+            to,
+            datetime,
+            count: count.map(|c| c as usize),
+            ..Default::default()
+        };
+        serde_json::to_string(&navitia::journeys(&self.timetable, &request))
+            .expect("Could not serialize the journeys")
+    }
+
+    // The profiles of every stop towards the stop area (or stop), as a protobuf Profiles message
+    pub fn profiles(&self, stop_area: &str) -> Vec<u8> {
+        let destinations = self.timetable.stop_indices(stop_area);
+        let profiles = compute(&self.timetable, &destinations);
+        protobuf::profiles(&self.timetable, &profiles)
+    }
+}
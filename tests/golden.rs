@@ -0,0 +1,101 @@
+// Runs `compute` on the bundled fixtures and compares the results with checked-in JSON files
+// Run with `UPDATE_GOLDEN=1 cargo test --test golden` to regenerate them after an intended change
+use csa::algo;
+use csa::structures::Timetable;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Stop and trip indices depend on the GTFS parsing order,
+// so profiles are serialized with stop ids instead of indices
+#[derive(Serialize)]
+struct GoldenProfile {
+    dep_time: u32,
+    arr_time: u32,
+    out_connection: Option<GoldenConnection>,
+}
+
+#[derive(Serialize)]
+struct GoldenConnection {
+    dep_stop: String,
+    arr_stop: String,
+    dep_time: u32,
+    arr_time: u32,
+}
+
+fn serialize_profiles(timetable: &Timetable, profiles: &[Vec<algo::Profile>]) -> String {
+    let result: BTreeMap<_, Vec<_>> = timetable
+        .stops
+        .iter()
+        .zip(profiles)
+        .map(|(stop, profiles)| {
+            let golden = profiles
+                .iter()
+                .map(|p| GoldenProfile {
+                    dep_time: p.dep_time,
+                    arr_time: p.arr_time,
+                    out_connection: p.out_connection.map(|c| {
+                        let c = &timetable.connections[c];
+                        GoldenConnection {
+                            dep_stop: timetable.stops[c.dep_stop].id.to_owned(),
+                            arr_stop: timetable.stops[c.arr_stop].id.to_owned(),
+                            dep_time: c.dep_time,
+                            arr_time: c.arr_time,
+                        }
+                    }),
+                })
+                .collect();
+            (stop.id.to_owned(), golden)
+        })
+        .collect();
+    serde_json::to_string_pretty(&result).unwrap() + "\n"
+}
+
+fn check_golden(name: &str, destinations: &[usize], timetable: &Timetable) {
+    let profiles = algo::compute(timetable, destinations);
+    let actual = serialize_profiles(timetable, &profiles);
+    let path = format!("tests/golden/{}.json", name);
+
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        std::fs::write(&path, &actual).unwrap();
+    }
+    let expected =
+        std::fs::read_to_string(&path).unwrap_or_else(|_| panic!("Missing golden file {}", path));
+    assert_eq!(expected, actual, "Profiles differ from {}", path);
+}
+
+fn fixtures() -> Timetable {
+    let gtfs = gtfs_structures::Gtfs::new("fixtures/").unwrap();
+    Timetable::from_gtfs(&gtfs, "2017-1-1", 10)
+}
+
+fn stop_index(timetable: &Timetable, id: &str) -> usize {
+    timetable
+        .stops
+        .iter()
+        .position(|s| s.id == id)
+        .unwrap_or_else(|| panic!("Unknown stop {}", id))
+}
+
+#[test]
+fn golden_to_stop3() {
+    let t = fixtures();
+    check_golden("to_stop3", &[stop_index(&t, "stop3")], &t);
+}
+
+#[test]
+fn golden_to_stop5() {
+    let t = fixtures();
+    check_golden("to_stop5", &[stop_index(&t, "stop5")], &t);
+}
+
+#[test]
+fn golden_to_stop2() {
+    let t = fixtures();
+    check_golden("to_stop2", &[stop_index(&t, "stop2")], &t);
+}
+
+#[test]
+fn golden_to_stop_area() {
+    let t = fixtures();
+    check_golden("to_stop1", &t.stop_index_by_stop_area_id("stop1"), &t);
+}
@@ -0,0 +1,112 @@
+// The parsing of unusual but valid feeds, written by the fixture generator of `support`
+mod support;
+
+use csa::structures::Timetable;
+use support::GtfsFixture;
+
+fn departures(timetable: &Timetable) -> Vec<(String, u32)> {
+    let mut departures: Vec<_> = timetable
+        .connections
+        .iter()
+        .map(|c| (timetable.stops[c.dep_stop].id.to_owned(), c.dep_time))
+        .collect();
+    departures.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    departures
+}
+
+#[test]
+fn calendar_variants() {
+    let mut fixture = GtfsFixture::new();
+    fixture
+        .calendar("weekend", "0000011", "20170101", "20170131")
+        .calendar_date("weekend", "20170101", 2)
+        .calendar_date("weekend", "20170102", 1)
+        .trip(
+            "t",
+            "weekend",
+            &[("a", "08:00:00", "08:00:00"), ("b", "08:10:00", "08:10:00")],
+        );
+    let t = fixture.timetable("calendar_variants", 7);
+    // Sunday 1st removed, Monday 2nd added and Saturday 7th
+    let days: Vec<_> = departures(&t).iter().map(|d| d.1 / 86400).collect();
+    assert_eq!(vec![1, 6], days);
+}
+
+#[test]
+fn only_calendar_dates() {
+    let mut fixture = GtfsFixture::new();
+    fixture
+        .without("calendar.txt")
+        .calendar_date("special", "20170103", 1)
+        .trip(
+            "t",
+            "special",
+            &[("a", "08:00:00", "08:00:00"), ("b", "08:10:00", "08:10:00")],
+        );
+    let t = fixture.timetable("only_calendar_dates", 5);
+    assert_eq!(vec![("a".to_owned(), 2 * 86400 + 8 * 3600)], departures(&t));
+}
+
+#[test]
+fn after_midnight() {
+    let mut fixture = GtfsFixture::new();
+    fixture.trip(
+        "night",
+        "everyday",
+        &[("a", "23:50:00", "23:50:00"), ("b", "25:10:00", "25:10:00")],
+    );
+    let t = fixture.timetable("after_midnight", 1);
+    assert_eq!(1, t.connections.len());
+    assert_eq!(25 * 3600 + 600, t.connections[0].arr_time);
+}
+
+#[test]
+fn untimed_stops() {
+    let mut fixture = GtfsFixture::new();
+    fixture.trip(
+        "t",
+        "everyday",
+        &[
+            ("a", "08:00:00", "08:00:00"),
+            ("b", "", ""),
+            ("c", "08:20:00", "08:20:00"),
+        ],
+    );
+    let t = fixture.timetable("untimed_stops", 1);
+    // The untimed stop is reached and left at the time of the previous one
+    assert_eq!(
+        vec![("a".to_owned(), 8 * 3600), ("b".to_owned(), 8 * 3600)],
+        departures(&t)
+    );
+}
+
+#[test]
+fn stations() {
+    let mut fixture = GtfsFixture::new();
+    fixture
+        .station("area", &["p1", "p2"])
+        .trip(
+            "t",
+            "everyday",
+            &[
+                ("a", "08:00:00", "08:00:00"),
+                ("p1", "08:10:00", "08:10:00"),
+            ],
+        )
+        .trip(
+            "u",
+            "everyday",
+            &[
+                ("p2", "08:20:00", "08:20:00"),
+                ("b", "08:30:00", "08:30:00"),
+            ],
+        );
+    let t = fixture.timetable("stations", 1);
+    let (a, b) = (
+        t.stop_index_by_id("a").unwrap(),
+        t.stop_index_by_id("b").unwrap(),
+    );
+    let journeys = csa::query::Query::new(vec![a], vec![b], 0).journeys(&t);
+    assert_eq!(1, journeys[0].transfers());
+    assert_eq!(2, t.stop_indices("area").len());
+}
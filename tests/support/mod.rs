@@ -0,0 +1,152 @@
+// Writes small synthetic GTFS folders to temporary directories, to cover the edge cases of
+// `from_gtfs` without committing a fixture folder for each of them
+// The default feed is valid: one agency, route and service running every day of January 2017
+#![allow(dead_code)]
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub struct GtfsFixture {
+    // By file name, without the header
+    files: BTreeMap<&'static str, Vec<String>>,
+}
+
+const HEADERS: [(&str, &str); 7] = [
+    (
+        "agency.txt",
+        "agency_id,agency_name,agency_url,agency_timezone",
+    ),
+    (
+        "stops.txt",
+        "stop_id,stop_name,stop_lat,stop_lon,location_type,parent_station",
+    ),
+    (
+        "routes.txt",
+        "route_id,agency_id,route_short_name,route_long_name,route_type",
+    ),
+    ("trips.txt", "route_id,service_id,trip_id,block_id"),
+    (
+        "stop_times.txt",
+        "trip_id,arrival_time,departure_time,stop_id,stop_sequence",
+    ),
+    (
+        "calendar.txt",
+        "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date",
+    ),
+    ("calendar_dates.txt", "service_id,date,exception_type"),
+];
+
+impl GtfsFixture {
+    pub fn new() -> Self {
+        let mut fixture = Self {
+            files: HEADERS
+                .iter()
+                .map(|(file, _)| (*file, Vec::new()))
+                .collect(),
+        };
+        fixture
+            .line(
+                "agency.txt",
+                "agency,Agency,https://example.com,Europe/Paris",
+            )
+            .line("routes.txt", "route,agency,R,Route,3")
+            .calendar("everyday", "1111111", "20170101", "20170131");
+        fixture
+    }
+
+    // A raw line, without checking the columns
+    pub fn line(&mut self, file: &'static str, line: &str) -> &mut Self {
+        self.files
+            .get_mut(file)
+            .unwrap_or_else(|| panic!("Unknown fixture file {}", file))
+            .push(line.to_owned());
+        self
+    }
+
+    // A stop point, all the stops are at the same place unless `located_stop` is used
+    pub fn stop(&mut self, id: &str) -> &mut Self {
+        self.located_stop(id, 48.85, 2.35)
+    }
+
+    pub fn located_stop(&mut self, id: &str, latitude: f64, longitude: f64) -> &mut Self {
+        let line = format!("{},{},{},{},0,", id, id, latitude, longitude);
+        self.line("stops.txt", &line)
+    }
+
+    pub fn station(&mut self, id: &str, children: &[&str]) -> &mut Self {
+        self.line("stops.txt", &format!("{},{},48.85,2.35,1,", id, id));
+        for child in children {
+            self.line(
+                "stops.txt",
+                &format!("{},{},48.85,2.35,0,{}", child, child, id),
+            );
+        }
+        self
+    }
+
+    // The stop times are given as (stop, arrival, departure), an empty time is left empty
+    // The stops are created when they do not exist yet
+    pub fn trip(
+        &mut self,
+        id: &str,
+        service: &str,
+        stop_times: &[(&str, &str, &str)],
+    ) -> &mut Self {
+        self.line("trips.txt", &format!("route,{},{},", service, id));
+        for (sequence, (stop, arrival, departure)) in stop_times.iter().enumerate() {
+            let known = self.files["stops.txt"]
+                .iter()
+                .any(|line| line.split(',').next() == Some(*stop));
+            if !known {
+                self.stop(stop);
+            }
+            let line = format!("{},{},{},{},{}", id, arrival, departure, stop, sequence);
+            self.line("stop_times.txt", &line);
+        }
+        self
+    }
+
+    // The days from monday to sunday, like "1111100" for the working days
+    pub fn calendar(&mut self, service: &str, days: &str, start: &str, end: &str) -> &mut Self {
+        let days: Vec<_> = days.chars().map(|d| d.to_string()).collect();
+        let line = format!("{},{},{},{}", service, days.join(","), start, end);
+        self.line("calendar.txt", &line)
+    }
+
+    // 1 adds the date to the service, 2 removes it
+    pub fn calendar_date(&mut self, service: &str, date: &str, exception_type: u8) -> &mut Self {
+        let line = format!("{},{},{}", service, date, exception_type);
+        self.line("calendar_dates.txt", &line)
+    }
+
+    // The file is not written, like an optional file missing from a feed
+    pub fn without(&mut self, file: &'static str) -> &mut Self {
+        self.files.remove(file);
+        self
+    }
+
+    // In a directory of its own, emptied first
+    pub fn write(&self, name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("csa_fixture_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, header) in HEADERS.iter() {
+            if let Some(lines) = self.files.get(file) {
+                let mut content = format!("{}\n", header);
+                for line in lines {
+                    content.push_str(line);
+                    content.push('\n');
+                }
+                std::fs::write(dir.join(file), content).unwrap();
+            }
+        }
+        dir
+    }
+
+    // Written, then loaded from 2017-01-01 for `horizon` days
+    pub fn timetable(&self, name: &str, horizon: u16) -> csa::structures::Timetable {
+        let dir = self.write(name);
+        let gtfs = gtfs_structures::Gtfs::new(dir.to_str().unwrap()).unwrap();
+        let start_date = chrono::NaiveDate::from_ymd(2017, 1, 1);
+        csa::structures::Timetable::from_gtfs_starting(&gtfs, start_date, horizon)
+    }
+}